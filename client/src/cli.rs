@@ -1,9 +1,12 @@
+use crate::client::ExitStatus;
 use anyhow::{anyhow, Error, Result};
-use protocol::{stream_log_response, StreamLogResponse};
+use humantime::Timestamp;
+use protocol::{resource_limits, stream_log_response, StreamLogResponse};
 use std::collections::HashMap;
-use std::convert::Infallible;
+use std::convert::{Infallible, TryFrom};
 use std::str;
 use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
 use structopt::clap::arg_enum;
 use structopt::StructOpt;
 use uuid::Uuid;
@@ -50,6 +53,138 @@ impl FromStr for StringMap {
     }
 }
 
+/// A newtype around a cgroup CPU bandwidth limit to allow structopt to parse it.
+/// The expected format is `QUOTA/PERIOD` in microseconds.
+#[derive(Debug)]
+pub struct CpuMax(pub resource_limits::CpuMax);
+
+impl FromStr for CpuMax {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut iter = s.split('/');
+        let quota_us = iter
+            .next()
+            .ok_or_else(|| anyhow!("no cpu quota provided"))?
+            .trim()
+            .parse()?;
+
+        let period_us = iter
+            .next()
+            .ok_or_else(|| anyhow!("no cpu period provided"))?
+            .trim()
+            .parse()?;
+
+        Ok(CpuMax(resource_limits::CpuMax {
+            quota_us,
+            period_us,
+        }))
+    }
+}
+
+/// A newtype around a cgroup IO limit for a single device to allow structopt to parse it.
+/// The expected format is `MAJOR:MINOR` followed by any of `,rbps=N`, `,wbps=N`, `,riops=N` and `,wiops=N`.
+#[derive(Debug)]
+pub struct IoMax(pub resource_limits::IoMax);
+
+impl FromStr for IoMax {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut iter = s.split(',');
+        let device = iter.next().ok_or_else(|| anyhow!("no device provided"))?;
+        let mut device = device.split(':');
+        let mut io_max = resource_limits::IoMax {
+            major: device
+                .next()
+                .ok_or_else(|| anyhow!("no device major number provided"))?
+                .trim()
+                .parse()?,
+            minor: device
+                .next()
+                .ok_or_else(|| anyhow!("no device minor number provided"))?
+                .trim()
+                .parse()?,
+            ..Default::default()
+        };
+
+        for limit in iter {
+            let mut pair = limit.split('=');
+            let key = pair.next().ok_or_else(|| anyhow!("no io limit provided"))?;
+            let value = pair
+                .next()
+                .ok_or_else(|| anyhow!("no io limit value provided"))?
+                .trim()
+                .parse()?;
+
+            match key.trim() {
+                "rbps" => io_max.rbps = value,
+                "wbps" => io_max.wbps = value,
+                "riops" => io_max.riops = value,
+                "wiops" => io_max.wiops = value,
+                key => return Err(anyhow!("unknown io limit {}", key)),
+            }
+        }
+
+        Ok(IoMax(io_max))
+    }
+}
+
+/// A newtype around a per-process resource limit to allow structopt to parse it.
+/// The expected format is `NAME=VALUE` where the name is that of the resource without the `RLIMIT_` prefix.
+#[derive(Debug)]
+pub struct Rlimit(pub String, pub u64);
+
+impl FromStr for Rlimit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut iter = s.split('=');
+        let name = iter.next().ok_or_else(|| anyhow!("no rlimit provided"))?;
+        let value = iter
+            .next()
+            .ok_or_else(|| anyhow!("no rlimit value provided"))?
+            .trim()
+            .parse()?;
+
+        Ok(Rlimit(name.trim().to_lowercase(), value))
+    }
+}
+
+/// A newtype around a signal number to allow structopt to parse it.
+/// Signals can be given by number or by name, with or without the `SIG` prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct Signal(pub i32);
+
+impl FromStr for Signal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        /// Common signals and their numbers on Linux.
+        const SIGNALS: &[(&str, i32)] = &[
+            ("HUP", 1),
+            ("INT", 2),
+            ("QUIT", 3),
+            ("KILL", 9),
+            ("USR1", 10),
+            ("USR2", 12),
+            ("TERM", 15),
+        ];
+
+        if let Ok(number) = s.parse() {
+            return Ok(Signal(number));
+        }
+
+        let name = s.trim().to_uppercase();
+        let name = name.trim_start_matches("SIG");
+        SIGNALS
+            .iter()
+            .find(|(signal, _)| *signal == name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or_else(|| anyhow!("unknown signal {}", s))
+    }
+}
+
 /// The base CLI options.
 #[derive(Debug, StructOpt)]
 #[structopt(name = "client")]
@@ -65,6 +200,8 @@ pub struct Opts {
 }
 
 /// This represents all subcommands.
+/// The arguments are only parsed once so it doesn't matter that spawning takes many more than the rest.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 pub enum CommandOpts {
     Spawn {
@@ -79,11 +216,82 @@ pub enum CommandOpts {
 
         #[structopt(short, long, default_value = "")]
         envs: StringMap,
+
+        #[structopt(long)]
+        cpu_max: Option<CpuMax>,
+
+        #[structopt(long)]
+        memory_max: Option<u64>,
+
+        #[structopt(long, number_of_values = 1)]
+        io_max: Vec<IoMax>,
+
+        #[structopt(long)]
+        pids_max: Option<u64>,
+
+        #[structopt(long)]
+        open_stdin: bool,
+
+        #[structopt(long)]
+        tty: bool,
+
+        #[structopt(long)]
+        timeout_ms: Option<u64>,
+
+        /// An RFC 3339 timestamp like 2021-02-01T12:00:00Z.
+        #[structopt(long)]
+        deadline: Option<Timestamp>,
+
+        #[structopt(long)]
+        timeout_signal: Option<Signal>,
+
+        #[structopt(long)]
+        timeout_grace_period_ms: Option<u64>,
+
+        #[structopt(long)]
+        timeout_escalation_signal: Option<Signal>,
+
+        /// A namespace to isolate the job in. May be given more than once.
+        #[structopt(long, number_of_values = 1, case_insensitive = true)]
+        namespace: Vec<Namespace>,
+
+        /// A directory to bind-mount read-only as the root filesystem of the job. Requires a mount namespace.
+        #[structopt(long)]
+        root: Option<String>,
+
+        /// The name of a security profile defined by the server.
+        #[structopt(long)]
+        profile: Option<String>,
+
+        /// A per-process resource limit like `nofile=1024`. May be given more than once.
+        #[structopt(long, number_of_values = 1)]
+        rlimit: Vec<Rlimit>,
+
+        /// Publish output as whole lines rather than however it happened to be read.
+        #[structopt(long)]
+        line_framing: bool,
+
+        /// Cut lines longer than this many bytes. Implies line framing.
+        #[structopt(long)]
+        max_line_bytes: Option<u64>,
+
+        /// Publish a partial line after holding it back for this long. Implies line framing.
+        #[structopt(long)]
+        flush_timeout_ms: Option<u64>,
     },
 
     Stop {
         #[structopt(short, long)]
         uuid: Uuid,
+
+        #[structopt(short, long)]
+        signal: Option<Signal>,
+
+        #[structopt(short, long)]
+        grace_period_ms: Option<u64>,
+
+        #[structopt(long)]
+        escalation_signal: Option<Signal>,
     },
 
     StreamLog {
@@ -95,12 +303,142 @@ pub enum CommandOpts {
 
         #[structopt(short, long)]
         past_events: bool,
+
+        /// Print the time every event was published at.
+        #[structopt(short, long)]
+        timestamps: bool,
+    },
+
+    Logs {
+        #[structopt(short, long)]
+        uuid: Uuid,
+
+        #[structopt(short, long, case_insensitive = true, default_value = "both")]
+        streams: LogStreamsFilter,
+
+        /// Print the first lines.
+        #[structopt(long, conflicts_with_all = &["tail", "offset", "length"])]
+        head: Option<u64>,
+
+        /// Print the last lines.
+        #[structopt(long, conflicts_with_all = &["offset", "length"])]
+        tail: Option<u64>,
+
+        /// Print the output starting at this many bytes in.
+        #[structopt(long)]
+        offset: Option<u64>,
+
+        /// Print at most this many bytes of output.
+        #[structopt(long)]
+        length: Option<u64>,
+    },
+
+    Search {
+        #[structopt(short, long)]
+        uuid: Uuid,
+
+        /// Print the lines matching a regular expression.
+        #[structopt(long, required_unless = "substring", conflicts_with = "substring")]
+        regex: Option<String>,
+
+        /// Print the lines containing a substring.
+        #[structopt(long)]
+        substring: Option<String>,
+
+        #[structopt(short, long, case_insensitive = true, default_value = "both")]
+        streams: LogStreamsFilter,
+
+        /// Only print lines published at or after this time.
+        #[structopt(long)]
+        since: Option<Timestamp>,
+
+        /// Only print lines published at or before this time.
+        #[structopt(long)]
+        until: Option<Timestamp>,
+
+        /// Stop after this many matching lines, 1000 by default.
+        #[structopt(long)]
+        max_matches: Option<u32>,
+    },
+
+    Attach {
+        #[structopt(short, long)]
+        uuid: Uuid,
+
+        #[structopt(short, long)]
+        past_events: bool,
+
+        #[structopt(short, long)]
+        tty: bool,
     },
 
     Status {
         #[structopt(short, long)]
         uuid: Uuid,
     },
+
+    Stats {
+        #[structopt(short, long)]
+        uuid: Uuid,
+    },
+
+    Wait {
+        #[structopt(short, long)]
+        uuid: Uuid,
+
+        #[structopt(short, long, default_value = "0")]
+        timeout_ms: u64,
+    },
+
+    Remove {
+        #[structopt(short, long)]
+        uuid: Uuid,
+
+        #[structopt(short, long)]
+        force: bool,
+    },
+
+    List {
+        #[structopt(short, long, case_insensitive = true)]
+        state: Option<JobStateFilter>,
+
+        #[structopt(short, long, default_value = "0")]
+        page_size: u32,
+
+        #[structopt(long, default_value = "")]
+        page_token: String,
+    },
+}
+
+arg_enum! {
+    /// Possible job states to filter listed jobs by.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum JobStateFilter {
+        Running,
+        Terminated,
+    }
+}
+
+arg_enum! {
+    /// Possible output streams to read logs from.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LogStreamsFilter {
+        Stdout,
+        Stderr,
+        Both,
+    }
+}
+
+arg_enum! {
+    /// Linux namespaces a job can be isolated in.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Namespace {
+        Pid,
+        Mount,
+        Uts,
+        Ipc,
+        Network,
+    }
 }
 
 arg_enum! {
@@ -114,11 +452,12 @@ arg_enum! {
 }
 
 impl StreamType {
-    pub fn writer(&self) -> Box<dyn StreamWriter + Send + 'static> {
+    /// Create a writer for the stream type, optionally printing the time of every event in front of it.
+    pub fn writer(&self, timestamps: bool) -> Box<dyn StreamWriter + Send + 'static> {
         match self {
-            Self::Raw => Box::new(RawStreamWriter),
-            Self::Stdout => Box::new(StdoutStreamWriter),
-            Self::Stderr => Box::new(StderrStreamWriter),
+            Self::Raw => Box::new(RawStreamWriter { timestamps }),
+            Self::Stdout => Box::new(StdoutStreamWriter { timestamps }),
+            Self::Stderr => Box::new(StderrStreamWriter { timestamps }),
         }
     }
 }
@@ -126,7 +465,7 @@ impl StreamType {
 #[derive(Debug, Clone, Copy)]
 pub enum StreamStatus {
     ExpectingMore,
-    Terminated(i32),
+    Terminated(ExitStatus),
 }
 
 pub trait StreamWriter {
@@ -134,8 +473,10 @@ pub trait StreamWriter {
     fn write(&mut self, event: StreamLogResponse) -> Result<StreamStatus>;
 }
 
-/// Event writer that writes all events in their raw form.
-struct RawStreamWriter;
+/// Event writer that writes all events in their raw form along with their sequence numbers.
+struct RawStreamWriter {
+    timestamps: bool,
+}
 
 impl StreamWriter for RawStreamWriter {
     fn start(&mut self) -> Result<()> {
@@ -144,17 +485,21 @@ impl StreamWriter for RawStreamWriter {
     }
 
     fn write(&mut self, event: StreamLogResponse) -> Result<StreamStatus> {
+        let prefix = prefix(&event, self.timestamps);
         let response = event
             .response
             .ok_or_else(|| anyhow!("incomplete event received"))?;
 
-        println!("{:?}", response);
-        Ok(status_from_response(&response))
+        let status = status_from_response(&response)?;
+        println!("{}#{} {:?}", prefix, event.sequence, response);
+        Ok(status)
     }
 }
 
 /// Event writer that filters out stdout events and displays them as text.
-struct StdoutStreamWriter;
+struct StdoutStreamWriter {
+    timestamps: bool,
+}
 
 impl StreamWriter for StdoutStreamWriter {
     fn start(&mut self) -> Result<()> {
@@ -163,14 +508,16 @@ impl StreamWriter for StdoutStreamWriter {
     }
 
     fn write(&mut self, event: StreamLogResponse) -> Result<StreamStatus> {
+        let prefix = prefix(&event, self.timestamps);
         let response = event
             .response
             .ok_or_else(|| anyhow!("incomplete event received"))?;
 
-        let status = status_from_response(&response);
+        let status = status_from_response(&response)?;
+        report_notices(&response);
         if let stream_log_response::Response::Stdout(data) = response {
             let text = str::from_utf8(&data.output)?;
-            print!("{}", text);
+            print!("{}{}", prefix, text);
         }
 
         Ok(status)
@@ -178,7 +525,9 @@ impl StreamWriter for StdoutStreamWriter {
 }
 
 /// Event writer that filters out stderr events and displays them as text.
-struct StderrStreamWriter;
+struct StderrStreamWriter {
+    timestamps: bool,
+}
 
 impl StreamWriter for StderrStreamWriter {
     fn start(&mut self) -> Result<()> {
@@ -187,24 +536,54 @@ impl StreamWriter for StderrStreamWriter {
     }
 
     fn write(&mut self, event: StreamLogResponse) -> Result<StreamStatus> {
+        let prefix = prefix(&event, self.timestamps);
         let response = event
             .response
             .ok_or_else(|| anyhow!("incomplete event received"))?;
 
-        let status = status_from_response(&response);
+        let status = status_from_response(&response)?;
+        report_notices(&response);
         if let stream_log_response::Response::Stderr(data) = response {
             let text = str::from_utf8(&data.output)?;
-            print!("{}", text);
+            print!("{}{}", prefix, text);
         }
 
         Ok(status)
     }
 }
 
-fn status_from_response(response: &stream_log_response::Response) -> StreamStatus {
+/// The time an event was published at, formatted to be printed in front of it, if timestamps are wanted.
+fn prefix(event: &StreamLogResponse, timestamps: bool) -> String {
+    if !timestamps {
+        return String::new();
+    }
+
+    let at = UNIX_EPOCH + Duration::from_millis(event.at_unix_ms);
+    format!("[{}] ", humantime::format_rfc3339_millis(at))
+}
+
+/// Let the user know should parts of the log be missing or should the job have timed out
+/// since only the raw writer shows every event.
+fn report_notices(response: &stream_log_response::Response) {
+    match response {
+        stream_log_response::Response::TimedOut(_) => {
+            eprintln!("job ran past its timeout or deadline and is being stopped")
+        }
+        stream_log_response::Response::Truncated(event) => {
+            eprintln!("{} bytes of earlier output were discarded", event.bytes)
+        }
+        stream_log_response::Response::Gap(event) => {
+            eprintln!("{} events were dropped while falling behind", event.events)
+        }
+        _ => (),
+    }
+}
+
+fn status_from_response(response: &stream_log_response::Response) -> Result<StreamStatus> {
     if let stream_log_response::Response::Exit(event) = response {
-        StreamStatus::Terminated(event.code)
+        let status = ExitStatus::try_from(event.status.clone())?;
+        Ok(StreamStatus::Terminated(status))
     } else {
-        StreamStatus::ExpectingMore
+        Ok(StreamStatus::ExpectingMore)
     }
 }
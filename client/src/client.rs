@@ -1,9 +1,18 @@
 use anyhow::{anyhow, Result};
+use futures::{stream, Stream, StreamExt};
 use protocol::{
-    api_client::ApiClient, status_response, IssueJwtRequest, SpawnRequest, StatusRequest,
-    StopRequest, StreamLogRequest, StreamLogResponse,
+    api_client::ApiClient, attach_request, exit_status, get_logs_request, list_jobs_response,
+    search_logs_request, status_response, AttachRequest, GetJobStatsRequest, GetLogsRequest,
+    Isolation, IssueJwtRequest, JobState, LineFraming, ListJobsRequest, LogStorage, LogStreams,
+    RemoveJobRequest, ResourceLimits, ResourceUsage, SearchLogsRequest, SpawnRequest,
+    StatusRequest, StopPolicy, StopRequest, StreamLogRequest, StreamLogResponse, WaitRequest,
 };
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedReceiver;
 use tonic::{
     metadata::MetadataValue,
     transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
@@ -11,6 +20,9 @@ use tonic::{
 };
 use uuid::Uuid;
 
+/// The exit code reported for a job that was lost or exited in an unknown way since it has no exit code of its own.
+const LOST_SHELL_CODE: i32 = 255;
+
 /// The permissions that the requested JWT should have.
 #[derive(Debug, Clone)]
 pub struct Claims {
@@ -33,10 +45,195 @@ impl Claims {
     }
 }
 
+/// Options controlling how a job is spawned.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    pub limits: ResourceLimits,
+
+    /// Give the job a stdin that can be written to by attaching to it.
+    pub open_stdin: bool,
+
+    /// Run the job in a pseudo-terminal.
+    pub tty: bool,
+
+    /// Stop the job once it has run for this long.
+    pub timeout: Option<Duration>,
+
+    /// Stop the job should it still be running at this point in time.
+    pub deadline: Option<SystemTime>,
+
+    /// How the job is stopped once it runs past its timeout or deadline. The server defaults are used if unset.
+    pub timeout_policy: Option<StopPolicy>,
+
+    /// The namespaces the job is isolated in. It shares every namespace with the server if unset.
+    pub isolation: Option<Isolation>,
+
+    /// The name of the security profile the job is restricted by.
+    pub profile: Option<String>,
+
+    /// Per-process resource limits keyed by the name of the resource, like `nofile`.
+    pub rlimits: HashMap<String, u64>,
+
+    /// Publish output as whole lines. Output is published in raw chunks if unset.
+    pub line_framing: Option<LineFraming>,
+}
+
+/// A search through the retained output of a job, line by line.
+#[derive(Debug, Clone)]
+pub struct LogSearch {
+    pub pattern: search_logs_request::Pattern,
+    pub streams: LogStreams,
+
+    /// Only lines that started being published within this time range match, should it be bounded.
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+
+    /// Stop searching after this many matching lines.
+    pub max_matches: Option<u32>,
+}
+
+/// A part of the retained output of a job along with the offsets it spans in the selected streams.
+#[derive(Debug, Clone)]
+pub struct LogPage {
+    pub events: Vec<StreamLogResponse>,
+    pub offsets: Range<u64>,
+}
+
+/// Input fed to a job while attached to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachInput {
+    Stdin(Vec<u8>),
+
+    /// Close the stdin of the job.
+    Eof,
+
+    /// Change the window size of the terminal of the job.
+    Resize {
+        rows: u16,
+        cols: u16,
+    },
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum JobStatus {
     Running,
-    Terminated(i32),
+    Terminated(ExitStatus),
+
+    /// The job terminated after it was stopped for running past its timeout or deadline.
+    TimedOut(ExitStatus),
+}
+
+impl JobStatus {
+    fn terminated(status: ExitStatus, timed_out: bool) -> Self {
+        if timed_out {
+            Self::TimedOut(status)
+        } else {
+            Self::Terminated(status)
+        }
+    }
+}
+
+/// A snapshot of a job as returned when listing jobs.
+#[derive(Debug, Clone)]
+pub struct JobSummary {
+    pub uuid: Uuid,
+    pub program: String,
+    pub args: Vec<String>,
+    pub status: JobStatus,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+}
+
+impl TryFrom<list_jobs_response::Job> for JobSummary {
+    type Error = anyhow::Error;
+
+    fn try_from(job: list_jobs_response::Job) -> Result<Self> {
+        fn from_unix_ms(ms: u64) -> SystemTime {
+            UNIX_EPOCH + Duration::from_millis(ms)
+        }
+
+        let (status, finished_at) = match JobState::from_i32(job.state) {
+            Some(JobState::Running) => (JobStatus::Running, None),
+            Some(JobState::Terminated) => (
+                JobStatus::terminated(ExitStatus::try_from(job.exit_status)?, job.timed_out),
+                Some(from_unix_ms(job.finished_at_unix_ms)),
+            ),
+            _ => return Err(anyhow!("unknown job state received")),
+        };
+
+        Ok(Self {
+            uuid: Uuid::from_slice(&job.uuid)?,
+            program: job.program,
+            args: job.args,
+            status,
+            started_at: from_unix_ms(job.started_at_unix_ms),
+            finished_at,
+        })
+    }
+}
+
+/// How a job terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Code(i32),
+    Signal {
+        signal: i32,
+        core_dumped: bool,
+    },
+
+    /// The job outlived a previous instance of the server and was killed since it couldn't be reattached to.
+    Lost,
+
+    /// The job outlived a previous instance of the server and exited on its own, but how is unknown.
+    Unknown,
+}
+
+impl TryFrom<Option<protocol::ExitStatus>> for ExitStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(status: Option<protocol::ExitStatus>) -> Result<Self> {
+        let status = status.ok_or_else(|| anyhow!("no exit status received"))?;
+
+        match status.status {
+            Some(exit_status::Status::Code(code)) => Ok(Self::Code(code)),
+            Some(exit_status::Status::Signal(signal)) => Ok(Self::Signal {
+                signal,
+                core_dumped: status.core_dumped,
+            }),
+            Some(exit_status::Status::Lost(_)) => Ok(Self::Lost),
+            Some(exit_status::Status::Unknown(_)) => Ok(Self::Unknown),
+            None => Err(anyhow!("incomplete exit status received")),
+        }
+    }
+}
+
+impl ExitStatus {
+    /// The exit code a shell would report for a process that terminated this way.
+    pub fn shell_code(&self) -> i32 {
+        match self {
+            Self::Code(code) => *code,
+            Self::Signal { signal, .. } => 128 + signal,
+            Self::Lost | Self::Unknown => LOST_SHELL_CODE,
+        }
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "exit code {}", code),
+            Self::Signal {
+                signal,
+                core_dumped: false,
+            } => write!(f, "signal {}", signal),
+            Self::Signal {
+                signal,
+                core_dumped: true,
+            } => write!(f, "signal {} (core dumped)", signal),
+            Self::Lost => write!(f, "lost"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -126,12 +323,25 @@ impl Client {
         working_directory: String,
         args: Vec<String>,
         envs: HashMap<String, String>,
+        options: SpawnOptions,
     ) -> Result<Uuid> {
         let request = self.authorize_request(SpawnRequest {
             program: program_path,
             working_directory,
             args,
             envs,
+            limits: Some(options.limits),
+            open_stdin: options.open_stdin,
+            tty: options.tty,
+            timeout_ms: options
+                .timeout
+                .map_or(0, |timeout| timeout.as_millis() as u64),
+            deadline_unix_ms: unix_ms(options.deadline),
+            timeout_policy: options.timeout_policy,
+            isolation: options.isolation,
+            profile: options.profile.unwrap_or_default(),
+            rlimits: options.rlimits,
+            line_framing: options.line_framing,
         });
 
         let response = self.remote.spawn(request).await?.into_inner();
@@ -139,15 +349,27 @@ impl Client {
         Ok(uuid)
     }
 
-    pub async fn stop(&mut self, job: Uuid) -> Result<()> {
+    pub async fn stop(&mut self, job: Uuid, policy: StopPolicy) -> Result<()> {
         let request = self.authorize_request(StopRequest {
             uuid: job.as_bytes()[..].into(),
+            policy: Some(policy),
         });
 
         self.remote.stop(request).await?;
         Ok(())
     }
 
+    /// Remove a job along with its output. Running jobs are only removed if forced, which kills them.
+    pub async fn remove_job(&mut self, job: Uuid, force: bool) -> Result<()> {
+        let request = self.authorize_request(RemoveJobRequest {
+            uuid: job.as_bytes()[..].into(),
+            force,
+        });
+
+        self.remote.remove_job(request).await?;
+        Ok(())
+    }
+
     pub async fn stream_log(
         &mut self,
         job: Uuid,
@@ -156,12 +378,135 @@ impl Client {
         let request = self.authorize_request(StreamLogRequest {
             uuid: job.as_bytes()[..].into(),
             from_beginning,
+            from_sequence: 0,
         });
 
         let response = self.remote.stream_log(request).await?.into_inner();
         Ok(response)
     }
 
+    /// Stream the events of a job starting from the retained event with the given sequence number,
+    /// such as the one right after the last event received before a connection dropped.
+    pub async fn resume_log(
+        &mut self,
+        job: Uuid,
+        from_sequence: u64,
+    ) -> Result<Streaming<StreamLogResponse>> {
+        let request = self.authorize_request(StreamLogRequest {
+            uuid: job.as_bytes()[..].into(),
+            from_beginning: false,
+            from_sequence,
+        });
+
+        let response = self.remote.stream_log(request).await?.into_inner();
+        Ok(response)
+    }
+
+    /// Read part of the retained output of a job without streaming it.
+    pub async fn get_logs(
+        &mut self,
+        job: Uuid,
+        streams: LogStreams,
+        range: get_logs_request::Range,
+    ) -> Result<LogPage> {
+        let request = self.authorize_request(GetLogsRequest {
+            uuid: job.as_bytes()[..].into(),
+            streams: streams as i32,
+            range: Some(range),
+        });
+
+        let response = self.remote.get_logs(request).await?.into_inner();
+        Ok(LogPage {
+            events: response.events,
+            offsets: response.start_offset..response.end_offset,
+        })
+    }
+
+    /// Read a range of bytes of the retained output of a job a page at a time, since the server caps how much a single
+    /// read returns. Pages start past the requested offset when the output before it is no longer retained.
+    pub fn page_logs(
+        &mut self,
+        job: Uuid,
+        streams: LogStreams,
+        offset: u64,
+        length: u64,
+    ) -> impl Stream<Item = Result<LogPage>> + '_ {
+        let end = offset.saturating_add(length);
+        stream::try_unfold((self, offset), move |(client, offset)| async move {
+            if offset >= end {
+                return Ok(None);
+            }
+
+            let range = get_logs_request::Range::Bytes(get_logs_request::ByteRange {
+                offset,
+                length: end - offset,
+            });
+
+            // A read that doesn't get any further means the end of the retained output was reached.
+            let page = client.get_logs(job, streams, range).await?;
+            if page.offsets.end <= offset {
+                return Ok(None);
+            }
+
+            let next = page.offsets.end;
+            Ok(Some((page, (client, next))))
+        })
+    }
+
+    /// Search the retained output of a job on the server, returning the matching lines.
+    pub async fn search_logs(
+        &mut self,
+        job: Uuid,
+        search: LogSearch,
+    ) -> Result<Vec<StreamLogResponse>> {
+        let request = self.authorize_request(SearchLogsRequest {
+            uuid: job.as_bytes()[..].into(),
+            pattern: Some(search.pattern),
+            streams: search.streams as i32,
+            since_unix_ms: unix_ms(search.since),
+            until_unix_ms: unix_ms(search.until),
+            max_matches: search.max_matches.unwrap_or(0),
+        });
+
+        let response = self.remote.search_logs(request).await?.into_inner();
+        Ok(response.matches)
+    }
+
+    /// Attach to a job, feeding it input from the given channel while streaming its output back.
+    /// The job must have been spawned with stdin open.
+    pub async fn attach(
+        &mut self,
+        job: Uuid,
+        from_beginning: bool,
+        mut input: UnboundedReceiver<AttachInput>,
+    ) -> Result<Streaming<StreamLogResponse>> {
+        let start = attach_request::Request::Start(attach_request::AttachStart {
+            uuid: job.as_bytes()[..].into(),
+            from_beginning,
+        });
+
+        let input = stream::poll_fn(move |cx| input.poll_recv(cx)).map(|input| match input {
+            AttachInput::Stdin(bytes) => attach_request::Request::Stdin(bytes),
+            AttachInput::Eof => attach_request::Request::Eof(attach_request::AttachEof {}),
+            AttachInput::Resize { rows, cols } => {
+                attach_request::Request::Resize(attach_request::AttachResize {
+                    rows: rows.into(),
+                    cols: cols.into(),
+                })
+            }
+        });
+
+        let requests = stream::once(async { start })
+            .chain(input)
+            .map(|request| AttachRequest {
+                request: Some(request),
+            });
+
+        let request = self.authorize_request(requests);
+        let response = self.remote.attach(request).await?.into_inner();
+        Ok(response)
+    }
+
     pub async fn status(&mut self, job: Uuid) -> Result<JobStatus> {
         let request = self.authorize_request(StatusRequest {
             uuid: job.as_bytes()[..].into(),
@@ -177,9 +522,78 @@ impl Client {
 
         Ok(match response {
             status_response::Response::Running(_) => JobStatus::Running,
-            status_response::Response::Terminated(terminated) => {
-                JobStatus::Terminated(terminated.code)
-            }
+            status_response::Response::Terminated(terminated) => JobStatus::terminated(
+                ExitStatus::try_from(terminated.status)?,
+                terminated.timed_out,
+            ),
         })
     }
+
+    /// Fetch the resources a job has used along with whether the job has terminated, in which case the usage is final,
+    /// and how much disk space its output takes up. There's no usage if the job hasn't been sampled yet.
+    pub async fn job_stats(
+        &mut self,
+        job: Uuid,
+    ) -> Result<(Option<ResourceUsage>, bool, LogStorage)> {
+        let request = self.authorize_request(GetJobStatsRequest {
+            uuid: job.as_bytes()[..].into(),
+        });
+
+        let response = self.remote.get_job_stats(request).await?.into_inner();
+        Ok((
+            response.usage,
+            response.terminated,
+            response.log_storage.unwrap_or_default(),
+        ))
+    }
+
+    /// Block until the job has terminated and return how it terminated.
+    /// Gives up after the timeout unless it is zero.
+    pub async fn wait(&mut self, job: Uuid, timeout_ms: u64) -> Result<ExitStatus> {
+        let request = self.authorize_request(WaitRequest {
+            uuid: job.as_bytes()[..].into(),
+            timeout_ms,
+        });
+
+        let response = self.remote.wait(request).await?.into_inner();
+        ExitStatus::try_from(response.status)
+    }
+
+    /// List jobs in the order they were spawned, optionally only those in a certain state.
+    /// Returns the listed jobs and the page token to continue listing from if there are more jobs.
+    pub async fn list(
+        &mut self,
+        state: JobState,
+        page_size: u32,
+        page_token: String,
+    ) -> Result<(Vec<JobSummary>, Option<String>)> {
+        let request = self.authorize_request(ListJobsRequest {
+            state: state as i32,
+            page_size,
+            page_token,
+        });
+
+        let response = self.remote.list_jobs(request).await?.into_inner();
+        let jobs = response
+            .jobs
+            .into_iter()
+            .map(JobSummary::try_from)
+            .collect::<Result<_>>()?;
+
+        let next_page_token = if response.next_page_token.is_empty() {
+            None
+        } else {
+            Some(response.next_page_token)
+        };
+
+        Ok((jobs, next_page_token))
+    }
+}
+
+/// A point in time in milliseconds since the unix epoch, with zero meaning none.
+fn unix_ms(time: Option<SystemTime>) -> u64 {
+    time.map_or(0, |time| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64)
+    })
 }
@@ -1,15 +1,30 @@
 mod cli;
 mod client;
+mod terminal;
 
 #[cfg(test)]
 mod tests;
 
-use anyhow::Result;
-use cli::{CommandOpts, Opts, StreamStatus, StreamType};
-use client::{Claims, Client, JobStatus, UnauthorizedClient};
+use anyhow::{anyhow, Result};
+use cli::{
+    CommandOpts, JobStateFilter, LogStreamsFilter, Namespace, Opts, StreamStatus, StreamType,
+};
+use client::{AttachInput, Claims, Client, JobStatus, LogSearch, SpawnOptions, UnauthorizedClient};
 use futures::StreamExt;
+use protocol::{
+    get_logs_request, search_logs_request, stream_log_response, Isolation, JobState, LineFraming,
+    LogStreams, ResourceLimits, StopPolicy, StreamLogResponse,
+};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
 use structopt::StructOpt;
+use terminal::RawMode;
+use tokio::io::AsyncReadExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::time;
 use tonic::transport::{Certificate, Identity};
 use uuid::Uuid;
 
@@ -17,6 +32,12 @@ const CLIENT_CERT: &[u8] = include_bytes!("../../data/client1.pem");
 const CLIENT_KEY: &[u8] = include_bytes!("../../data/client1.key");
 const SERVER_CA_CERT: &[u8] = include_bytes!("../../data/ca.pem");
 
+/// How many times in a row reconnecting to a dropped log stream is attempted before giving up.
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+/// How long to wait before reconnecting to a dropped log stream.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::from_args();
@@ -29,14 +50,190 @@ async fn main() -> Result<()> {
             working_directory,
             args,
             envs,
-        } => spawn(&mut client, program_path, working_directory, args.0, envs.0).await?,
-        CommandOpts::Stop { uuid } => stop(&mut client, uuid).await?,
+            cpu_max,
+            memory_max,
+            io_max,
+            pids_max,
+            open_stdin,
+            tty,
+            timeout_ms,
+            deadline,
+            timeout_signal,
+            timeout_grace_period_ms,
+            timeout_escalation_signal,
+            namespace,
+            root,
+            profile,
+            rlimit,
+            line_framing,
+            max_line_bytes,
+            flush_timeout_ms,
+        } => {
+            let limits = ResourceLimits {
+                cpu_max: cpu_max.map(|cpu_max| cpu_max.0),
+                memory_max: memory_max.unwrap_or(0),
+                io_max: io_max.into_iter().map(|io_max| io_max.0).collect(),
+                pids_max: pids_max.unwrap_or(0),
+            };
+
+            // Anything left unset in the timeout policy is filled in with the server defaults.
+            let timeout_policy = StopPolicy {
+                signal: timeout_signal.map_or(0, |signal| signal.0),
+                grace_period_ms: timeout_grace_period_ms.unwrap_or(0),
+                escalation_signal: timeout_escalation_signal.map_or(0, |signal| signal.0),
+            };
+
+            let isolation = Isolation {
+                pid: namespace.contains(&Namespace::Pid),
+                mount: namespace.contains(&Namespace::Mount),
+                uts: namespace.contains(&Namespace::Uts),
+                ipc: namespace.contains(&Namespace::Ipc),
+                network: namespace.contains(&Namespace::Network),
+                root: root.unwrap_or_default(),
+            };
+
+            // Anything left unset in the line framing is filled in with the server defaults.
+            let line_framing =
+                if line_framing || max_line_bytes.is_some() || flush_timeout_ms.is_some() {
+                    Some(LineFraming {
+                        max_line_bytes: max_line_bytes.unwrap_or(0),
+                        flush_timeout_ms: flush_timeout_ms.unwrap_or(0),
+                    })
+                } else {
+                    None
+                };
+
+            let options = SpawnOptions {
+                limits,
+                open_stdin,
+                tty,
+                timeout: timeout_ms.map(Duration::from_millis),
+                deadline: deadline.map(Into::into),
+                timeout_policy: Some(timeout_policy),
+                isolation: Some(isolation),
+                profile,
+                rlimits: rlimit
+                    .into_iter()
+                    .map(|rlimit| (rlimit.0, rlimit.1))
+                    .collect(),
+                line_framing,
+            };
+
+            spawn(
+                &mut client,
+                program_path,
+                working_directory,
+                args.0,
+                envs.0,
+                options,
+            )
+            .await?
+        }
+        CommandOpts::Stop {
+            uuid,
+            signal,
+            grace_period_ms,
+            escalation_signal,
+        } => {
+            // Anything left unset is filled in with the server defaults.
+            let policy = StopPolicy {
+                signal: signal.map_or(0, |signal| signal.0),
+                grace_period_ms: grace_period_ms.unwrap_or(0),
+                escalation_signal: escalation_signal.map_or(0, |signal| signal.0),
+            };
+
+            stop(&mut client, uuid, policy).await?
+        }
         CommandOpts::StreamLog {
             uuid,
             past_events,
             stream_type,
-        } => stream_log(&mut client, uuid, past_events, stream_type).await?,
+            timestamps,
+        } => stream_log(&mut client, uuid, past_events, stream_type, timestamps).await?,
+        CommandOpts::Logs {
+            uuid,
+            streams,
+            head,
+            tail,
+            offset,
+            length,
+        } => {
+            let streams = log_streams(streams);
+
+            // Everything that's retained is printed a page at a time unless a range of lines is given.
+            match (head, tail) {
+                (Some(lines), _) => {
+                    let range = get_logs_request::Range::HeadLines(lines);
+                    print_logs(client.get_logs(uuid, streams, range).await?.events)?
+                }
+                (_, Some(lines)) => {
+                    let range = get_logs_request::Range::TailLines(lines);
+                    print_logs(client.get_logs(uuid, streams, range).await?.events)?
+                }
+                _ => {
+                    let offset = offset.unwrap_or(0);
+                    let length = length.unwrap_or(u64::MAX);
+                    logs(&mut client, uuid, streams, offset, length).await?
+                }
+            }
+        }
+        CommandOpts::Search {
+            uuid,
+            regex,
+            substring,
+            streams,
+            since,
+            until,
+            max_matches,
+        } => {
+            let pattern = match (regex, substring) {
+                (Some(regex), _) => search_logs_request::Pattern::Regex(regex),
+                (_, Some(substring)) => search_logs_request::Pattern::Substring(substring),
+                _ => unreachable!(),
+            };
+
+            let query = LogSearch {
+                pattern,
+                streams: log_streams(streams),
+                since: since.map(Into::into),
+                until: until.map(Into::into),
+                max_matches,
+            };
+
+            search(&mut client, uuid, query).await?
+        }
+        CommandOpts::Attach {
+            uuid,
+            past_events,
+            tty,
+        } => {
+            let code = attach(&mut client, uuid, past_events, tty).await?;
+
+            // Mirror the exit code of the job so that scripts can act on it.
+            std::process::exit(code)
+        }
         CommandOpts::Status { uuid } => status(&mut client, uuid).await?,
+        CommandOpts::Stats { uuid } => stats(&mut client, uuid).await?,
+        CommandOpts::Wait { uuid, timeout_ms } => {
+            let code = wait(&mut client, uuid, timeout_ms).await?;
+
+            // Mirror the exit code of the job so that scripts can act on it.
+            std::process::exit(code)
+        }
+        CommandOpts::Remove { uuid, force } => remove(&mut client, uuid, force).await?,
+        CommandOpts::List {
+            state,
+            page_size,
+            page_token,
+        } => {
+            let state = match state {
+                None => JobState::Unspecified,
+                Some(JobStateFilter::Running) => JobState::Running,
+                Some(JobStateFilter::Terminated) => JobState::Terminated,
+            };
+
+            list(&mut client, state, page_size, page_token).await?
+        }
     }
 
     Ok(())
@@ -62,55 +259,364 @@ async fn spawn(
     working_directory: String,
     args: Vec<String>,
     envs: HashMap<String, String>,
+    options: SpawnOptions,
 ) -> Result<()> {
     let uuid = client
-        .spawn(program_path, working_directory, args, envs)
+        .spawn(program_path, working_directory, args, envs, options)
         .await?;
 
     println!("spawned job with id {}", uuid);
     Ok(())
 }
 
-async fn stop(client: &mut Client, uuid: Uuid) -> Result<()> {
-    client.stop(uuid).await?;
+async fn stop(client: &mut Client, uuid: Uuid, policy: StopPolicy) -> Result<()> {
+    client.stop(uuid, policy).await?;
     println!("stopped job with id {} if it was running", uuid);
     Ok(())
 }
 
+async fn remove(client: &mut Client, uuid: Uuid, force: bool) -> Result<()> {
+    client.remove_job(uuid, force).await?;
+    println!("removed job with id {}", uuid);
+    Ok(())
+}
+
 async fn stream_log(
     client: &mut Client,
     uuid: Uuid,
     past_events: bool,
     stream_type: StreamType,
+    timestamps: bool,
 ) -> Result<()> {
     // Grab the stream of raw events coming from the server.
     let mut stream = client.stream_log(uuid, past_events).await?;
 
     // Creates the appropriate event formatter based on the stream_type parameter.
-    let mut writer = stream_type.writer();
+    let mut writer = stream_type.writer(timestamps);
     writer.start()?;
 
-    // Continue to accept events and write them out using the writer.
-    while let Some(Ok(event)) = stream.next().await {
-        if let StreamStatus::Terminated(code) = writer.write(event)? {
-            println!("terminated with exit code {}", code);
-            break;
+    // The sequence number of the last event received, which a dropped stream is resumed right after.
+    let mut last_sequence = None;
+    let mut attempts = 0;
+
+    loop {
+        // Continue to accept events and write them out using the writer.
+        while let Some(event) = stream.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    eprintln!("log stream dropped: {}", error.message());
+                    break;
+                }
+            };
+
+            attempts = 0;
+            last_sequence = Some(event.sequence);
+            if let StreamStatus::Terminated(status) = writer.write(event)? {
+                println!("terminated with {}", status);
+                return Ok(());
+            }
         }
+
+        // The job hasn't terminated yet so the stream was cut short. Pick up right where it left off.
+        stream = loop {
+            if attempts == RECONNECT_ATTEMPTS {
+                return Err(anyhow!("gave up reconnecting to the log stream"));
+            }
+
+            attempts += 1;
+            time::sleep(RECONNECT_DELAY).await;
+
+            let resumed = match last_sequence {
+                Some(sequence) => client.resume_log(uuid, sequence + 1).await,
+                None => client.stream_log(uuid, past_events).await,
+            };
+
+            match resumed {
+                Ok(stream) => break stream,
+                Err(error) => eprintln!("failed to reconnect to the log stream: {}", error),
+            }
+        };
+    }
+}
+
+/// Print a range of bytes of the retained output of a job, noting any part of it that was discarded.
+async fn logs(
+    client: &mut Client,
+    uuid: Uuid,
+    streams: LogStreams,
+    offset: u64,
+    length: u64,
+) -> Result<()> {
+    let mut pages = Box::pin(client.page_logs(uuid, streams, offset, length));
+    let mut next = offset;
+
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        if page.offsets.start > next {
+            eprintln!(
+                "{} bytes of earlier output were discarded",
+                page.offsets.start - next
+            );
+        }
+
+        next = page.offsets.end;
+        print_logs(page.events)?;
     }
 
-    // We've processed all events the server has to send.
     Ok(())
 }
 
+/// Print the output of a job, each stream to the matching stream of the client.
+fn print_logs(events: Vec<StreamLogResponse>) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    let mut stderr = std::io::stderr();
+
+    for event in events {
+        match event.response {
+            Some(stream_log_response::Response::Stdout(inner)) => {
+                stdout.write_all(&inner.output)?
+            }
+            Some(stream_log_response::Response::Stderr(inner)) => {
+                stderr.write_all(&inner.output)?
+            }
+            _ => (),
+        }
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print the lines of the output of a job matching a search along with their sequence numbers and times.
+async fn search(client: &mut Client, uuid: Uuid, search: LogSearch) -> Result<()> {
+    let matches = client.search_logs(uuid, search).await?;
+
+    for line in matches {
+        let at = UNIX_EPOCH + Duration::from_millis(line.at_unix_ms);
+        let (stream, output) = match line.response {
+            Some(stream_log_response::Response::Stdout(inner)) => ("stdout", inner.output),
+            Some(stream_log_response::Response::Stderr(inner)) => ("stderr", inner.output),
+            _ => continue,
+        };
+
+        println!(
+            "#{} [{}] {}: {}",
+            line.sequence,
+            humantime::format_rfc3339_millis(at),
+            stream,
+            String::from_utf8_lossy(&output)
+        );
+    }
+
+    Ok(())
+}
+
+/// The streams to read in our gRPC protocol format.
+fn log_streams(streams: LogStreamsFilter) -> LogStreams {
+    match streams {
+        LogStreamsFilter::Stdout => LogStreams::Stdout,
+        LogStreamsFilter::Stderr => LogStreams::Stderr,
+        LogStreamsFilter::Both => LogStreams::Both,
+    }
+}
+
+/// Wire the local terminal up to a job, forwarding our stdin to it and writing its output
+/// to our stdout and stderr. Returns the exit code to exit with once the job has terminated.
+///
+/// With `tty` the local terminal is put in raw mode and its window size is kept in sync
+/// with that of the job, which is meant for jobs spawned with a terminal.
+async fn attach(client: &mut Client, uuid: Uuid, past_events: bool, tty: bool) -> Result<i32> {
+    let (input, input_rx) = mpsc::unbounded_channel();
+    let mut stream = client.attach(uuid, past_events, input_rx).await?;
+
+    // Restores the local terminal once we're done, which is before the exit code is acted on.
+    let _raw_mode = if tty {
+        if let Some((rows, cols)) = terminal::size() {
+            let _ = input.send(AttachInput::Resize { rows, cols });
+        }
+
+        let mut window_changes = signal(SignalKind::window_change())?;
+        let input = input.clone();
+        tokio::spawn(async move {
+            while window_changes.recv().await.is_some() {
+                if let Some((rows, cols)) = terminal::size() {
+                    if input.send(AttachInput::Resize { rows, cols }).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Some(RawMode::enable()?)
+    } else {
+        None
+    };
+
+    tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin();
+        let mut buffer = [0; 1024];
+
+        // Close the stdin of the job once ours is closed.
+        loop {
+            let input_event = match stdin.read(&mut buffer).await {
+                Ok(read) if read != 0 => AttachInput::Stdin(buffer[..read].to_vec()),
+                _ => AttachInput::Eof,
+            };
+
+            let eof = input_event == AttachInput::Eof;
+            if input.send(input_event).is_err() || eof {
+                break;
+            }
+        }
+    });
+
+    while let Some(event) = stream.next().await {
+        match event?.response {
+            Some(stream_log_response::Response::Stdout(inner)) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&inner.output)?;
+                stdout.flush()?;
+            }
+            Some(stream_log_response::Response::Stderr(inner)) => {
+                std::io::stderr().write_all(&inner.output)?;
+            }
+            Some(stream_log_response::Response::Exit(exit)) => {
+                let status = client::ExitStatus::try_from(exit.status)?;
+
+                // A raw terminal doesn't move back to the start of the line by itself.
+                if tty {
+                    eprint!("\r\nterminated with {}\r\n", status);
+                } else {
+                    eprintln!("terminated with {}", status);
+                }
+
+                return Ok(status.shell_code());
+            }
+            Some(stream_log_response::Response::Truncated(inner)) => {
+                eprintln!("{} bytes of earlier output were discarded", inner.bytes);
+            }
+            Some(stream_log_response::Response::Gap(inner)) => {
+                eprintln!("{} events were dropped while falling behind", inner.events);
+            }
+            Some(stream_log_response::Response::TimedOut(_)) => {
+                eprintln!("job ran past its timeout or deadline and is being stopped");
+            }
+            Some(stream_log_response::Response::Stop(_)) | None => {}
+        }
+    }
+
+    Err(anyhow!("connection closed before the job terminated"))
+}
+
 async fn status(client: &mut Client, uuid: Uuid) -> Result<()> {
     let status = client.status(uuid).await?;
 
     match status {
         JobStatus::Running => println!("job with id {} is running", uuid),
-        JobStatus::Terminated(code) => {
-            println!("job with id {} has terminated with code {}", uuid, code)
+        JobStatus::Terminated(status) => {
+            println!("job with id {} has terminated with {}", uuid, status)
+        }
+        JobStatus::TimedOut(status) => println!(
+            "job with id {} timed out and has terminated with {}",
+            uuid, status
+        ),
+    }
+
+    Ok(())
+}
+
+async fn stats(client: &mut Client, uuid: Uuid) -> Result<()> {
+    let (usage, terminated, storage) = client.job_stats(uuid).await?;
+    let usage = match usage {
+        Some(usage) => usage,
+        None => {
+            println!("job with id {} hasn't been sampled yet", uuid);
+            return Ok(());
+        }
+    };
+
+    let state = if terminated {
+        "used"
+    } else {
+        "has used so far"
+    };
+    println!("job with id {} {}:", uuid, state);
+    println!(
+        "  user time: {:?}",
+        Duration::from_micros(usage.user_time_us)
+    );
+    println!(
+        "  system time: {:?}",
+        Duration::from_micros(usage.system_time_us)
+    );
+    println!("  peak memory: {} bytes", usage.max_rss_bytes);
+    println!("  read: {} bytes", usage.read_bytes);
+    println!("  written: {} bytes", usage.write_bytes);
+    println!(
+        "  context switches: {} voluntary, {} involuntary",
+        usage.voluntary_context_switches, usage.involuntary_context_switches
+    );
+
+    if storage.raw_bytes != 0 {
+        println!(
+            "  output on disk: {} bytes compressed to {} bytes",
+            storage.raw_bytes, storage.compressed_bytes
+        );
+    }
+
+    Ok(())
+}
+
+async fn wait(client: &mut Client, uuid: Uuid, timeout_ms: u64) -> Result<i32> {
+    let status = client.wait(uuid, timeout_ms).await?;
+    println!("job with id {} has terminated with {}", uuid, status);
+    Ok(status.shell_code())
+}
+
+async fn list(
+    client: &mut Client,
+    state: JobState,
+    page_size: u32,
+    page_token: String,
+) -> Result<()> {
+    let (jobs, next_page_token) = client.list(state, page_size, page_token).await?;
+
+    for job in jobs {
+        let started_at = humantime::format_rfc3339_seconds(job.started_at);
+        let command = std::iter::once(&job.program)
+            .chain(&job.args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match (job.status, job.finished_at) {
+            (JobStatus::Terminated(status), Some(finished_at)) => println!(
+                "{} started {} terminated {} with {}: {}",
+                job.uuid,
+                started_at,
+                humantime::format_rfc3339_seconds(finished_at),
+                status,
+                command
+            ),
+            (JobStatus::TimedOut(status), Some(finished_at)) => println!(
+                "{} started {} timed out and terminated {} with {}: {}",
+                job.uuid,
+                started_at,
+                humantime::format_rfc3339_seconds(finished_at),
+                status,
+                command
+            ),
+            _ => println!("{} started {} running: {}", job.uuid, started_at, command),
         }
     }
 
+    if let Some(page_token) = next_page_token {
+        println!(
+            "more jobs available, continue with --page-token {}",
+            page_token
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use std::io;
+use std::mem;
+
+/// Puts the local terminal in raw mode for as long as it's alive so that every keystroke, including control
+/// characters like Ctrl-C, is passed on to the remote terminal instead of being interpreted locally.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<Self> {
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let original = termios;
+        unsafe { libc::cfmakeraw(&mut termios) };
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// The window size of the local terminal as rows and columns, if stdout is a terminal.
+pub fn size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == -1 {
+        None
+    } else {
+        Some((size.ws_row, size.ws_col))
+    }
+}
@@ -1,5 +1,5 @@
 use super::{ENDPOINT, USERNAME};
-use crate::client::{Claims, Jwt, UnauthorizedClient};
+use crate::client::{Claims, Jwt, SpawnOptions, UnauthorizedClient};
 use crate::{CLIENT_CERT, CLIENT_KEY, SERVER_CA_CERT};
 use anyhow::Result;
 use serial_test::serial;
@@ -20,6 +20,7 @@ async fn success() {
                 ".".into(),
                 vec!["-c".into(), "echo hi".into()],
                 HashMap::new(),
+                SpawnOptions::default(),
             )
             .await?;
 
@@ -51,6 +52,7 @@ async fn invalid_jwt_signature() {
                 ".".into(),
                 vec!["-c".into(), "echo hi".into()],
                 HashMap::new(),
+                SpawnOptions::default(),
             )
             .await?;
 
@@ -89,6 +91,7 @@ async fn missing_privileges() {
                 ".".into(),
                 vec!["-c".into(), "echo hi".into()],
                 HashMap::new(),
+                SpawnOptions::default(),
             )
             .await?;
 
@@ -1,10 +1,19 @@
 use super::{ENDPOINT, USERNAME};
+use crate::client::{AttachInput, ExitStatus, JobStatus, LogSearch, SpawnOptions};
 use anyhow::{anyhow, Result};
 use futures::StreamExt;
-use protocol::stream_log_response;
+use protocol::{
+    get_logs_request, search_logs_request, stream_log_response, JobState, LineFraming, LogStreams,
+    StopPolicy, StreamLogResponse,
+};
 use serial_test::serial;
 use server::server;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::time::Duration;
+use tokio::{sync::mpsc, time};
+use tonic::Streaming;
 
 #[tokio::test]
 #[serial]
@@ -19,6 +28,7 @@ async fn spawn_capture_verify_stdout() {
                 ".".into(),
                 vec!["-c".into(), "echo hi".into()],
                 HashMap::new(),
+                SpawnOptions::default(),
             )
             .await?;
 
@@ -40,3 +50,836 @@ async fn spawn_capture_verify_stdout() {
 
     test().await.unwrap()
 }
+
+#[tokio::test]
+#[serial]
+async fn stream_sequenced_events() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "echo out; sleep 0.1; echo err >&2".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        let events: Vec<_> = client.stream_log(uuid, true).await?.take(3).collect().await;
+        let mut sequences = Vec::new();
+        let mut times = Vec::new();
+
+        for event in events {
+            let event = event?;
+            sequences.push(event.sequence);
+            times.push(event.at_unix_ms);
+        }
+
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert!(times[0] > 0);
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn resume_log_from_sequence() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "echo a; sleep 0.1; echo b".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let mut stream = client.resume_log(uuid, 2).await?;
+        assert_eq!(stdout_lines(&mut stream, 1).await, vec!["b"]);
+        wait_for_exit(&mut stream).await;
+
+        // Nothing has been published past the exit event yet.
+        assert!(client.resume_log(uuid, 5).await.is_err());
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn get_log_ranges() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "printf 'a\\nb\\nc\\n'; echo err >&2".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let ranges = vec![
+            (get_logs_request::Range::HeadLines(1), "a\n"),
+            (get_logs_request::Range::TailLines(2), "b\nc\n"),
+            (
+                get_logs_request::Range::Bytes(get_logs_request::ByteRange {
+                    offset: 2,
+                    length: 2,
+                }),
+                "b\n",
+            ),
+        ];
+
+        for (range, expected) in ranges {
+            let mut stdout = Vec::new();
+            for event in client
+                .get_logs(uuid, LogStreams::Stdout, range)
+                .await?
+                .events
+            {
+                match event.response {
+                    Some(stream_log_response::Response::Stdout(inner)) => {
+                        stdout.extend(inner.output)
+                    }
+                    _ => return Err(anyhow!("unexpected event in stdout range")),
+                }
+            }
+
+            assert_eq!(stdout, expected.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn page_through_logs() {
+    async fn test() -> Result<()> {
+        let mut config = server::Config::default();
+        config.retention.memory_limit_bytes = 2 * 1024 * 1024;
+        tokio::spawn(server::serve_with_config(config));
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        // More output than is retained, and what's retained is more than a single read returns.
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "seq 1 500000".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let expected: String = (1..=500000).map(|i| format!("{}\n", i)).collect();
+
+        let mut pages = Box::pin(client.page_logs(uuid, LogStreams::Stdout, 0, u64::MAX));
+        let mut offsets = Vec::new();
+        let mut stdout = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            offsets.push(page.offsets);
+            for event in page.events {
+                if let Some(stream_log_response::Response::Stdout(inner)) = event.response {
+                    stdout.extend(inner.output);
+                }
+            }
+        }
+
+        let start = offsets[0].start;
+        assert!(start > 0);
+        assert!(offsets.len() > 1);
+        assert!(offsets.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert_eq!(stdout, expected.as_bytes()[start as usize..]);
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn spawn_with_line_framing() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let options = SpawnOptions {
+            line_framing: Some(LineFraming {
+                max_line_bytes: 0,
+                flush_timeout_ms: 1000,
+            }),
+            ..Default::default()
+        };
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "printf a; sleep 0.2; printf 'b\\nc\\n'".into()],
+                HashMap::new(),
+                options,
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let lines: Vec<_> = client
+            .get_logs(
+                uuid,
+                LogStreams::Stdout,
+                get_logs_request::Range::HeadLines(2),
+            )
+            .await?
+            .events
+            .into_iter()
+            .map(|event| match event.response {
+                Some(stream_log_response::Response::Stdout(inner)) => inner.output,
+                _ => Vec::new(),
+            })
+            .collect();
+
+        assert_eq!(lines, vec![b"ab\n".to_vec(), b"c\n".to_vec()]);
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn search_logs() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec![
+                    "-c".into(),
+                    "echo 'warn: a'; echo ok; echo 'warn: b' >&2; printf 'warn: c'".into(),
+                ],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let search = |pattern, streams| LogSearch {
+            pattern,
+            streams,
+            since: None,
+            until: None,
+            max_matches: None,
+        };
+
+        let matches = client
+            .search_logs(
+                uuid,
+                search(
+                    search_logs_request::Pattern::Regex("^warn: [ac]$".into()),
+                    LogStreams::Both,
+                ),
+            )
+            .await?;
+
+        let lines: Vec<_> = matches
+            .into_iter()
+            .map(|line| match line.response {
+                Some(stream_log_response::Response::Stdout(inner)) => inner.output,
+                _ => Vec::new(),
+            })
+            .collect();
+
+        assert_eq!(lines, vec![b"warn: a".to_vec(), b"warn: c".to_vec()]);
+
+        let matches = client
+            .search_logs(
+                uuid,
+                search(
+                    search_logs_request::Pattern::Substring("warn".into()),
+                    LogStreams::Stderr,
+                ),
+            )
+            .await?;
+
+        assert_eq!(matches.len(), 1);
+        assert!(client
+            .search_logs(
+                uuid,
+                search(
+                    search_logs_request::Pattern::Regex("(".into()),
+                    LogStreams::Both
+                )
+            )
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn spawn_report_termination_signal() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "kill -KILL $$".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        let events: Vec<_> = client.stream_log(uuid, true).await?.take(1).collect().await;
+        let response = events[0].as_ref().unwrap().response.as_ref().unwrap();
+
+        if let stream_log_response::Response::Exit(inner) = response {
+            let status = ExitStatus::try_from(inner.status.clone())?;
+            assert_eq!(
+                status,
+                ExitStatus::Signal {
+                    signal: 9,
+                    core_dumped: false
+                }
+            );
+
+            assert!(matches!(
+                client.status(uuid).await?,
+                JobStatus::Terminated(ExitStatus::Signal { signal: 9, .. })
+            ));
+
+            Ok(())
+        } else {
+            Err(anyhow!("wrong event type"))
+        }
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn stop_escalates_after_grace_period() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec![
+                    "-c".into(),
+                    "trap '' TERM; echo ready; while true; do sleep 0.1; done".into(),
+                ],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        // Wait until the job has started ignoring SIGTERM before stopping it.
+        let mut stream = client.stream_log(uuid, true).await?;
+        stream.next().await;
+
+        let policy = StopPolicy {
+            signal: 15,
+            grace_period_ms: 200,
+            escalation_signal: 9,
+        };
+
+        client.stop(uuid, policy).await?;
+        let events: Vec<_> = stream
+            .take(3)
+            .map(|event| event.unwrap().response.unwrap())
+            .collect()
+            .await;
+
+        assert!(matches!(
+            &events[0],
+            stream_log_response::Response::Stop(stream_log_response::StreamLogStopEvent {
+                signal: 15,
+                escalated: false
+            })
+        ));
+
+        assert!(matches!(
+            &events[1],
+            stream_log_response::Response::Stop(stream_log_response::StreamLogStopEvent {
+                signal: 9,
+                escalated: true
+            })
+        ));
+
+        if let stream_log_response::Response::Exit(inner) = &events[2] {
+            let status = ExitStatus::try_from(inner.status.clone())?;
+            assert!(matches!(status, ExitStatus::Signal { signal: 9, .. }));
+            Ok(())
+        } else {
+            Err(anyhow!("wrong event type"))
+        }
+    }
+
+    test().await.unwrap()
+}
+
+/// Check if a process exists and hasn't terminated. Zombies waiting to be reaped by init don't count.
+fn is_running(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map(|stat| {
+            let state = stat[stat.rfind(')').unwrap() + 1..]
+                .split_whitespace()
+                .next();
+            state != Some("Z")
+        })
+        .unwrap_or(false)
+}
+
+/// Collect the first `count` lines a job writes to stdout.
+async fn stdout_lines(stream: &mut Streaming<StreamLogResponse>, count: usize) -> Vec<String> {
+    let mut stdout = String::new();
+
+    while stdout.lines().count() < count {
+        let event = stream.next().await.unwrap().unwrap();
+        if let Some(stream_log_response::Response::Stdout(inner)) = event.response {
+            stdout.push_str(std::str::from_utf8(&inner.output).unwrap());
+        }
+    }
+
+    stdout.lines().map(String::from).collect()
+}
+
+/// Wait for a job to exit, skipping over any other events.
+async fn wait_for_exit(stream: &mut Streaming<StreamLogResponse>) {
+    while let Some(Ok(event)) = stream.next().await {
+        if let Some(stream_log_response::Response::Exit(_)) = event.response {
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn stop_kills_entire_process_tree() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        // One grandchild stays in the process group of the job and the other escapes into a session of its own.
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec![
+                    "-c".into(),
+                    "sleep 1000 & echo $!; setsid sleep 1000 & echo $!; wait".into(),
+                ],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        let mut stream = client.stream_log(uuid, true).await?;
+        let pids: Vec<u32> = stdout_lines(&mut stream, 2)
+            .await
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect();
+
+        assert!(pids.iter().all(|pid| is_running(*pid)));
+        client.stop(uuid, StopPolicy::default()).await?;
+        wait_for_exit(&mut stream).await;
+
+        // Signalled processes may take a moment to actually go away.
+        for _ in 0..50 {
+            if pids.iter().all(|pid| !is_running(*pid)) {
+                return Ok(());
+            }
+
+            time::sleep(Duration::from_millis(20)).await;
+        }
+
+        Err(anyhow!("orphaned processes survived stopping the job"))
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn exit_kills_background_processes() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "sleep 1000 | cat & echo $!".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        let mut stream = client.stream_log(uuid, true).await?;
+        let pid: u32 = stdout_lines(&mut stream, 1).await[0].parse()?;
+        wait_for_exit(&mut stream).await;
+
+        for _ in 0..50 {
+            if !is_running(pid) {
+                return Ok(());
+            }
+
+            time::sleep(Duration::from_millis(20)).await;
+        }
+
+        Err(anyhow!("background process survived the job exiting"))
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn list_jobs_filter_paginate() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+        let mut uuids = Vec::new();
+
+        for script in &["true", "sleep 1000", "true"] {
+            let uuid = client
+                .spawn(
+                    "/bin/bash".into(),
+                    ".".into(),
+                    vec!["-c".into(), script.to_string()],
+                    HashMap::new(),
+                    SpawnOptions::default(),
+                )
+                .await?;
+
+            uuids.push(uuid);
+        }
+
+        wait_for_exit(&mut client.stream_log(uuids[0], true).await?).await;
+        wait_for_exit(&mut client.stream_log(uuids[2], true).await?).await;
+
+        let (first, token) = client.list(JobState::Unspecified, 2, String::new()).await?;
+        let (second, end) = client
+            .list(JobState::Unspecified, 2, token.unwrap())
+            .await?;
+        let listed: Vec<_> = first.iter().chain(&second).map(|job| job.uuid).collect();
+        assert_eq!(listed, uuids);
+        assert!(end.is_none());
+
+        let (running, _) = client.list(JobState::Running, 0, String::new()).await?;
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].uuid, uuids[1]);
+        assert_eq!(running[0].args[1], "sleep 1000");
+
+        let (terminated, _) = client.list(JobState::Terminated, 0, String::new()).await?;
+        assert_eq!(terminated.len(), 2);
+        assert!(terminated.iter().all(|job| matches!(
+            job.status,
+            JobStatus::Terminated(ExitStatus::Code(0))
+        ) && job.finished_at.is_some()));
+
+        client.stop(uuids[1], StopPolicy::default()).await?;
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn wait_for_termination() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/bash".into(),
+                ".".into(),
+                vec!["-c".into(), "sleep 0.2; exit 7".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        assert!(client.wait(uuid, 10).await.is_err());
+        assert_eq!(client.wait(uuid, 0).await?, ExitStatus::Code(7));
+
+        // Waiting on a job that has already terminated returns immediately.
+        assert_eq!(client.wait(uuid, 10).await?, ExitStatus::Code(7));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn job_stats_after_exit() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/sh".into(),
+                ".".into(),
+                vec![
+                    "-c".into(),
+                    "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done".into(),
+                ],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        client.wait(uuid, 0).await?;
+        let (usage, terminated, _) = client.job_stats(uuid).await?;
+        let usage = usage.ok_or_else(|| anyhow!("no usage recorded"))?;
+        assert!(terminated);
+        assert!(usage.user_time_us + usage.system_time_us > 0);
+        assert!(usage.max_rss_bytes > 0);
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn stop_after_timeout() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let options = SpawnOptions {
+            timeout: Some(Duration::from_millis(200)),
+            timeout_policy: Some(StopPolicy {
+                signal: libc::SIGINT,
+                grace_period_ms: 0,
+                escalation_signal: 0,
+            }),
+            ..Default::default()
+        };
+
+        let uuid = client
+            .spawn(
+                "/bin/sleep".into(),
+                ".".into(),
+                vec!["30".into()],
+                HashMap::new(),
+                options,
+            )
+            .await?;
+
+        let mut stream = client.stream_log(uuid, true).await?;
+        let status = client.wait(uuid, 5000).await?;
+        let expected = ExitStatus::Signal {
+            signal: libc::SIGINT,
+            core_dumped: false,
+        };
+
+        assert_eq!(status, expected);
+        assert!(matches!(
+            client.status(uuid).await?,
+            JobStatus::TimedOut(status) if status == expected
+        ));
+
+        // The stream tells that the job timed out before it tells about stopping it.
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            let event = event?.response;
+            let exit = matches!(event, Some(stream_log_response::Response::Exit(_)));
+            events.push(event);
+
+            if exit {
+                break;
+            }
+        }
+
+        assert!(matches!(
+            events.as_slice(),
+            [
+                Some(stream_log_response::Response::TimedOut(_)),
+                Some(stream_log_response::Response::Stop(_)),
+                Some(stream_log_response::Response::Exit(_)),
+            ]
+        ));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn remove_jobs() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/sleep".into(),
+                ".".into(),
+                vec!["30".into()],
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        // Running jobs are only removed when forced.
+        assert!(client.remove_job(uuid, false).await.is_err());
+        assert!(matches!(client.status(uuid).await?, JobStatus::Running));
+
+        client.remove_job(uuid, true).await?;
+        assert!(client.status(uuid).await.is_err());
+        assert!(client.remove_job(uuid, true).await.is_err());
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn attach_feed_stdin() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let options = SpawnOptions {
+            open_stdin: true,
+            ..Default::default()
+        };
+
+        let uuid = client
+            .spawn(
+                "/bin/cat".into(),
+                ".".into(),
+                Vec::new(),
+                HashMap::new(),
+                options,
+            )
+            .await?;
+
+        let (input, input_rx) = mpsc::unbounded_channel();
+        let mut stream = client.attach(uuid, true, input_rx).await?;
+
+        input.send(AttachInput::Stdin(b"hello\n".to_vec()))?;
+        assert_eq!(stdout_lines(&mut stream, 1).await, vec!["hello"]);
+
+        // Cat exits on its own once it reads end-of-file.
+        input.send(AttachInput::Eof)?;
+        assert_eq!(client.wait(uuid, 5000).await?, ExitStatus::Code(0));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn attach_requires_open_stdin() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let uuid = client
+            .spawn(
+                "/bin/cat".into(),
+                ".".into(),
+                Vec::new(),
+                HashMap::new(),
+                SpawnOptions::default(),
+            )
+            .await?;
+
+        let (_input, input_rx) = mpsc::unbounded_channel();
+        assert!(client.attach(uuid, true, input_rx).await.is_err());
+
+        // Without stdin open the job reads end-of-file right away.
+        assert_eq!(client.wait(uuid, 5000).await?, ExitStatus::Code(0));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn attach_tty_resize() {
+    async fn test() -> Result<()> {
+        tokio::spawn(server::serve());
+        let mut client = crate::init_client(USERNAME.into(), ENDPOINT).await?;
+
+        let options = SpawnOptions {
+            tty: true,
+            ..Default::default()
+        };
+
+        let uuid = client
+            .spawn(
+                "/bin/sh".into(),
+                ".".into(),
+                vec![
+                    "-c".into(),
+                    "test -t 0 && test -t 1 && read line && stty size".into(),
+                ],
+                HashMap::new(),
+                options,
+            )
+            .await?;
+
+        let (input, input_rx) = mpsc::unbounded_channel();
+        let mut stream = client.attach(uuid, true, input_rx).await?;
+        input.send(AttachInput::Resize {
+            rows: 30,
+            cols: 100,
+        })?;
+        input.send(AttachInput::Stdin(b"go\n".to_vec()))?;
+
+        // The terminal echoes the input back before the job reports its window size.
+        assert_eq!(stdout_lines(&mut stream, 2).await, vec!["go", "30 100"]);
+        assert_eq!(client.wait(uuid, 5000).await?, ExitStatus::Code(0));
+        Ok(())
+    }
+
+    test().await.unwrap()
+}
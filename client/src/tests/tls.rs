@@ -1,5 +1,5 @@
 use super::{ENDPOINT, USERNAME};
-use crate::client::{Claims, UnauthorizedClient};
+use crate::client::{Claims, SpawnOptions, UnauthorizedClient};
 use crate::{CLIENT_CERT, CLIENT_KEY};
 use anyhow::Result;
 use serial_test::serial;
@@ -30,6 +30,7 @@ async fn wrong_server_cert_issuer() {
                 ".".into(),
                 vec!["hi pal".into()],
                 HashMap::new(),
+                SpawnOptions::default(),
             )
             .await?;
 
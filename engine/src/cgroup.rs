@@ -0,0 +1,340 @@
+use crate::usage::{self, ResourceUsage};
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time;
+
+/// The mount point of the unified cgroup v2 hierarchy.
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+/// The name of the cgroup all job cgroups are created under.
+const CGROUP_PARENT: &str = "worker-process-service";
+
+/// How many times we try to remove a cgroup that still has processes in it
+/// before giving up. Killed processes need a moment before they leave the cgroup.
+const REMOVE_ATTEMPTS: usize = 50;
+
+/// The delay between attempts to remove a busy cgroup.
+const REMOVE_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// The CPU bandwidth a job may consume, expressed as `quota` microseconds of CPU time
+/// every `period` microseconds. This maps directly onto `cpu.max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuMax {
+    pub quota: u64,
+    pub period: u64,
+}
+
+/// Bandwidth and IOPS limits for a single block device, identified by its major and minor number.
+/// This maps directly onto a line in `io.max`. Limits that are `None` are left unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoMax {
+    pub major: u32,
+    pub minor: u32,
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
+/// The resource limits applied to a job through its own cgroup.
+/// Limits that are `None` or empty are left unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    pub cpu_max: Option<CpuMax>,
+    pub memory_max: Option<u64>,
+    pub io_max: Vec<IoMax>,
+    pub pids_max: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Returns true if no limits are set. Jobs without limits aren't placed in a cgroup of their own.
+    pub fn is_empty(&self) -> bool {
+        self.cpu_max.is_none()
+            && self.memory_max.is_none()
+            && self.io_max.is_empty()
+            && self.pids_max.is_none()
+    }
+
+    /// The controllers that need to be enabled for these limits to be enforced.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+
+        if self.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+
+        if !self.io_max.is_empty() {
+            controllers.push("io");
+        }
+
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+
+        controllers
+    }
+
+    /// The interface files and their contents that need to be written to apply these limits.
+    fn files(&self) -> Vec<(&'static str, String)> {
+        let mut files = Vec::new();
+
+        if let Some(cpu_max) = self.cpu_max {
+            files.push(("cpu.max", format!("{} {}", cpu_max.quota, cpu_max.period)));
+        }
+
+        if let Some(memory_max) = self.memory_max {
+            files.push(("memory.max", memory_max.to_string()));
+        }
+
+        // The kernel only accepts one device per write to `io.max`.
+        for io_max in &self.io_max {
+            files.push(("io.max", io_max.to_string()));
+        }
+
+        if let Some(pids_max) = self.pids_max {
+            files.push(("pids.max", pids_max.to_string()));
+        }
+
+        files
+    }
+}
+
+impl std::fmt::Display for IoMax {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn limit(value: Option<u64>) -> String {
+            value.map_or_else(|| "max".into(), |value| value.to_string())
+        }
+
+        write!(
+            f,
+            "{}:{} rbps={} wbps={} riops={} wiops={}",
+            self.major,
+            self.minor,
+            limit(self.rbps),
+            limit(self.wbps),
+            limit(self.riops),
+            limit(self.wiops)
+        )
+    }
+}
+
+/// A cgroup created for a single job.
+///
+/// The job is moved into the cgroup by the child itself before it calls exec so that
+/// no process the job creates can ever run outside of it.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+
+    /// An open handle to `cgroup.procs`, used by the child to move itself into the cgroup.
+    procs: File,
+}
+
+impl Cgroup {
+    /// Create a new cgroup with the given name and apply the limits to it.
+    pub fn create(name: &str, limits: &ResourceLimits) -> Result<Self> {
+        let controllers = limits.controllers();
+        let parent = Path::new(CGROUP_MOUNT).join(CGROUP_PARENT);
+        create_dir(&parent)?;
+
+        // Controllers need to be enabled in every ancestor for them to be available to the job cgroup.
+        enable_controllers(Path::new(CGROUP_MOUNT), &controllers)?;
+        enable_controllers(&parent, &controllers)?;
+
+        let path = parent.join(name);
+        fs::create_dir(&path)
+            .with_context(|| format!("failed to create cgroup {}", path.display()))?;
+
+        let cgroup = Self {
+            procs: OpenOptions::new()
+                .write(true)
+                .open(path.join("cgroup.procs"))
+                .context("failed to open cgroup.procs")?,
+            path,
+        };
+
+        for (file, contents) in limits.files() {
+            fs::write(cgroup.path.join(file), contents)
+                .with_context(|| format!("failed to write cgroup limit {}", file))?;
+        }
+
+        Ok(cgroup)
+    }
+
+    /// Open the existing cgroup with the given name, like the cgroup of a job from before a restart.
+    pub fn open(name: &str) -> Option<Self> {
+        let path = Path::new(CGROUP_MOUNT).join(CGROUP_PARENT).join(name);
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))
+            .ok()?;
+
+        Some(Self { path, procs })
+    }
+
+    /// The raw file descriptor of `cgroup.procs`. This is inherited by the forked child
+    /// and closed on exec.
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// The resource usage of every process that has ever been in the cgroup. Memory and IO are only accounted
+    /// for if their controllers are enabled and context switches aren't accounted for at all.
+    pub fn usage(&self) -> Option<ResourceUsage> {
+        let read = |file| fs::read_to_string(self.path.join(file)).unwrap_or_default();
+        let cpu = read("cpu.stat");
+        let usec = |key| usage::field(&cpu, key).map(Duration::from_micros);
+
+        // Every device has a line of its own in `io.stat` like `8:0 rbytes=1 wbytes=2 rios=3 wios=4`.
+        let io = read("io.stat");
+        let io_bytes = |key: &str| -> u64 {
+            io.split_whitespace()
+                .filter_map(|pair| pair.strip_prefix(key)?.parse::<u64>().ok())
+                .sum()
+        };
+
+        Some(ResourceUsage {
+            user_time: usec("user_usec")?,
+            system_time: usec("system_usec")?,
+            max_rss_bytes: read("memory.peak").trim().parse().unwrap_or(0),
+            read_bytes: io_bytes("rbytes="),
+            write_bytes: io_bytes("wbytes="),
+            ..Default::default()
+        })
+    }
+
+    /// Remove the cgroup, killing any processes that are left in it.
+    pub async fn remove(self) -> Result<()> {
+        for _ in 0..REMOVE_ATTEMPTS {
+            match fs::remove_dir(&self.path) {
+                Ok(()) => return Ok(()),
+                Err(error) if error.raw_os_error() == Some(libc::EBUSY) => {
+                    self.kill_all()?;
+                    time::sleep(REMOVE_RETRY_DELAY).await;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Err(anyhow!("cgroup {} is still busy", self.path.display()))
+    }
+
+    /// Send SIGKILL to every process still in the cgroup.
+    fn kill_all(&self) -> Result<()> {
+        // `cgroup.kill` is only available on Linux 5.14 and later so fall back to killing
+        // processes one by one if it doesn't exist.
+        match fs::write(self.path.join("cgroup.kill"), "1") {
+            Ok(()) => return Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        let procs = fs::read_to_string(self.path.join("cgroup.procs"))?;
+        for pid in procs.lines().filter_map(|line| line.parse().ok()) {
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Best effort cleanup for cgroups that were never handed a process, like when spawning fails.
+        // This is a no-op if the cgroup has already been removed.
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is open as `procs_fd`.
+///
+/// This runs in the forked child before exec and must therefore be async-signal-safe.
+pub fn join(procs_fd: RawFd) -> io::Result<()> {
+    // Writing 0 to `cgroup.procs` moves the writing process.
+    let pid = b"0";
+    let written = unsafe { libc::write(procs_fd, pid.as_ptr() as *const libc::c_void, pid.len()) };
+
+    if written < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Create a cgroup directory if it doesn't already exist.
+fn create_dir(path: &Path) -> Result<()> {
+    match fs::create_dir(path) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        Err(error) => {
+            Err(error).with_context(|| format!("failed to create cgroup {}", path.display()))
+        }
+    }
+}
+
+/// Enable the given controllers for the children of a cgroup.
+fn enable_controllers(path: &Path, controllers: &[&str]) -> Result<()> {
+    if controllers.is_empty() {
+        return Ok(());
+    }
+
+    let enable = controllers
+        .iter()
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    fs::write(path.join("cgroup.subtree_control"), enable).with_context(|| {
+        format!(
+            "failed to enable cgroup controllers {:?} in {}",
+            controllers,
+            path.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuMax, IoMax, ResourceLimits};
+
+    #[test]
+    fn limit_files() {
+        let limits = ResourceLimits {
+            cpu_max: Some(CpuMax {
+                quota: 50000,
+                period: 100000,
+            }),
+            memory_max: Some(1 << 20),
+            io_max: vec![IoMax {
+                major: 8,
+                minor: 0,
+                rbps: Some(1024),
+                wbps: None,
+                riops: None,
+                wiops: Some(10),
+            }],
+            pids_max: Some(16),
+        };
+
+        assert_eq!(limits.controllers(), vec!["cpu", "memory", "io", "pids"]);
+        assert_eq!(
+            limits.files(),
+            vec![
+                ("cpu.max", "50000 100000".into()),
+                ("memory.max", "1048576".into()),
+                ("io.max", "8:0 rbps=1024 wbps=max riops=max wiops=10".into()),
+                ("pids.max", "16".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::io;
+
+/// The unix user and groups a job runs as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+
+    /// The supplementary groups of the job. It has none other than its primary group if empty.
+    pub groups: Vec<libc::gid_t>,
+}
+
+impl Credentials {
+    /// Switch the calling process over to these credentials. The groups are changed first
+    /// since that's no longer permitted once the user has been changed.
+    ///
+    /// This runs in the forked child before exec and must therefore be async-signal-safe.
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(self.groups.len(), self.groups.as_ptr()) == -1
+                || libc::setgid(self.gid) == -1
+                || libc::setuid(self.uid) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
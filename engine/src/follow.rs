@@ -0,0 +1,65 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, ReadBuf},
+    time::{self, Instant, Sleep},
+};
+
+/// How often a followed file is checked for more output once everything written so far has been read.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Reads a file that is being written to by a job, like `tail -f`. Reaching the end of the file
+/// only ends reading once the job is done, at which point everything it has written has been read.
+#[derive(Debug)]
+pub struct Follow {
+    file: File,
+    done: Arc<AtomicBool>,
+    sleep: Pin<Box<Sleep>>,
+    waiting: bool,
+}
+
+impl Follow {
+    pub fn new(file: File, done: Arc<AtomicBool>) -> Self {
+        Self {
+            file,
+            done,
+            sleep: Box::pin(time::sleep(Duration::from_secs(0))),
+            waiting: false,
+        }
+    }
+}
+
+impl AsyncRead for Follow {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if self.waiting {
+                match self.sleep.as_mut().poll(cx) {
+                    Poll::Ready(()) => self.waiting = false,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            // Check whether the job is done before reading so that anything written before that is read.
+            let done = self.done.load(Ordering::SeqCst);
+            let filled = buf.filled().len();
+
+            match Pin::new(&mut self.file).poll_read(cx, buf) {
+                Poll::Ready(Ok(())) if buf.filled().len() == filled && !done => {
+                    self.sleep.as_mut().reset(Instant::now() + FOLLOW_INTERVAL);
+                    self.waiting = true;
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The longest line published by default before it's cut.
+const DEFAULT_MAX_LINE: usize = 64 * 1024;
+
+/// How long a partial line is held back by default before it's published anyway.
+const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Publish the output of a job as whole lines rather than however it happened to be read.
+/// Lines longer than the maximum are cut, at a character boundary should the line be UTF-8,
+/// and a partial line is published anyway once it has been held back for the flush timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineFraming {
+    pub max_line: usize,
+    pub flush_timeout: Duration,
+}
+
+impl Default for LineFraming {
+    fn default() -> Self {
+        Self {
+            max_line: DEFAULT_MAX_LINE,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+        }
+    }
+}
+
+/// Turns the chunks read from one output stream into the chunks that are published.
+/// Without line framing every chunk is published as it was read.
+#[derive(Debug)]
+pub struct Framer {
+    framing: Option<LineFraming>,
+
+    /// The partial line held back along with when it has to be published at the latest.
+    partial: Vec<u8>,
+    flush_at: Option<Instant>,
+}
+
+impl Framer {
+    pub fn new(framing: Option<LineFraming>) -> Self {
+        Self {
+            framing,
+            partial: Vec::new(),
+            flush_at: None,
+        }
+    }
+
+    /// Feed a chunk that was read and get back what is ready to be published.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let framing = match self.framing {
+            Some(framing) => framing,
+            None => return vec![bytes.to_vec()],
+        };
+
+        // The maximum line length can't be smaller than a character.
+        let max_line = framing.max_line.max(4);
+        let mut chunks = Vec::new();
+
+        for &byte in bytes {
+            self.partial.push(byte);
+
+            if byte == b'\n' {
+                chunks.push(std::mem::take(&mut self.partial));
+            } else if self.partial.len() >= max_line {
+                let cut = char_boundary(&self.partial);
+                let rest = self.partial.split_off(cut);
+                chunks.push(std::mem::replace(&mut self.partial, rest));
+            }
+        }
+
+        self.flush_at = match (self.partial.is_empty(), self.flush_at) {
+            (true, _) => None,
+            (false, Some(at)) => Some(at),
+            (false, None) => Some(Instant::now() + framing.flush_timeout),
+        };
+
+        chunks
+    }
+
+    /// When the partial line has to be published, should there be one.
+    pub fn flush_at(&self) -> Option<Instant> {
+        self.flush_at
+    }
+
+    /// Give up on waiting for the rest of the partial line, should there be one.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        self.flush_at = None;
+
+        if self.partial.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.partial))
+        }
+    }
+}
+
+/// Where a full line is cut so that a UTF-8 character that doesn't fit is moved to the next line in one piece.
+/// Lines that aren't UTF-8 where they end are cut at the very end.
+fn char_boundary(line: &[u8]) -> usize {
+    let is_continuation = |byte: u8| byte & 0xc0 == 0x80;
+    let start = line
+        .iter()
+        .rposition(|&byte| !is_continuation(byte))
+        .unwrap_or(0);
+
+    let width = match line[start] {
+        byte if byte < 0x80 => 1,
+        byte if byte >> 5 == 0b110 => 2,
+        byte if byte >> 4 == 0b1110 => 3,
+        byte if byte >> 3 == 0b11110 => 4,
+        _ => return line.len(),
+    };
+
+    if start > 0 && line.len() - start < width {
+        start
+    } else {
+        line.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Framer, LineFraming};
+    use std::time::Duration;
+
+    #[test]
+    fn frame_lines() {
+        let mut framer = Framer::new(Some(LineFraming {
+            max_line: 8,
+            flush_timeout: Duration::from_secs(1),
+        }));
+
+        assert_eq!(framer.push(b"a\nb"), vec![b"a\n".to_vec()]);
+        assert!(framer.flush_at().is_some());
+        assert_eq!(framer.push(b"c\n"), vec![b"bc\n".to_vec()]);
+        assert!(framer.flush_at().is_none());
+
+        // The character that would straddle the cut is moved to the next line whole.
+        let line = "abcdefgé!\n".as_bytes();
+        assert_eq!(
+            framer.push(line),
+            vec![b"abcdefg".to_vec(), "é!\n".as_bytes().to_vec()]
+        );
+
+        assert_eq!(framer.push(b"tail"), Vec::<Vec<u8>>::new());
+        assert_eq!(framer.flush(), Some(b"tail".to_vec()));
+        assert_eq!(framer.flush(), None);
+
+        let mut raw = Framer::new(None);
+        assert_eq!(raw.push(b"a\nb"), vec![b"a\nb".to_vec()]);
+        assert_eq!(raw.flush(), None);
+    }
+}
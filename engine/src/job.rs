@@ -0,0 +1,77 @@
+use crate::output::{ExitStatus, LogStorage, Output};
+use crate::remote::Remote;
+use crate::usage::ResourceUsage;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Everything the engine keeps track of for a single job.
+#[derive(Debug)]
+pub struct Job {
+    /// The position of the job in spawn order. This is used as a stable cursor when listing jobs.
+    pub index: u64,
+
+    pub uuid: Uuid,
+    pub program: String,
+    pub args: Vec<String>,
+    pub started_at: SystemTime,
+    /// The remote monitoring the job. Jobs restored from a store after they terminated don't have one.
+    pub remote: Option<Mutex<Remote>>,
+    pub output: Arc<Mutex<Output>>,
+}
+
+impl Job {
+    /// Take a snapshot of the job and its current lifecycle state.
+    pub fn summary(&self) -> JobSummary {
+        let output = self.output.lock().unwrap();
+
+        JobSummary {
+            uuid: self.uuid,
+            program: self.program.clone(),
+            args: self.args.clone(),
+            started_at: self.started_at,
+            exit: output.exit(),
+            timed_out: output.timed_out(),
+            usage: output.usage(),
+            storage: output.storage(),
+        }
+    }
+}
+
+/// The lifecycle state of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Terminated,
+}
+
+/// A snapshot of a job and its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSummary {
+    pub uuid: Uuid,
+    pub program: String,
+    pub args: Vec<String>,
+    pub started_at: SystemTime,
+
+    /// How and when the job terminated, should it have done so.
+    pub exit: Option<(ExitStatus, SystemTime)>,
+
+    /// Whether the job was stopped for running past its deadline.
+    pub timed_out: bool,
+
+    /// The resources the job has used as of the latest sample, or altogether once it has terminated.
+    pub usage: Option<ResourceUsage>,
+
+    /// How much disk space the spilled output of the job takes up.
+    pub storage: LogStorage,
+}
+
+impl JobSummary {
+    pub fn state(&self) -> JobState {
+        if self.exit.is_some() {
+            JobState::Terminated
+        } else {
+            JobState::Running
+        }
+    }
+}
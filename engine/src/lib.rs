@@ -1,37 +1,315 @@
+mod cgroup;
+mod credentials;
+mod follow;
+mod framing;
+mod job;
+mod namespace;
 mod output;
+mod pidfd;
+mod profile;
+mod pty;
 mod remote;
+mod report;
+mod rlimit;
+mod search;
+mod segment;
+mod store;
+mod subscriber;
+mod syscalls;
+mod tree;
+mod usage;
 
-pub use output::OutputEvent;
+pub use cgroup::{CpuMax, IoMax, ResourceLimits};
+pub use credentials::Credentials;
+pub use framing::LineFraming;
+pub use job::{JobState, JobSummary};
+pub use namespace::Isolation;
+pub use output::{
+    ExitStatus, LogEvent, LogRange, LogRead, LogStorage, LogStreams, OutputEvent, RetentionPolicy,
+    TailFrom,
+};
+pub use profile::{Capability, SeccompAction, SeccompFilter, SecurityProfile, Syscall};
+pub use remote::{Deadline, Stdin, StopPolicy};
+pub use report::ErrorReporter;
+pub use rlimit::Rlimit;
+pub use search::LogSearch;
+pub use store::{FileStore, JobRecord, LogFiles, OutputFiles, Store, StoredJob};
+pub use subscriber::{BackpressurePolicy, Disconnected, Overflow, Subscriber};
+pub use usage::ResourceUsage;
 
 use anyhow::{anyhow, Result};
+use cgroup::Cgroup;
+use job::Job;
 use output::Output;
-use remote::Remote;
+use remote::{Remote, Sandbox};
 use std::{
+    cmp::Reverse,
     collections::HashMap,
+    fs::File,
+    future::Future,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
-use tokio::sync::mpsc::UnboundedReceiver;
 use uuid::Uuid;
 
+/// Configuration that applies to every job run by an engine.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub retention: RetentionPolicy,
+    pub backpressure: BackpressurePolicy,
+
+    /// Where jobs are recorded so that they outlive the engine. Jobs are only kept in memory if unset.
+    pub store: Option<Arc<dyn Store>>,
+
+    /// What happens to jobs in the store that are still running when the engine is created.
+    pub orphans: OrphanPolicy,
+
+    /// When terminated jobs are removed by garbage collection.
+    pub gc: GcPolicy,
+
+    /// The unix user the jobs of each user run as. Users that aren't mapped to a unix user can't spawn jobs.
+    /// Jobs run as the user of the engine if unset.
+    pub users: Option<HashMap<String, Credentials>>,
+
+    /// The security profiles jobs can be spawned with by name.
+    pub profiles: HashMap<String, SecurityProfile>,
+
+    /// The highest limits jobs may request for each resource. Requests past a ceiling are rejected, and resources
+    /// that aren't requested keep the limits of the engine.
+    pub rlimit_ceilings: HashMap<Rlimit, u64>,
+
+    /// Where errors that happen in the background are reported, like failing to record output in the store.
+    pub errors: ErrorReporter,
+}
+
+/// Decides which terminated jobs are removed when collecting garbage. Jobs are kept forever by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Remove jobs once they have been terminated for this long.
+    pub ttl: Option<Duration>,
+
+    /// Only keep this many of the most recently terminated jobs of each user.
+    pub max_finished_per_user: Option<usize>,
+}
+
+impl GcPolicy {
+    /// Returns true if no jobs are ever removed by garbage collection.
+    pub fn is_disabled(&self) -> bool {
+        self.ttl.is_none() && self.max_finished_per_user.is_none()
+    }
+}
+
+/// What happens to jobs that are still running after the engine that spawned them went away without
+/// stopping them, like when the server crashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrphanPolicy {
+    /// Resume monitoring jobs that write their output to files. Jobs that can't be reattached to are killed.
+    #[default]
+    Reattach,
+
+    /// Kill every orphaned job.
+    Kill,
+}
+
+/// Options controlling how a job is spawned.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    pub limits: ResourceLimits,
+
+    /// Give the job a stdin that clients can write to. Otherwise stdin reads from `/dev/null`.
+    pub open_stdin: bool,
+
+    /// Run the job in a pseudo-terminal which serves as its stdin, stdout and stderr.
+    /// Its output is published as stdout and it always has a stdin that clients can write to.
+    pub tty: bool,
+
+    /// Stop the job once it has run for this long.
+    pub timeout: Option<Duration>,
+
+    /// Stop the job should it still be running at this point in time. The earlier of this and the timeout applies.
+    pub deadline: Option<SystemTime>,
+
+    /// How the job is stopped once it runs past its timeout or deadline.
+    pub timeout_policy: StopPolicy,
+
+    /// The namespaces the job is isolated in.
+    pub isolation: Isolation,
+
+    /// The name of the security profile the job is restricted by. Jobs are only restricted by the user they run as if unset.
+    pub profile: Option<String>,
+
+    /// Per-process resource limits, which apply to both the soft and the hard limit.
+    pub rlimits: HashMap<Rlimit, u64>,
+
+    /// Publish output as whole lines. Output is published however it happened to be read if unset,
+    /// which suits binary output.
+    pub line_framing: Option<LineFraming>,
+}
+
+impl SpawnOptions {
+    /// The deadline of a job started at the given time, should it have a timeout or a deadline.
+    fn deadline(&self, started_at: SystemTime) -> Option<Deadline> {
+        let timeout = self.timeout.map(|timeout| started_at + timeout);
+        let at = match (timeout, self.deadline) {
+            (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+            (timeout, deadline) => timeout.or(deadline),
+        };
+
+        at.map(|at| Deadline {
+            at,
+            policy: self.timeout_policy,
+        })
+    }
+}
+
 /// An engine represents an abstraction on top of the OS
 /// that allows you to run jobs associated with a username and a unique id
 /// while capturing and streaming output.
 #[derive(Debug, Default)]
 pub struct Engine {
-    remotes: HashMap<UniqueJobId, Mutex<Remote>>,
-    outputs: HashMap<UniqueJobId, Arc<Mutex<Output>>>,
+    jobs: HashMap<UniqueJobId, Job>,
+
+    /// The index the next spawned job is assigned.
+    next_index: u64,
+
+    config: EngineConfig,
 }
 
 impl Engine {
     pub fn new() -> Engine {
-        Self {
-            remotes: HashMap::new(),
-            outputs: HashMap::new(),
+        Self::default()
+    }
+
+    /// Create an engine with the given configuration, restoring the jobs of the previous engine should there be a store.
+    /// Jobs that outlived the previous engine are reattached to or killed according to the orphan policy.
+    pub fn with_config(config: EngineConfig) -> Result<Engine> {
+        let mut engine = Self {
+            jobs: HashMap::new(),
+            next_index: 0,
+            config,
+        };
+
+        if let Some(store) = engine.config.store.clone() {
+            for job in store.load()? {
+                match job {
+                    Ok(job) => engine.restore(&store, job)?,
+                    Err(error) => engine.config.errors.report(error),
+                }
+            }
+        }
+
+        Ok(engine)
+    }
+
+    /// Bring back a job from the store, whose past events are read back from its log as they're needed.
+    /// A job whose exit was never recorded has outlived the previous engine and is either reattached to,
+    /// in which case how it exits is unknown, or killed and marked as lost.
+    fn restore(&mut self, store: &Arc<dyn Store>, job: StoredJob) -> Result<()> {
+        let StoredJob { record, log, usage } = job;
+
+        // The usage is taken note of before the job is recorded again since it's already in the store.
+        let mut output = Output::new(self.config.retention.clone(), self.config.backpressure);
+        output.report_errors_to(self.config.errors.clone());
+
+        if let Some(usage) = usage {
+            output.record_usage(usage);
+        }
+
+        output.record_to(Arc::clone(store), record.uuid, log)?;
+
+        let exited = output.exit().is_some();
+        let offsets = output.offsets();
+        let output = Arc::new(Mutex::new(output));
+        let remote = if exited {
+            None
+        } else {
+            self.reattach(&record, offsets, &output)?
+        };
+
+        let job = Job {
+            index: record.index,
+            uuid: record.uuid,
+            program: record.program,
+            args: record.args,
+            started_at: record.started_at,
+            remote: remote.map(Mutex::new),
+            output,
+        };
+
+        self.next_index = self.next_index.max(record.index + 1);
+        self.jobs
+            .insert(UniqueJobId::new(record.user, record.uuid), job);
+        Ok(())
+    }
+
+    /// Resume monitoring a job that outlived the previous engine according to the orphan policy.
+    /// Should that not be possible, the job is killed if it's still running and marked as lost.
+    fn reattach(
+        &self,
+        record: &JobRecord,
+        offsets: (u64, u64),
+        output: &Arc<Mutex<Output>>,
+    ) -> Result<Option<Remote>> {
+        let remote = match (self.config.orphans, &record.output_files) {
+            (OrphanPolicy::Reattach, Some(files)) => {
+                let cgroup = Cgroup::open(&record.uuid.to_string());
+                Remote::reattach(
+                    record.pid,
+                    record.start_time,
+                    files,
+                    offsets,
+                    cgroup,
+                    record.line_framing,
+                )
+                .ok()
+            }
+            _ => None,
+        };
+
+        match remote {
+            Some(mut remote) => {
+                remote.spawn_events_processor(Arc::clone(output), record.deadline)?;
+                Ok(Some(remote))
+            }
+            None => {
+                // The pid may have been reused by an unrelated process if the start time doesn't match.
+                if tree::start_time(record.pid) == Some(record.start_time) {
+                    tree::signal(record.pid, libc::SIGKILL);
+                }
+
+                let mut output = output.lock().unwrap();
+
+                // Whatever the job wrote to its output files after what made it into its log is published still.
+                if let Some(files) = &record.output_files {
+                    if let Some(bytes) = read_from(&files.stdout, offsets.0) {
+                        output.publish(OutputEvent::Stdout(bytes));
+                    }
+
+                    if let Some(bytes) = read_from(&files.stderr, offsets.1) {
+                        output.publish(OutputEvent::Stderr(bytes));
+                    }
+                }
+
+                output.publish(OutputEvent::Exit(ExitStatus::Lost));
+
+                if let Some(cgroup) = Cgroup::open(&record.uuid.to_string()) {
+                    let errors = self.config.errors.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = cgroup.remove().await {
+                            errors.report(error.context("failed to remove job cgroup"));
+                        }
+                    });
+                }
+
+                Ok(None)
+            }
         }
     }
 
     /// Spawn a new job associated with a certain username using the given
-    /// username, program path, working directory, arguments and environment variables.
+    /// username, program path, working directory, arguments, environment variables and spawn options.
     pub fn spawn(
         &mut self,
         username: String,
@@ -39,60 +317,341 @@ impl Engine {
         working_directory: &str,
         args: &[String],
         envs: &HashMap<String, String>,
+        options: &SpawnOptions,
     ) -> Result<Uuid> {
+        options.timeout_policy.validate()?;
+
+        let credentials = match &self.config.users {
+            Some(users) => Some(
+                users
+                    .get(&username)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("user is not mapped to a unix user"))?,
+            ),
+            None => None,
+        };
+
+        let rlimits = rlimit::resolve(&options.rlimits, &self.config.rlimit_ceilings)?;
+
+        let profile = match &options.profile {
+            Some(name) => Some(
+                self.config
+                    .profiles
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("unknown security profile {}", name))?,
+            ),
+            None => None,
+        };
+
         // Create a new job id based on a random UUID and the supplied username.
         let uuid = Uuid::new_v4();
         let id = UniqueJobId::new(username, uuid);
 
-        // Create the remote for the job and attach an output to it.
-        let mut remote = Remote::new(program, working_directory, args, envs)?;
-        let output = Arc::new(Mutex::new(Output::new()));
-        remote.spawn_events_processor(Arc::clone(&output))?;
+        // Jobs with resource limits get a cgroup of their own named after the job.
+        let cgroup = if options.limits.is_empty() {
+            None
+        } else {
+            Some(Cgroup::create(&uuid.to_string(), &options.limits)?)
+        };
+
+        // Whatever the store keeps for the job is created along with its output files, so it's removed again should
+        // the job fail to start from there on.
+        let start = || -> Result<Job> {
+            // Jobs write their output to files kept by the store so that it can still be read should the engine go away.
+            // Output written to a terminal can't be redirected so jobs with one can't be reattached to. Nor can jobs
+            // whose output is read under the block policy, since only pipes that fill up hold back the job writing
+            // to them.
+            let output_files = match &self.config.store {
+                Some(store)
+                    if !options.tty && self.config.backpressure.overflow != Overflow::Block =>
+                {
+                    store.output_files(uuid)?
+                }
+                _ => None,
+            };
+
+            // Create the remote for the job and attach an output to it.
+            let mut remote = Remote::new(
+                program,
+                working_directory,
+                args,
+                envs,
+                Sandbox {
+                    cgroup,
+                    credentials,
+                    profile,
+                    rlimits,
+                },
+                options,
+                output_files.as_ref(),
+            )?;
+            let started_at = SystemTime::now();
+            let deadline = options.deadline(started_at);
+            let mut output = Output::new(self.config.retention.clone(), self.config.backpressure);
+            output.report_errors_to(self.config.errors.clone());
+
+            // Record the job before it gets to publish anything so that none of its events go missing.
+            if let Some(store) = &self.config.store {
+                let log = store.insert(&JobRecord {
+                    user: id.user.clone(),
+                    uuid,
+                    index: self.next_index,
+                    program: program.into(),
+                    args: args.into(),
+                    started_at,
+                    pid: remote.pid(),
+                    start_time: tree::start_time(remote.pid()).unwrap_or_default(),
+                    output_files,
+                    deadline,
+                    line_framing: options.line_framing,
+                })?;
+
+                output.record_to(Arc::clone(store), uuid, log)?;
+            }
+
+            let output = Arc::new(Mutex::new(output));
+            remote.spawn_events_processor(Arc::clone(&output), deadline)?;
+
+            Ok(Job {
+                index: self.next_index,
+                uuid,
+                program: program.into(),
+                args: args.into(),
+                started_at,
+                remote: Some(Mutex::new(remote)),
+                output,
+            })
+        };
 
-        self.remotes.insert(id.clone(), Mutex::new(remote));
-        self.outputs.insert(id, output);
+        let job = match start() {
+            Ok(job) => job,
+            Err(error) => {
+                if let Some(store) = &self.config.store {
+                    if let Err(error) = store.remove(uuid) {
+                        self.config
+                            .errors
+                            .report(error.context("failed to remove job from the store"));
+                    }
+                }
+
+                return Err(error);
+            }
+        };
+
+        self.next_index += 1;
+        self.jobs.insert(id, job);
         Ok(uuid)
     }
 
-    /// Stop the specified job. If the job has already terminated, nothing will be done.
-    pub fn stop(&self, id: &UniqueJobId) -> Result<()> {
-        let mut remote = self
-            .remotes
-            .get(id)
-            .ok_or_else(|| anyhow!("job does not exist"))?
-            .lock()
-            .unwrap();
+    /// Stop the specified job according to the stop policy. If the job has already terminated, nothing will be done.
+    /// A job that is already being stopped for running past its deadline is escalated on whichever schedule comes first.
+    pub fn stop(&self, id: &UniqueJobId, policy: StopPolicy) -> Result<()> {
+        if let Some(remote) = &self.job(id)?.remote {
+            remote.lock().unwrap().stop(policy)?;
+        }
 
-        remote.stop()?;
         Ok(())
     }
 
-    /// Creates an event stream that receives all future output events from a job and optionally those of the past.
-    pub fn tail_log(
+    /// Creates an event stream that receives all future output events from a job and past events
+    /// according to where it starts, like right after the last event a previous stream received.
+    /// Past events are read as they're received, so the stream doesn't borrow the engine.
+    pub fn tail_log(&self, id: &UniqueJobId, from: TailFrom) -> Result<Subscriber> {
+        Output::tail(&self.job(id)?.output, from)
+    }
+
+    /// Get a handle to the stdin of a job for feeding it input.
+    /// This fails if the job wasn't spawned with stdin open or with a terminal.
+    pub fn stdin(&self, id: &UniqueJobId) -> Result<Stdin> {
+        let remote = self
+            .job(id)?
+            .remote
+            .as_ref()
+            .ok_or_else(|| anyhow!("job has already terminated"))?;
+
+        remote
+            .lock()
+            .unwrap()
+            .stdin()
+            .ok_or_else(|| anyhow!("job was not spawned with stdin open"))
+    }
+
+    /// Creates a future that resolves with the exit status of a job once it has terminated.
+    /// The future doesn't borrow the engine so it can be awaited without blocking other operations.
+    pub fn wait(&self, id: &UniqueJobId) -> Result<impl Future<Output = Result<ExitStatus>>> {
+        let exit = self.job(id)?.output.lock().unwrap().wait();
+        Ok(async move {
+            exit.await
+                .map_err(|_| anyhow!("job went away before it terminated"))
+        })
+    }
+
+    /// Collect every retained past event of a job, preceded by a truncation event
+    /// should any output have been discarded according to the retention policy.
+    pub fn get_past_events(&self, id: &UniqueJobId) -> Result<Vec<LogEvent>> {
+        let output = self.job(id)?.output.lock().unwrap();
+        output.get_events()
+    }
+
+    /// Creates a future that reads part of the retained output of a job without subscribing to it. Like searching,
+    /// the output is snapshotted right away and read on a blocking thread.
+    pub fn read_log(
         &self,
         id: &UniqueJobId,
-        from_start: bool,
-    ) -> Result<UnboundedReceiver<OutputEvent>> {
-        let mut output = self
-            .outputs
-            .get(id)
-            .ok_or_else(|| anyhow!("job does not exist"))?
-            .lock()
-            .unwrap();
+        streams: LogStreams,
+        range: LogRange,
+    ) -> Result<impl Future<Output = Result<LogRead>>> {
+        let snapshot = self.job(id)?.output.lock().unwrap().snapshot();
+        Ok(async move { tokio::task::spawn_blocking(move || snapshot.read(streams, range)).await? })
+    }
 
-        Ok(output.tail(from_start))
+    /// Creates a future that searches the retained output of a job line by line. The output is snapshotted right away
+    /// and searched on a blocking thread, so the future neither borrows the engine nor holds the lock on the output.
+    pub fn search_log(
+        &self,
+        id: &UniqueJobId,
+        search: LogSearch,
+    ) -> Result<impl Future<Output = Result<Vec<LogEvent>>>> {
+        let snapshot = self.job(id)?.output.lock().unwrap().snapshot();
+        Ok(async move { tokio::task::spawn_blocking(move || snapshot.search(&search)).await? })
     }
 
-    pub fn get_past_events(&self, id: &UniqueJobId) -> Result<Vec<OutputEvent>> {
-        let mut output = self
-            .outputs
-            .get(id)
-            .ok_or_else(|| anyhow!("job does not exist"))?
-            .lock()
-            .unwrap();
+    /// Remove a job along with its output, from the store too should there be one.
+    /// Running jobs are only removed if forced, in which case they're killed.
+    pub fn remove(&mut self, id: &UniqueJobId, force: bool) -> Result<()> {
+        let running = self.job(id)?.output.lock().unwrap().exit().is_none();
+
+        if running && !force {
+            return Err(anyhow!("job is still running"));
+        }
 
-        Ok(output.get_events())
+        let job = self.jobs.remove(id).unwrap();
+        self.forget(job)
     }
+
+    /// Remove the terminated jobs that are due according to the garbage collection policy
+    /// and return how many were removed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let policy = self.config.gc;
+        let now = SystemTime::now();
+        let mut terminated: HashMap<&str, Vec<(SystemTime, &UniqueJobId)>> = HashMap::new();
+
+        for (id, job) in &self.jobs {
+            if let Some((_, at)) = job.output.lock().unwrap().exit() {
+                terminated.entry(&id.user).or_default().push((at, id));
+            }
+        }
+
+        let mut expired = Vec::new();
+
+        for (_, mut jobs) in terminated {
+            // Most recently terminated first so that the jobs beyond the limit are the oldest ones.
+            jobs.sort_by_key(|&(at, _)| Reverse(at));
+
+            for (position, (at, id)) in jobs.into_iter().enumerate() {
+                let too_old = policy.ttl.map_or(false, |ttl| {
+                    now.duration_since(at).map_or(false, |age| age >= ttl)
+                });
+                let too_many = policy
+                    .max_finished_per_user
+                    .map_or(false, |max| position >= max);
+
+                if too_old || too_many {
+                    expired.push(id.clone());
+                }
+            }
+        }
+
+        for id in &expired {
+            let job = self.jobs.remove(id).unwrap();
+
+            if let Err(error) = self.forget(job) {
+                self.config
+                    .errors
+                    .report(error.context("failed to remove job from the store"));
+            }
+        }
+
+        expired.len()
+    }
+
+    /// Drop a job that has been taken out of the engine, which kills it should it still be running.
+    fn forget(&self, job: Job) -> Result<()> {
+        let uuid = job.uuid;
+
+        // Whatever the job publishes while it's being killed shouldn't end up in the store after it's been removed.
+        job.output.lock().unwrap().stop_recording();
+        drop(job);
+
+        if let Some(store) = &self.config.store {
+            store.remove(uuid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take a snapshot of a job and its current lifecycle state.
+    pub fn summary(&self, id: &UniqueJobId) -> Result<JobSummary> {
+        Ok(self.job(id)?.summary())
+    }
+
+    /// List the jobs of a user in the order they were spawned, optionally only those in a certain state.
+    ///
+    /// Listing starts after the job at `cursor` and returns at most `limit` jobs
+    /// along with the cursor to continue from if there are more jobs left to list.
+    pub fn list(
+        &self,
+        user: &str,
+        state: Option<JobState>,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> (Vec<JobSummary>, Option<u64>) {
+        let mut jobs: Vec<_> = self
+            .jobs
+            .iter()
+            .filter(|(id, job)| id.user == user && cursor.map_or(true, |cursor| job.index > cursor))
+            .map(|(_, job)| job)
+            .collect();
+
+        jobs.sort_by_key(|job| job.index);
+
+        let mut summaries = Vec::new();
+        let mut last_index = None;
+
+        for job in jobs {
+            let summary = job.summary();
+
+            if state.map_or(false, |state| summary.state() != state) {
+                continue;
+            }
+
+            // There are more jobs left so hand out a cursor pointing at the last job listed.
+            if summaries.len() == limit {
+                return (summaries, last_index);
+            }
+
+            last_index = Some(job.index);
+            summaries.push(summary);
+        }
+
+        (summaries, None)
+    }
+
+    fn job(&self, id: &UniqueJobId) -> Result<&Job> {
+        self.jobs
+            .get(id)
+            .ok_or_else(|| anyhow!("job does not exist"))
+    }
+}
+
+/// Read whatever was written to a file past an offset, should there be anything.
+fn read_from(path: &Path, offset: u64) -> Option<Vec<u8>> {
+    let mut file = File::open(path).ok()?;
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.read_to_end(&mut bytes).ok()?;
+    Some(bytes).filter(|bytes| !bytes.is_empty())
 }
 
 /// Represents a job associated with a username.
@@ -107,3 +666,700 @@ impl UniqueJobId {
         Self { user, job }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BackpressurePolicy, Credentials, Engine, EngineConfig, ExitStatus, FileStore, GcPolicy,
+        Isolation, JobRecord, LineFraming, OrphanPolicy, Output, OutputEvent, Overflow,
+        RetentionPolicy, Rlimit, SeccompAction, SeccompFilter, SecurityProfile, SpawnOptions,
+        StopPolicy, Store, Syscall, TailFrom, UniqueJobId,
+    };
+    use anyhow::Result;
+    use std::collections::HashMap;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::path::Path;
+    use std::process::{Child, Command};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+    use uuid::Uuid;
+
+    /// Spawn a shell command writing its output to the output files of a job recorded in the store at the
+    /// given directory, as if it had been spawned by an engine that went away without stopping it.
+    fn spawn_orphan(directory: &Path, uuid: Uuid, script: &str, stored: &[OutputEvent]) -> Child {
+        let store = FileStore::open(directory.to_path_buf()).unwrap();
+        let files = store.output_files(uuid).unwrap().unwrap();
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .unwrap()
+        };
+        let stdout = || open(&files.stdout);
+
+        // Output published before the engine went away has already been written to the file.
+        for event in stored {
+            if let OutputEvent::Stdout(bytes) = event {
+                stdout().write_all(bytes).unwrap();
+            }
+        }
+
+        // Jobs lead a process group of their own.
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg(script)
+            .stdout(stdout())
+            .stderr(open(&files.stderr));
+
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+
+        let child = command.spawn().unwrap();
+
+        let pid = child.id() as libc::pid_t;
+        let log = store
+            .insert(&JobRecord {
+                user: "user".into(),
+                uuid,
+                index: 0,
+                program: "/bin/sh".into(),
+                args: vec!["-c".into(), script.into()],
+                started_at: SystemTime::now(),
+                pid,
+                start_time: super::tree::start_time(pid).unwrap(),
+                output_files: Some(files),
+                deadline: None,
+                line_framing: None,
+            })
+            .unwrap();
+
+        // The events are recorded outside of the runtime so that they're written by the time this returns.
+        let stored = stored.to_vec();
+        std::thread::spawn(move || {
+            let mut output = Output::new(RetentionPolicy::default(), BackpressurePolicy::default());
+            output.record_to(Arc::new(store), uuid, log).unwrap();
+
+            for event in stored {
+                output.publish(event);
+            }
+        })
+        .join()
+        .unwrap();
+
+        child
+    }
+
+    #[tokio::test]
+    async fn restore_terminated_jobs() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let config = || EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            ..Default::default()
+        };
+
+        let mut engine = Engine::with_config(config()).unwrap();
+        let id = spawn(&mut engine, "user", "/bin/echo", &["hi"]);
+        engine.wait(&id).unwrap().await.unwrap();
+        let summary = engine.summary(&id).unwrap();
+        drop(engine);
+
+        // Once the job has exited its output is only kept in its log, which is what the summary reports.
+        let job = directory.join(id.job.to_string());
+        let on_disk = std::fs::metadata(job.join("events")).unwrap().len();
+        let output_files = job.join("stdout").exists() || job.join("stderr").exists();
+
+        let engine = Engine::with_config(config()).unwrap();
+        let events = engine.get_past_events(&id).unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(engine.summary(&id).unwrap(), summary);
+        assert_eq!(summary.storage.compressed_bytes, on_disk);
+        assert!(summary.storage.raw_bytes > 0);
+        assert!(!output_files);
+        assert_eq!(
+            events
+                .into_iter()
+                .map(|event| event.event)
+                .collect::<Vec<_>>(),
+            vec![
+                OutputEvent::Stdout(b"hi\n".to_vec()),
+                OutputEvent::Exit(ExitStatus::Code(0)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reattach_orphaned_jobs() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let uuid = Uuid::new_v4();
+
+        // The job only writes more output once the engine has reattached to it.
+        let release = directory.join("release");
+        let script = format!(
+            "while [ ! -e {} ]; do sleep 0.01; done; echo bye",
+            release.display()
+        );
+        let mut child = spawn_orphan(
+            &directory,
+            uuid,
+            &script,
+            &[OutputEvent::Stdout(b"hi\n".to_vec())],
+        );
+
+        let engine = Engine::with_config(EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            ..Default::default()
+        })
+        .unwrap();
+
+        std::fs::write(&release, b"").unwrap();
+        let id = UniqueJobId::new("user".into(), uuid);
+        let status = engine.wait(&id).unwrap().await.unwrap();
+        let events = engine.get_past_events(&id).unwrap();
+        child.wait().unwrap();
+        drop(engine);
+
+        // Output written while the engine was away is picked up where the stored events left off.
+        let stdout: Vec<u8> = events
+            .iter()
+            .flat_map(|event| match &event.event {
+                OutputEvent::Stdout(bytes) => bytes.clone(),
+                _ => Vec::new(),
+            })
+            .collect();
+
+        let engine = Engine::with_config(EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            ..Default::default()
+        })
+        .unwrap();
+
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(status, ExitStatus::Unknown);
+        assert_eq!(stdout, b"hi\nbye\n");
+        assert_eq!(
+            events.last().map(|event| &event.event),
+            Some(&OutputEvent::Exit(ExitStatus::Unknown))
+        );
+        assert_eq!(engine.get_past_events(&id).unwrap(), events);
+    }
+
+    #[tokio::test]
+    async fn kill_orphaned_jobs() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let uuid = Uuid::new_v4();
+        let mut child = spawn_orphan(&directory, uuid, "sleep 30", &[]);
+
+        let engine = Engine::with_config(EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            orphans: OrphanPolicy::Kill,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let id = UniqueJobId::new("user".into(), uuid);
+        let summary = engine.summary(&id).unwrap();
+        let status = child.wait().unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert_eq!(
+            summary.exit.map(|(status, _)| status),
+            Some(ExitStatus::Lost)
+        );
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
+
+    fn spawn(engine: &mut Engine, user: &str, program: &str, args: &[&str]) -> UniqueJobId {
+        spawn_with(engine, user, program, args, &SpawnOptions::default()).unwrap()
+    }
+
+    fn spawn_with(
+        engine: &mut Engine,
+        user: &str,
+        program: &str,
+        args: &[&str],
+        options: &SpawnOptions,
+    ) -> Result<UniqueJobId> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let uuid = engine.spawn(user.into(), program, ".", &args, &HashMap::new(), options)?;
+        Ok(UniqueJobId::new(user.into(), uuid))
+    }
+
+    /// Wait for a job to publish the given event.
+    async fn wait_for(engine: &Engine, id: &UniqueJobId, event: &OutputEvent) {
+        let mut rx = engine.tail_log(id, TailFrom::Start).unwrap();
+        while rx.recv().await.unwrap().unwrap().event != *event {}
+    }
+
+    /// Every event a job has published so far.
+    fn events(engine: &Engine, id: &UniqueJobId) -> Vec<OutputEvent> {
+        engine
+            .get_past_events(id)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.event)
+            .collect()
+    }
+
+    /// Everything a job has written to stdout so far.
+    fn stdout(engine: &Engine, id: &UniqueJobId) -> String {
+        let stdout: Vec<u8> = engine
+            .get_past_events(id)
+            .unwrap()
+            .into_iter()
+            .flat_map(|event| match event.event {
+                OutputEvent::Stdout(bytes) => bytes,
+                _ => Vec::new(),
+            })
+            .collect();
+
+        String::from_utf8(stdout).unwrap()
+    }
+
+    #[tokio::test]
+    async fn remove_jobs() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = Arc::new(FileStore::open(directory.clone()).unwrap());
+        let mut engine = Engine::with_config(EngineConfig {
+            store: Some(store.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let finished = spawn(&mut engine, "user", "/bin/true", &[]);
+        let running = spawn(&mut engine, "user", "/bin/sleep", &["30"]);
+        engine.wait(&finished).unwrap().await.unwrap();
+        let wait = engine.wait(&running).unwrap();
+
+        engine.remove(&finished, false).unwrap();
+        assert!(engine.remove(&running, false).is_err());
+        engine.remove(&running, true).unwrap();
+
+        // Forcefully removing a running job kills it.
+        let status = wait.await.unwrap();
+        let stored = store.load().unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(engine.summary(&finished).is_err());
+        assert!(engine.summary(&running).is_err());
+        assert_eq!(
+            status,
+            ExitStatus::Signal {
+                signal: libc::SIGKILL,
+                core_dumped: false
+            }
+        );
+        assert!(stored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_on_pipes() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let mut engine = Engine::with_config(EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            backpressure: BackpressurePolicy {
+                overflow: Overflow::Block,
+                ..BackpressurePolicy::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Output files never fill up, so the job writes to pipes instead to be held back by slow subscribers.
+        let id = spawn(&mut engine, "user", "/bin/sleep", &["30"]);
+        let job = directory.join(id.job.to_string());
+        let output_files = job.join("stdout").exists() || job.join("stderr").exists();
+        engine.remove(&id, true).unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(!output_files);
+    }
+
+    #[tokio::test]
+    async fn clean_up_failed_spawns() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let mut engine = Engine::with_config(EngineConfig {
+            store: Some(Arc::new(FileStore::open(directory.clone()).unwrap())),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Nothing is left of a job that never started in the store.
+        let spawned = spawn_with(
+            &mut engine,
+            "user",
+            "/nonexistent",
+            &[],
+            &SpawnOptions::default(),
+        );
+        let jobs = std::fs::read_dir(&directory)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+            .count();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(spawned.is_err());
+        assert_eq!(jobs, 0);
+    }
+
+    #[tokio::test]
+    async fn collect_garbage() {
+        let mut engine = Engine::with_config(EngineConfig {
+            gc: GcPolicy {
+                ttl: None,
+                max_finished_per_user: Some(1),
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let oldest = spawn(&mut engine, "user", "/bin/true", &[]);
+        engine.wait(&oldest).unwrap().await.unwrap();
+        let newest = spawn(&mut engine, "user", "/bin/true", &[]);
+        engine.wait(&newest).unwrap().await.unwrap();
+        let other = spawn(&mut engine, "other", "/bin/true", &[]);
+        engine.wait(&other).unwrap().await.unwrap();
+        let running = spawn(&mut engine, "user", "/bin/sleep", &["30"]);
+
+        assert_eq!(engine.collect_garbage(), 1);
+        assert!(engine.summary(&oldest).is_err());
+        assert!(engine.summary(&newest).is_ok());
+        assert!(engine.summary(&other).is_ok());
+        assert!(engine.summary(&running).is_ok());
+
+        // Every terminated job has expired once the time to live is zero.
+        engine.config.gc = GcPolicy {
+            ttl: Some(Duration::from_secs(0)),
+            max_finished_per_user: None,
+        };
+
+        assert_eq!(engine.collect_garbage(), 2);
+        assert!(engine.summary(&running).is_ok());
+    }
+
+    #[tokio::test]
+    async fn stop_job_past_timeout() {
+        let mut engine = Engine::new();
+        let options = SpawnOptions {
+            timeout: Some(Duration::from_millis(100)),
+            deadline: Some(SystemTime::now() + Duration::from_secs(30)),
+            ..Default::default()
+        };
+
+        let id = spawn_with(&mut engine, "user", "/bin/sleep", &["30"], &options).unwrap();
+        let status = engine.wait(&id).unwrap().await.unwrap();
+        let stopped = ExitStatus::Signal {
+            signal: libc::SIGTERM,
+            core_dumped: false,
+        };
+
+        assert_eq!(status, stopped);
+        assert!(engine.summary(&id).unwrap().timed_out);
+        assert_eq!(
+            events(&engine, &id),
+            vec![
+                OutputEvent::TimedOut,
+                OutputEvent::Stop {
+                    signal: libc::SIGTERM,
+                    escalated: false
+                },
+                OutputEvent::Exit(stopped),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn escalate_explicit_stop_past_timeout() {
+        let mut engine = Engine::new();
+        let options = SpawnOptions {
+            timeout: Some(Duration::from_secs(1)),
+            timeout_policy: StopPolicy {
+                grace_period: Duration::from_secs(30),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // The job ignores the stop signal of the timeout and the explicit stop comes with a shorter grace period.
+        let script = "trap '' TERM; sleep 30";
+        let id = spawn_with(&mut engine, "user", "/bin/sh", &["-c", script], &options).unwrap();
+        let signalled = OutputEvent::Stop {
+            signal: libc::SIGTERM,
+            escalated: false,
+        };
+        wait_for(&engine, &id, &signalled).await;
+        engine
+            .stop(
+                &id,
+                StopPolicy {
+                    grace_period: Duration::from_millis(100),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let status = engine.wait(&id).unwrap().await.unwrap();
+        let killed = ExitStatus::Signal {
+            signal: libc::SIGKILL,
+            core_dumped: false,
+        };
+
+        assert_eq!(status, killed);
+        assert_eq!(
+            events(&engine, &id),
+            vec![
+                OutputEvent::TimedOut,
+                signalled,
+                OutputEvent::Stop {
+                    signal: libc::SIGKILL,
+                    escalated: true
+                },
+                OutputEvent::Exit(killed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_as_mapped_user() {
+        let credentials = Credentials {
+            uid: 65534,
+            gid: 65534,
+            groups: vec![65533],
+        };
+
+        let mut users = HashMap::new();
+        users.insert("user".to_string(), credentials);
+
+        let mut engine = Engine::with_config(EngineConfig {
+            users: Some(users),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let id = spawn(
+            &mut engine,
+            "user",
+            "/bin/sh",
+            &["-c", "id -u; id -g; id -G"],
+        );
+        engine.wait(&id).unwrap().await.unwrap();
+        assert_eq!(stdout(&engine, &id), "65534\n65534\n65534 65533\n");
+
+        // Users without a mapping don't get to spawn jobs at all.
+        assert!(spawn_with(
+            &mut engine,
+            "other",
+            "/bin/true",
+            &[],
+            &SpawnOptions::default()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn isolate_in_namespaces() {
+        let mut engine = Engine::new();
+        let isolation = Isolation {
+            pid: true,
+            mount: true,
+            uts: true,
+            ipc: true,
+            network: true,
+            root: Some("/".into()),
+        };
+
+        let options = SpawnOptions {
+            isolation: isolation.clone(),
+            ..Default::default()
+        };
+
+        // The job is the first process after the init of its pid namespace, its network only
+        // has a loopback interface and its root filesystem is read-only.
+        let script = "echo $$; tail -n +3 /proc/net/dev | cut -d: -f1; touch /isolated 2>/dev/null || echo read-only";
+        let id = spawn_with(&mut engine, "user", "/bin/sh", &["-c", script], &options).unwrap();
+        engine.wait(&id).unwrap().await.unwrap();
+        assert_eq!(stdout(&engine, &id).replace(' ', ""), "2\nlo\nread-only\n");
+
+        // Stop signals still reach the job and how it terminated still reaches us.
+        let options = SpawnOptions {
+            isolation,
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+
+        let id = spawn_with(&mut engine, "user", "/bin/sleep", &["30"], &options).unwrap();
+        let status = engine.wait(&id).unwrap().await.unwrap();
+        assert_eq!(
+            status,
+            ExitStatus::Signal {
+                signal: libc::SIGTERM,
+                core_dumped: false
+            }
+        );
+
+        // Failing to exec the job is reported as such rather than as a job that exited.
+        let options = SpawnOptions {
+            isolation: Isolation {
+                pid: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let error = spawn_with(&mut engine, "user", "/nonexistent", &[], &options).unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<std::io::Error>()
+                .map(|error| error.kind()),
+            Some(std::io::ErrorKind::NotFound)
+        );
+
+        // A root filesystem can't be used without a mount namespace.
+        let options = SpawnOptions {
+            isolation: Isolation {
+                root: Some("/".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(spawn_with(&mut engine, "user", "/bin/true", &[], &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn restrict_by_profile() {
+        let profile = SecurityProfile {
+            capabilities: Some(vec!["CAP_KILL".parse().unwrap()]),
+            seccomp: Some(SeccompFilter {
+                syscalls: vec![Syscall(libc::SYS_uname)],
+                action: SeccompAction::Errno,
+                default_action: SeccompAction::Allow,
+            }),
+        };
+
+        let mut profiles = HashMap::new();
+        profiles.insert("restricted".to_string(), profile);
+
+        let mut engine = Engine::with_config(EngineConfig {
+            profiles,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let options = SpawnOptions {
+            profile: Some("restricted".into()),
+            ..Default::default()
+        };
+
+        let script = "grep CapBnd /proc/self/status; uname 2>/dev/null || echo denied";
+        let id = spawn_with(&mut engine, "user", "/bin/sh", &["-c", script], &options).unwrap();
+        engine.wait(&id).unwrap().await.unwrap();
+        assert_eq!(stdout(&engine, &id), "CapBnd:\t0000000000000020\ndenied\n");
+
+        // Jobs can't pick a profile that doesn't exist.
+        let options = SpawnOptions {
+            profile: Some("unknown".into()),
+            ..Default::default()
+        };
+
+        assert!(spawn_with(&mut engine, "user", "/bin/true", &[], &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn apply_rlimits() {
+        let mut ceilings = HashMap::new();
+        ceilings.insert(Rlimit::Nofile, 128);
+
+        let mut engine = Engine::with_config(EngineConfig {
+            rlimit_ceilings: ceilings,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut spawn_with_rlimits = |rlimits: &[(Rlimit, u64)]| {
+            let options = SpawnOptions {
+                rlimits: rlimits.iter().copied().collect(),
+                ..Default::default()
+            };
+
+            spawn_with(
+                &mut engine,
+                "user",
+                "/bin/sh",
+                &["-c", "ulimit -n; ulimit -c"],
+                &options,
+            )
+        };
+
+        // Only the requested limits are applied, and only up to their ceiling.
+        let nofile = unsafe {
+            let mut limit: libc::rlimit = std::mem::zeroed();
+            libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+            limit.rlim_cur
+        };
+        let requested = spawn_with_rlimits(&[(Rlimit::Core, 0)]).unwrap();
+        let lowered = spawn_with_rlimits(&[(Rlimit::Nofile, 64), (Rlimit::Core, 0)]).unwrap();
+        assert!(spawn_with_rlimits(&[(Rlimit::Nofile, 256)]).is_err());
+
+        let inherited = format!("{}\n0\n", nofile);
+        for (id, expected) in &[(requested, inherited.as_str()), (lowered, "64\n0\n")] {
+            engine.wait(id).unwrap().await.unwrap();
+            assert_eq!(stdout(&engine, id), *expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn account_resource_usage() {
+        let mut engine = Engine::new();
+        let id = spawn(
+            &mut engine,
+            "user",
+            "/bin/sh",
+            &["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i + 1)); done"],
+        );
+
+        engine.wait(&id).unwrap().await.unwrap();
+        let usage = engine.summary(&id).unwrap().usage.unwrap();
+        assert!(usage.user_time + usage.system_time > Duration::from_millis(0));
+        assert!(usage.max_rss_bytes > 0);
+        assert!(usage.voluntary_context_switches + usage.involuntary_context_switches > 0);
+    }
+
+    #[tokio::test]
+    async fn frame_output_as_lines() {
+        let mut engine = Engine::new();
+        let options = SpawnOptions {
+            line_framing: Some(LineFraming {
+                max_line: 1024,
+                flush_timeout: Duration::from_secs(1),
+            }),
+            open_stdin: true,
+            ..Default::default()
+        };
+
+        // The job writes the rest of each line once it reads a line of its own.
+        let script = "printf par; read x; printf 'tial\\nhold'; read x; printf 'on\\n'";
+        let id = spawn_with(&mut engine, "user", "/bin/sh", &["-c", script], &options).unwrap();
+        let stdin = engine.stdin(&id).unwrap();
+        stdin.write(b"\n").await.unwrap();
+
+        // The partial line that is written last is held back for too long and published on its own.
+        let held = OutputEvent::Stdout(b"hold".to_vec());
+        wait_for(&engine, &id, &held).await;
+        stdin.write(b"\n").await.unwrap();
+        engine.wait(&id).unwrap().await.unwrap();
+
+        assert_eq!(
+            events(&engine, &id),
+            vec![
+                OutputEvent::Stdout(b"partial\n".to_vec()),
+                OutputEvent::Stdout(b"hold".to_vec()),
+                OutputEvent::Stdout(b"on\n".to_vec()),
+                OutputEvent::Exit(ExitStatus::Code(0)),
+            ]
+        );
+    }
+}
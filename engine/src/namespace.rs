@@ -0,0 +1,353 @@
+use anyhow::{anyhow, Result};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+/// The namespaces a job is isolated in. Jobs share every namespace with the engine by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Isolation {
+    /// Give the job process ids of its own so that it only sees the processes it has spawned itself.
+    pub pid: bool,
+
+    /// Give the job mounts of its own so that anything it mounts stays private to it.
+    /// With a pid namespace as well, `/proc` is remounted so that it only lists the processes of the job.
+    pub mount: bool,
+
+    /// Give the job a hostname of its own.
+    pub uts: bool,
+
+    /// Give the job System V IPC objects and POSIX message queues of its own.
+    pub ipc: bool,
+
+    /// Give the job a network of its own that only has a loopback interface.
+    pub network: bool,
+
+    /// Bind-mount this directory read-only as the root filesystem of the job. Mounts below the directory
+    /// are carried over as they are, only the directory itself is made read-only. The program and working
+    /// directory are resolved within it. Requires a mount namespace.
+    pub root: Option<PathBuf>,
+}
+
+impl Isolation {
+    /// Returns true if the job isn't isolated in any namespace.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check that the namespaces make sense together.
+    pub fn validate(&self) -> Result<()> {
+        if self.root.is_some() && !self.mount {
+            return Err(anyhow!("a root filesystem requires a mount namespace"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything needed to move a process into its namespaces, prepared up front since allocating
+/// isn't permitted in the forked child.
+#[derive(Debug)]
+pub struct Namespaces {
+    flags: libc::c_int,
+    pid: bool,
+    mount: bool,
+    network: bool,
+    root: Option<CString>,
+
+    /// The working directory within the root filesystem.
+    working_directory: Option<CString>,
+}
+
+impl Namespaces {
+    pub fn new(isolation: &Isolation, working_directory: &str) -> Result<Self> {
+        isolation.validate()?;
+
+        let namespaces = [
+            (isolation.pid, libc::CLONE_NEWPID),
+            (isolation.mount, libc::CLONE_NEWNS),
+            (isolation.uts, libc::CLONE_NEWUTS),
+            (isolation.ipc, libc::CLONE_NEWIPC),
+            (isolation.network, libc::CLONE_NEWNET),
+        ];
+
+        let flags = namespaces
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(0, |flags, (_, flag)| flags | flag);
+
+        let root = match &isolation.root {
+            Some(root) => Some(CString::new(root.as_os_str().as_bytes())?),
+            None => None,
+        };
+
+        let working_directory = match root {
+            Some(_) => Some(CString::new(working_directory)?),
+            None => None,
+        };
+
+        Ok(Self {
+            flags,
+            pid: isolation.pid,
+            mount: isolation.mount,
+            network: isolation.network,
+            root,
+            working_directory,
+        })
+    }
+
+    /// Returns true if the working directory is entered within the root filesystem
+    /// rather than before the process enters its namespaces.
+    pub fn has_root(&self) -> bool {
+        self.root.is_some()
+    }
+
+    /// Move the calling process into its namespaces.
+    ///
+    /// A new pid namespace only applies to the children of the process that creates it, and the first
+    /// of those is its init which ignores signals it hasn't installed a handler for. So with a pid namespace
+    /// the calling process forks off an init that reaps everything in the namespace, which in turn forks
+    /// off the process that goes on to exec the job. Neither of the two ever return, they wait for the
+    /// job and exit the same way it did so that the exit status still reaches the engine.
+    ///
+    /// Should the job fail to exec, the error reaches the engine through the pipe the standard library spawns
+    /// the process with, which the job shares. The job exits right after, which takes down the init and then
+    /// the calling process, and that is the process the standard library reaps before spawning fails.
+    ///
+    /// This runs in the forked child before exec and must therefore be async-signal-safe.
+    pub fn enter(&self) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(self.flags))?;
+
+            if !self.pid {
+                return self.set_up();
+            }
+
+            let mut status_pipe = [-1; 2];
+            check(libc::pipe2(status_pipe.as_mut_ptr(), libc::O_CLOEXEC))?;
+            let [status_rx, status_tx] = status_pipe;
+
+            match check(libc::fork())? {
+                0 => {
+                    libc::close(status_rx);
+                    self.set_up()?;
+
+                    match check(libc::fork())? {
+                        0 => {
+                            libc::close(status_tx);
+                            Ok(())
+                        }
+                        job => reap(job, status_tx),
+                    }
+                }
+                init => forward(init, status_rx),
+            }
+        }
+    }
+
+    /// Set up the mounts and the network from within the namespaces.
+    unsafe fn set_up(&self) -> io::Result<()> {
+        if self.mount {
+            // Keep the mounts of the job from propagating back to the host.
+            check(libc::mount(
+                ptr::null(),
+                b"/\0".as_ptr().cast(),
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+
+            if let Some(root) = &self.root {
+                enter_root(root)?;
+            }
+
+            if self.pid {
+                check(libc::mount(
+                    b"proc\0".as_ptr().cast(),
+                    b"/proc\0".as_ptr().cast(),
+                    b"proc\0".as_ptr().cast(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    ptr::null(),
+                ))?;
+            }
+
+            if let Some(working_directory) = &self.working_directory {
+                check(libc::chdir(working_directory.as_ptr()))?;
+            }
+        }
+
+        if self.network {
+            bring_up_loopback()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Bind-mount a directory read-only, along with the mounts below it, and make it the root filesystem
+/// of the calling process.
+///
+/// The directory is mounted over `/tmp` rather than over itself, which is only visible within the mount namespace
+/// of the job, since a mount stacked on top of the current root can't be entered should the directory be `/`.
+unsafe fn enter_root(root: &CString) -> io::Result<()> {
+    let staging = b"/tmp\0".as_ptr().cast::<libc::c_char>();
+    check(libc::mount(
+        root.as_ptr(),
+        staging,
+        ptr::null(),
+        libc::MS_BIND | libc::MS_REC,
+        ptr::null(),
+    ))?;
+
+    check(libc::mount(
+        ptr::null(),
+        staging,
+        ptr::null(),
+        libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY,
+        ptr::null(),
+    ))?;
+
+    // Stacking the old root on top of the new one and detaching it leaves the new one
+    // as the only root, without needing a directory to move the old root to.
+    let current = b".\0".as_ptr().cast::<libc::c_char>();
+    check(libc::chdir(staging))?;
+    check(libc::syscall(libc::SYS_pivot_root, current, current) as libc::c_int)?;
+    check(libc::umount2(current, libc::MNT_DETACH))?;
+    check(libc::chdir(b"/\0".as_ptr().cast()))?;
+    Ok(())
+}
+
+/// The part of `struct ifreq` that's used to get and set the flags of a network interface.
+#[repr(C)]
+struct InterfaceFlags {
+    name: [u8; 16],
+    flags: libc::c_short,
+
+    /// Pads the struct to the size of the union in `struct ifreq`.
+    _padding: [u8; 22],
+}
+
+/// A new network namespace comes with a loopback interface that's down.
+unsafe fn bring_up_loopback() -> io::Result<()> {
+    let socket = check(libc::socket(
+        libc::AF_INET,
+        libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+        0,
+    ))?;
+
+    let mut request: InterfaceFlags = mem::zeroed();
+    request.name[..2].copy_from_slice(b"lo");
+
+    let result = check(libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request)).and_then(|_| {
+        request.flags |= libc::IFF_UP as libc::c_short;
+        check(libc::ioctl(socket, libc::SIOCSIFFLAGS, &request))
+    });
+
+    libc::close(socket);
+    result.map(drop)
+}
+
+/// Reap every process in the pid namespace until the job exits and then send its wait status
+/// to the process outside of the namespace. The namespace is torn down along with everything
+/// left in it once this exits.
+unsafe fn reap(job: libc::pid_t, status_tx: libc::c_int) -> ! {
+    close_all_except(status_tx);
+
+    loop {
+        let mut status = 0;
+        match libc::waitpid(-1, &mut status, 0) {
+            pid if pid == job => {
+                libc::write(
+                    status_tx,
+                    (&status as *const libc::c_int).cast(),
+                    mem::size_of::<libc::c_int>(),
+                );
+
+                libc::_exit(0)
+            }
+            -1 if *libc::__errno_location() != libc::EINTR => libc::_exit(1),
+            _ => (),
+        }
+    }
+}
+
+/// Wait for the init of the pid namespace and exit the same way the job did. Signals meant for the job
+/// reach it through its process group so they're ignored here, except for those that can't be.
+unsafe fn forward(init: libc::pid_t, status_rx: libc::c_int) -> ! {
+    close_all_except(status_rx);
+
+    // Real-time signals are left alone, they're rarely used to stop anything.
+    for signal in 1..32 {
+        if signal != libc::SIGCHLD && signal != libc::SIGKILL && signal != libc::SIGSTOP {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+    }
+
+    let mut job_status = 0;
+    let read = loop {
+        let read = libc::read(
+            status_rx,
+            (&mut job_status as *mut libc::c_int).cast(),
+            mem::size_of::<libc::c_int>(),
+        );
+
+        if read != -1 || *libc::__errno_location() != libc::EINTR {
+            break read;
+        }
+    };
+
+    let mut init_status = 0;
+    while libc::waitpid(init, &mut init_status, 0) == -1 && *libc::__errno_location() == libc::EINTR
+    {
+    }
+
+    // The init only goes without reporting the status of the job if it was killed itself.
+    let status = if read == mem::size_of::<libc::c_int>() as isize {
+        job_status
+    } else {
+        init_status
+    };
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal)
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Close every file descriptor but one. Processes that don't exec must not hold on to anything,
+/// least of all the pipe through which the engine learns whether the job has been executed.
+unsafe fn close_all_except(keep: libc::c_int) {
+    let ranges = [(0, keep - 1), (keep + 1, libc::c_int::MAX)];
+
+    for (first, last) in ranges.iter().copied().filter(|(first, last)| first <= last) {
+        if libc::syscall(
+            libc::SYS_close_range,
+            first as libc::c_uint,
+            last as libc::c_uint,
+            0,
+        ) == -1
+        {
+            let mut limit: libc::rlimit = mem::zeroed();
+            libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+            let last = (last as libc::rlim_t).min(limit.rlim_cur) as libc::c_int;
+
+            for fd in first..=last {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+fn check<T: Default + PartialOrd>(result: T) -> io::Result<T> {
+    if result < T::default() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -1,78 +1,1699 @@
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use crate::report::ErrorReporter;
+use crate::search::LogSearch;
+use crate::segment::{Segment, SegmentReader};
+use crate::store::{LogFiles, Store};
+use crate::subscriber::{self, BackpressurePolicy, Publisher, Ready, Subscriber};
+use crate::usage::ResourceUsage;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// How many bytes of output are kept in memory for each job by default.
+const DEFAULT_MEMORY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// How many bytes of output are spilled to disk for each job by default.
+const DEFAULT_SPILL_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// The most output bytes a single read returns.
+const MAX_READ_BYTES: u64 = 1024 * 1024;
+
+/// The most output bytes a single read looks through, whether it returns them or not.
+const MAX_SCAN_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How often the resource usage of a running job is recorded in the store at most.
+const USAGE_RECORD_INTERVAL: Duration = Duration::from_secs(60);
 
 /// An `OutputEvent` is any output from a process. Partial or not.
 /// A stream of these should be able to be reconstructed into a full output.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OutputEvent {
     Stdout(Vec<u8>),
     Stderr(Vec<u8>),
-    Exit(i32),
+
+    /// A stop signal was delivered to the process group, possibly as an escalation
+    /// after the process didn't exit within its grace period.
+    Stop {
+        signal: i32,
+        escalated: bool,
+    },
+
+    Exit(ExitStatus),
+
+    /// The job ran past its deadline and is being stopped. The stop events follow.
+    TimedOut,
+
+    /// The given number of bytes of output from the start of the log have been discarded
+    /// according to the retention policy. This is only ever sent first to subscribers
+    /// that ask for past events.
+    Truncated {
+        bytes: u64,
+    },
+
+    /// The given number of events were dropped since the subscriber fell too far behind.
+    /// This is only ever sent to the subscriber that missed the events.
+    Gap {
+        events: u64,
+    },
+}
+
+impl OutputEvent {
+    /// The number of output bytes the event carries, which is what retention is accounted in.
+    fn size(&self) -> usize {
+        match self {
+            Self::Stdout(bytes) | Self::Stderr(bytes) => bytes.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// An event along with where it falls in the log of the job and when it was published.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEvent {
+    /// Numbers the events of a job in the order they were published, starting from 1, which orders stdout
+    /// and stderr relative to each other. Truncation and gap events carry the number of the last event they
+    /// stand in for so that continuing after any event picks up right where it left off.
+    pub sequence: u64,
+
+    pub at: SystemTime,
+    pub event: OutputEvent,
+}
+
+impl LogEvent {
+    /// The number of output bytes the event carries.
+    pub(crate) fn size(&self) -> usize {
+        self.event.size()
+    }
+}
+
+/// Where a new listener starts receiving events from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailFrom {
+    /// Only events published from now on.
+    Now,
+
+    /// Every retained past event followed by every event published from now on.
+    Start,
+
+    /// The retained past events with this sequence number and later followed by every event published from
+    /// now on, which resumes a stream right after the last event it received. Should events from the requested
+    /// one on have been discarded, the truncation event comes first. Retained events are always consecutive
+    /// so nothing else can be missing, and only the events from the requested one on are read back.
+    Sequence(u64),
+}
+
+/// Which of the output streams of a job to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStreams {
+    Stdout,
+    Stderr,
+
+    /// Both streams interleaved in the order their output was published.
+    Both,
+}
+
+impl LogStreams {
+    /// The bytes an event carries should it be output of a selected stream.
+    pub(crate) fn bytes(self, event: &OutputEvent) -> Option<&[u8]> {
+        match (self, event) {
+            (Self::Stdout, OutputEvent::Stdout(bytes))
+            | (Self::Stderr, OutputEvent::Stderr(bytes))
+            | (Self::Both, OutputEvent::Stdout(bytes))
+            | (Self::Both, OutputEvent::Stderr(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// How many bytes of the selected streams were published before an event.
+    fn offset(self, offsets: Offsets) -> u64 {
+        match self {
+            Self::Stdout => offsets.stdout,
+            Self::Stderr => offsets.stderr,
+            Self::Both => offsets.stdout + offsets.stderr,
+        }
+    }
+}
+
+/// A part of the output of a job to read. At most `MAX_READ_BYTES` of it are read at once, where the first lines are
+/// cut at the end, the last lines at the start and a byte range is shortened. Longer ranges are read in byte ranges.
+/// A read also looks through no more than `MAX_SCAN_BYTES` of output, so lines that are further away than that from
+/// where it starts, like those of a stream that hasn't output anything in a while, aren't read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRange {
+    /// The first lines.
+    Head(usize),
+
+    /// The last lines.
+    Tail(usize),
+
+    /// A number of bytes starting at an offset into the output, where both streams are counted
+    /// together should both be read. Only the part that's still retained is read.
+    Bytes { offset: u64, length: u64 },
+}
+
+/// Part of the output of a job as it was read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRead {
+    /// The output events in the range, cut down to it.
+    pub events: Vec<LogEvent>,
+
+    /// The part of the output of the selected streams the read went through, counted like byte ranges. It starts
+    /// past where a byte range does should the output before it have been discarded, and reading on from where it
+    /// ends picks up right where the read left off.
+    pub offsets: Range<u64>,
+}
+
+/// How many bytes of stdout and stderr were published before an event, which indexes the log by output offset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Offsets {
+    stdout: u64,
+    stderr: u64,
+}
+
+impl Offsets {
+    /// The offsets of whatever comes after the event.
+    pub(crate) fn after(self, event: &OutputEvent) -> Self {
+        match event {
+            OutputEvent::Stdout(bytes) => Self {
+                stdout: self.stdout + bytes.len() as u64,
+                ..self
+            },
+            OutputEvent::Stderr(bytes) => Self {
+                stderr: self.stderr + bytes.len() as u64,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+/// A copy of an event carrying only part of its output.
+fn slice(event: &LogEvent, range: Range<usize>) -> LogEvent {
+    let output = match &event.event {
+        OutputEvent::Stdout(bytes) => OutputEvent::Stdout(bytes[range].to_vec()),
+        OutputEvent::Stderr(bytes) => OutputEvent::Stderr(bytes[range].to_vec()),
+        other => other.clone(),
+    };
+
+    LogEvent {
+        sequence: event.sequence,
+        at: event.at,
+        event: output,
+    }
+}
+
+/// How much disk space the output of a job takes up, which is its log should it be recorded in a store and its
+/// spilled output otherwise. Events on disk are compressed in frames and only count once their frame has been written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogStorage {
+    /// The size of the events on disk had they not been compressed.
+    pub raw_bytes: u64,
+
+    /// The size of the events on disk.
+    pub compressed_bytes: u64,
+}
+
+/// How much past output is retained for each job. Once the in-memory log exceeds its limit,
+/// the oldest events are evicted to a segment file on disk if spilling is enabled, or discarded otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The maximum number of output bytes kept in memory.
+    pub memory_limit: usize,
+
+    /// The directory to create segment files for evicted events in, if spilling is enabled.
+    /// Segment files are unlinked right after creation so nothing is left behind on disk.
+    pub spill_directory: Option<PathBuf>,
+
+    /// The maximum number of output bytes kept on disk, whether spilled or in the log of a job recorded in a store.
+    /// Once it's reached the oldest events on disk are discarded to make room for new ones.
+    pub spill_limit: u64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            spill_directory: None,
+            spill_limit: DEFAULT_SPILL_LIMIT,
+        }
+    }
+}
+
+/// How a process terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitStatus {
+    /// The process exited on its own with the given exit code.
+    Code(i32),
+
+    /// The process was terminated by a signal.
+    Signal { signal: i32, core_dumped: bool },
+
+    /// The process outlived the server that spawned it and was killed when the server came back,
+    /// since it couldn't be reattached to.
+    Lost,
+
+    /// The process outlived the server that spawned it and exited on its own once it had been reattached to.
+    /// How it exited is unknown since it was no longer ours to reap.
+    Unknown,
+}
+
+impl From<process::ExitStatus> for ExitStatus {
+    fn from(status: process::ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Self::Code(code),
+            (None, Some(signal)) => Self::Signal {
+                signal,
+                core_dumped: libc::WCOREDUMP(status.into_raw()),
+            },
+            // A process that has terminated has either exited or been signalled
+            // so this is just here to make the compiler happy.
+            (None, None) => Self::Code(1),
+        }
+    }
+}
+
+/// The retained events of a job as of when the snapshot was taken.
+#[derive(Debug)]
+pub struct LogSnapshot {
+    segment: Option<SegmentReader>,
+    memory: Vec<(LogEvent, Offsets)>,
+
+    /// The offsets of whatever comes after the events.
+    end: Offsets,
+}
+
+impl LogSnapshot {
+    /// Search the events line by line. Events on disk are read back one frame at a time.
+    pub fn search(&self, search: &LogSearch) -> Result<Vec<LogEvent>> {
+        let on_disk = self.segment.iter().flat_map(SegmentReader::events);
+        let in_memory = self.memory.iter().map(|(event, _)| Ok(event.clone()));
+        search.run(on_disk.chain(in_memory))
+    }
+
+    /// Read part of the output of the selected streams. Only output events are read, cut down to the range, and the
+    /// frames on disk that are outside of it are left alone.
+    pub fn read(&self, streams: LogStreams, range: LogRange) -> Result<LogRead> {
+        let mut events = Vec::new();
+        let mut budget = MAX_READ_BYTES as usize;
+        let mut scanned = 0;
+
+        let retained = streams.offset(self.first_offsets())..streams.offset(self.end);
+
+        let offsets = match range {
+            LogRange::Head(lines) => {
+                let mut seen = 0;
+                let mut position = retained.start;
+
+                'head: for block in 0..self.blocks() {
+                    for (event, offsets) in self.block(block)?.iter() {
+                        if seen == lines || budget == 0 || scanned >= MAX_SCAN_BYTES {
+                            break 'head;
+                        }
+
+                        scanned += event.size() as u64;
+                        let bytes = match streams.bytes(&event.event) {
+                            Some(bytes) => bytes,
+                            None => continue,
+                        };
+
+                        let mut end = bytes.len();
+                        for (position, byte) in bytes.iter().enumerate() {
+                            if *byte == b'\n' {
+                                seen += 1;
+                                if seen == lines {
+                                    end = position + 1;
+                                    break;
+                                }
+                            }
+                        }
+
+                        let end = end.min(budget);
+                        budget -= end;
+                        position = streams.offset(*offsets) + end as u64;
+                        events.push(slice(event, 0..end));
+                    }
+                }
+
+                retained.start..position
+            }
+
+            LogRange::Tail(lines) => {
+                // The newline ending the output doesn't start another line.
+                let mut seen = 0;
+                let mut last = true;
+                let mut position = retained.end;
+
+                'tail: for block in (0..self.blocks()).rev() {
+                    for (event, offsets) in self.block(block)?.iter().rev() {
+                        if seen == lines || budget == 0 || scanned >= MAX_SCAN_BYTES {
+                            break 'tail;
+                        }
+
+                        scanned += event.size() as u64;
+                        let bytes = match streams.bytes(&event.event) {
+                            Some(bytes) if !bytes.is_empty() => bytes,
+                            _ => continue,
+                        };
+
+                        let mut start = 0;
+                        for (position, byte) in bytes.iter().enumerate().rev() {
+                            if *byte == b'\n' && !last {
+                                seen += 1;
+                                if seen == lines {
+                                    start = position + 1;
+                                    break;
+                                }
+                            }
+
+                            last = false;
+                        }
+
+                        let start = start.max(bytes.len().saturating_sub(budget));
+                        if start != bytes.len() {
+                            budget -= bytes.len() - start;
+                            position = streams.offset(*offsets) + start as u64;
+                            events.push(slice(event, start..bytes.len()));
+                        }
+                    }
+                }
+
+                events.reverse();
+                position..retained.end
+            }
+
+            LogRange::Bytes { offset, length } => {
+                // The part of the range that has been discarded is left out.
+                let requested_end = offset.saturating_add(length);
+                let offset = offset.max(retained.start);
+                let end = requested_end
+                    .min(offset.saturating_add(MAX_READ_BYTES))
+                    .max(offset);
+                let mut position = offset;
+
+                // Find the last block starting at or before the offset, which is where the range starts.
+                let (mut low, mut high) = (0, self.blocks());
+                while low < high {
+                    let middle = (low + high) / 2;
+                    if streams.offset(self.block_offsets(middle)) <= offset {
+                        low = middle + 1;
+                    } else {
+                        high = middle;
+                    }
+                }
+
+                let first = low.saturating_sub(1);
+                'bytes: for block in first..self.blocks() {
+                    let block_events = self.block(block)?;
+
+                    // Within the first block, start at the last event starting at or before the offset too.
+                    let skip = if block == first {
+                        block_events
+                            .partition_point(|(_, offsets)| streams.offset(*offsets) <= offset)
+                            .saturating_sub(1)
+                    } else {
+                        0
+                    };
+
+                    for (event, offsets) in &block_events[skip..] {
+                        let start = streams.offset(*offsets);
+                        if start >= end || scanned >= MAX_SCAN_BYTES {
+                            break 'bytes;
+                        }
+
+                        scanned += event.size() as u64;
+                        let bytes = match streams.bytes(&event.event) {
+                            Some(bytes) => bytes,
+                            None => continue,
+                        };
+
+                        let from = offset.saturating_sub(start).min(bytes.len() as u64) as usize;
+                        let to = (end - start).min(bytes.len() as u64) as usize;
+                        if from < to {
+                            position = start + to as u64;
+                            events.push(slice(event, from..to));
+                        }
+                    }
+                }
+
+                offset..position
+            }
+        };
+
+        Ok(LogRead { events, offsets })
+    }
+
+    /// The offsets of the first retained event, or of whatever comes next should there be none.
+    fn first_offsets(&self) -> Offsets {
+        match self.blocks() {
+            0 => self.end,
+            _ => self.block_offsets(0),
+        }
+    }
+
+    /// The number of blocks the events are read back in, which are those of the segment followed by the events
+    /// in memory.
+    fn blocks(&self) -> usize {
+        self.segment.as_ref().map_or(0, SegmentReader::blocks)
+            + usize::from(!self.memory.is_empty())
+    }
+
+    /// The offsets of the first event in a block.
+    fn block_offsets(&self, block: usize) -> Offsets {
+        match &self.segment {
+            Some(segment) if block < segment.blocks() => segment.block_offsets(block),
+            _ => self.memory[0].1,
+        }
+    }
+
+    /// The events in a block along with their offsets, which are read back from disk should they be there.
+    fn block(&self, block: usize) -> Result<Cow<'_, [(LogEvent, Offsets)]>> {
+        match &self.segment {
+            Some(segment) if block < segment.blocks() => Ok(Cow::Owned(segment.block(block)?)),
+            _ => Ok(Cow::Borrowed(&self.memory)),
+        }
+    }
+}
+
+/// Reads the past events a listener receives before it starts receiving published ones. The lock on the output is only
+/// held to find out where the next events are, and events on disk are read one frame at a time without it.
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    output: Arc<Mutex<Output>>,
+
+    /// The sequence number of the next event to read.
+    next: u64,
+
+    /// Set once reading has started, after which events that have been discarded in the meantime are stood in for
+    /// by a gap event rather than a truncation event.
+    started: bool,
+
+    /// The most events read at once, which is the capacity of the queue of the listener.
+    limit: usize,
+
+    /// The events of the frame read last that haven't been handed over yet.
+    rest: VecDeque<LogEvent>,
+
+    /// Registered with the output once every past event has been read, so that nothing published is missed.
+    publisher: Option<Publisher>,
+}
+
+impl Backlog {
+    /// Read the next past events, blocking on the disk should they be there. Returns no events once every past
+    /// event has been read and the listener has been registered with the output.
+    pub(crate) fn read(&mut self) -> Vec<LogEvent> {
+        let mut events = Vec::new();
+
+        while self.rest.is_empty() {
+            let mut output = self.output.lock().unwrap();
+            let retained = output.retained();
+
+            // The events from the next one on that have been discarded are stood in for by a single event,
+            // which is stamped with the time of the event following it.
+            if self.next < retained.start {
+                let event = if self.started {
+                    OutputEvent::Gap {
+                        events: retained.start - self.next,
+                    }
+                } else {
+                    OutputEvent::Truncated {
+                        bytes: output.truncated(),
+                    }
+                };
+
+                events.push(LogEvent {
+                    sequence: retained.start - 1,
+                    at: SystemTime::now(),
+                    event,
+                });
+                self.next = retained.start;
+            }
+
+            self.started = true;
+
+            if self.next == retained.end {
+                if events.is_empty() {
+                    output.publishers.extend(self.publisher.take());
+                }
+
+                return events;
+            }
+
+            let reader = match (&output.segment, output.log.front()) {
+                (_, Some(first)) if self.next >= first.sequence => {
+                    let skip = (self.next - first.sequence) as usize;
+                    self.rest = output
+                        .log
+                        .iter()
+                        .skip(skip)
+                        .take(self.limit)
+                        .cloned()
+                        .collect();
+                    continue;
+                }
+                (Some(segment), _) => segment.reader_from(self.next),
+                (None, _) => unreachable!("retained events are either in memory or on disk"),
+            };
+
+            let errors = output.errors();
+            drop(output);
+
+            match reader.events().collect::<Result<Vec<_>>>() {
+                Ok(read) => {
+                    let next = self.next;
+                    self.rest = read
+                        .into_iter()
+                        .filter(|event| event.sequence >= next)
+                        .collect();
+                }
+
+                // The frame may have been discarded while it was read, in which case the next read says so.
+                // Otherwise the events in it are lost to the listener, which is told so by a gap event.
+                Err(error) => {
+                    if self.output.lock().unwrap().retained().start <= self.next {
+                        errors.report(error.context("failed to read past output events"));
+                        events.push(LogEvent {
+                            sequence: reader.end() - 1,
+                            at: SystemTime::now(),
+                            event: OutputEvent::Gap {
+                                events: reader.end() - self.next,
+                            },
+                        });
+                        self.next = reader.end();
+                    }
+                }
+            }
+        }
+
+        let count = self.rest.len().min(self.limit);
+        if let Some(after) = self.rest.front() {
+            for event in &mut events {
+                event.at = after.at;
+            }
+        }
+
+        for event in self.rest.drain(..count) {
+            self.next = event.sequence + 1;
+            events.push(event);
+        }
+
+        events
+    }
 }
 
 /// The `Output` struct handles storing a log of previous events and continously broadcasting
 /// past and new events to any listeners.
 #[derive(Debug)]
 pub struct Output {
-    pub log: Vec<OutputEvent>,
-    senders: Vec<UnboundedSender<OutputEvent>>,
+    /// The most recent events, bounded according to the retention policy.
+    log: VecDeque<LogEvent>,
+
+    /// The offsets of every event in memory, kept alongside the log.
+    log_offsets: VecDeque<Offsets>,
+
+    /// The offsets of whatever is published next.
+    offsets: Offsets,
+
+    /// The sequence number of the most recently published event, or 0 if nothing has been published yet.
+    sequence: u64,
+
+    /// The number of output bytes held by the events in memory.
+    log_bytes: usize,
+
+    retention: RetentionPolicy,
+
+    /// Events on disk. Should the job be recorded in a store this is its log, which every event is written
+    /// through to. Otherwise it holds the older events evicted from memory, once there are any and if spilling
+    /// is enabled.
+    segment: Option<Segment>,
+
+    publishers: Vec<Publisher>,
+    backpressure: BackpressurePolicy,
+
+    /// How and when the process terminated, once the exit event has been published.
+    exit: Option<(ExitStatus, SystemTime)>,
+
+    /// Set once the job has been stopped for running past its deadline.
+    timed_out: bool,
+
+    /// The resources the process has used as of the latest sample.
+    usage: Option<ResourceUsage>,
+
+    /// Everyone waiting for the process to terminate.
+    waiters: Vec<oneshot::Sender<ExitStatus>>,
+
+    /// The store the job is recorded in along with the job it's recorded as, if any.
+    store: Option<(Arc<dyn Store>, Uuid)>,
+
+    /// When the resource usage was last recorded in the store.
+    usage_recorded_at: Option<Instant>,
+
+    /// Where errors writing to disk or the store are reported.
+    errors: ErrorReporter,
 }
 
 impl Output {
-    /// Creates a new event log and broadcast channel.
-    pub fn new() -> Self {
+    /// Creates a new event log and broadcast channel retaining past events
+    /// and bounding the queue of each listener according to the given policies.
+    pub fn new(retention: RetentionPolicy, backpressure: BackpressurePolicy) -> Self {
         Self {
-            log: Vec::new(),
-            senders: Vec::new(),
+            log: VecDeque::new(),
+            log_offsets: VecDeque::new(),
+            offsets: Offsets::default(),
+            sequence: 0,
+            log_bytes: 0,
+            retention,
+            segment: None,
+            publishers: Vec::new(),
+            backpressure,
+            exit: None,
+            timed_out: false,
+            usage: None,
+            waiters: Vec::new(),
+            store: None,
+            usage_recorded_at: None,
+            errors: ErrorReporter::default(),
+        }
+    }
+
+    /// Report errors writing to disk or the store to the given reporter, since they happen in the background.
+    pub fn report_errors_to(&mut self, errors: ErrorReporter) {
+        self.errors = errors;
+    }
+
+    /// Where errors in the background of the job are reported.
+    pub fn errors(&self) -> ErrorReporter {
+        self.errors.clone()
+    }
+
+    /// Record every event published from now on for the given job in its log in a store. Should the log already
+    /// hold events, like when the job is restored, they're read back from it and publishing picks up after them.
+    pub fn record_to(&mut self, store: Arc<dyn Store>, job: Uuid, log: LogFiles) -> Result<()> {
+        let segment = Segment::open(log, self.errors.clone())?;
+        let sequences = segment.sequences();
+
+        if !sequences.is_empty() {
+            let (last, offsets) = segment.event(sequences.end - 1)?;
+            self.sequence = last.sequence;
+            self.offsets = offsets.after(&last.event);
+
+            if let OutputEvent::Exit(status) = last.event {
+                self.exit = Some((status, last.at));
+            }
+        }
+
+        self.timed_out = segment.timed_out();
+        self.segment = Some(segment);
+        self.store = Some((store, job));
+        Ok(())
+    }
+
+    /// Stop recording the job in the store, like when the job is being removed from it.
+    pub fn stop_recording(&mut self) {
+        self.store = None;
+    }
+
+    /// How many bytes of stdout and stderr have been published.
+    pub fn offsets(&self) -> (u64, u64) {
+        (self.offsets.stdout, self.offsets.stderr)
+    }
+
+    /// Take note of a sample of the resource usage of the process, which is recorded in the store too
+    /// every so often. The usage as of the exit is always recorded.
+    pub fn record_usage(&mut self, usage: ResourceUsage) {
+        let usage = self.usage.map_or(usage, |previous| previous.max(usage));
+        self.usage = Some(usage);
+
+        let due = self
+            .usage_recorded_at
+            .map_or(true, |at| at.elapsed() >= USAGE_RECORD_INTERVAL);
+
+        if let (Some((store, job)), Some(segment), true) = (&self.store, &self.segment, due) {
+            let (store, job) = (Arc::clone(store), *job);
+            segment.then("failed to record resource usage", move || {
+                store.record_usage(job, &usage)
+            });
+            self.usage_recorded_at = Some(Instant::now());
         }
     }
 
     /// Publish an event. This stores the event in a log and publishes it to all active listeners.
     pub fn publish(&mut self, event: OutputEvent) {
+        self.publish_at(event, SystemTime::now());
+    }
+
+    /// Publish an event as if it happened at the given time.
+    pub fn publish_at(&mut self, event: OutputEvent, at: SystemTime) {
+        match event {
+            OutputEvent::Exit(status) => self.exit = Some((status, at)),
+            OutputEvent::TimedOut => self.timed_out = true,
+            _ => (),
+        }
+
+        self.sequence += 1;
+        let event = LogEvent {
+            sequence: self.sequence,
+            at,
+            event,
+        };
+
         // Attempt to send the events to all registered listeners and any listeners that have become inactive.
-        self.senders
-            .retain(|sender| sender.send(event.clone()).is_ok());
+        self.publishers
+            .retain(|publisher| publisher.send(event.clone()));
+
+        if self.segment.as_ref().map_or(false, Segment::persistent) {
+            if let Err(error) = self.record(&event) {
+                self.errors
+                    .report(error.context("failed to record output event"));
+
+                // The log would be missing the event so the job is no longer recorded.
+                self.segment = None;
+                self.store = None;
+            }
+        }
+
+        if let OutputEvent::Exit(status) = event.event {
+            let waiters = std::mem::take(&mut self.waiters);
+            let notify = move || {
+                for waiter in waiters {
+                    let _ = waiter.send(status);
+                }
+
+                Ok(())
+            };
+
+            // Waiters on a recorded job are only told once its log is on disk, so that it can be read back by then.
+            match &self.segment {
+                Some(segment) if segment.persistent() => {
+                    segment.then("failed to notify waiters", notify)
+                }
+                _ => notify().unwrap(),
+            }
+        }
+
+        self.log_bytes += event.size();
+        self.log_offsets.push_back(self.offsets);
+        self.offsets = self.offsets.after(&event.event);
+        self.log.push_back(event);
+        self.evict();
+    }
+
+    /// Write an event through to the log of the job, discarding its oldest events should it grow beyond the limit.
+    /// The output files of the job are let go of as far as the output in them has been written to the log. Once the
+    /// job has exited the log is synced and the usage as of the exit recorded. All of that is done off the runtime.
+    fn record(&mut self, event: &LogEvent) -> Result<()> {
+        let exited = matches!(event.event, OutputEvent::Exit(_));
+        let segment = self.segment.as_mut().unwrap();
+        segment.append(event, self.offsets)?;
+
+        if exited {
+            segment.sync()?;
+        }
+
+        self.discard_beyond_limit()?;
+
+        let segment = self.segment.as_mut().unwrap();
+        let flushed = segment.take_flushed();
+        let (store, job) = match &self.store {
+            Some((store, job)) => (Arc::clone(store), *job),
+            None => return Ok(()),
+        };
+
+        if exited {
+            let usage = self.usage;
+            segment.then("failed to record the job as exited", move || {
+                if let Some(usage) = usage {
+                    store.record_usage(job, &usage)?;
+                }
+
+                store.release_output(job, None)
+            });
+        } else if let Some(offsets) = flushed {
+            segment.then("failed to release output files", move || {
+                store.release_output(job, Some((offsets.stdout, offsets.stderr)))
+            });
+        }
 
-        self.log.push(event);
+        Ok(())
     }
 
-    /// Register a new event listener that will all future events and optionally those of the past.
-    pub fn tail(&mut self, from_start: bool) -> UnboundedReceiver<OutputEvent> {
-        let (tx, rx) = mpsc::unbounded_channel();
+    /// Evict the oldest events from memory until the log fits within its limit.
+    /// Events of a job that's recorded in a store are in its log already.
+    fn evict(&mut self) {
+        while self.log_bytes > self.retention.memory_limit {
+            let (event, offsets) = match (self.log.pop_front(), self.log_offsets.pop_front()) {
+                (Some(event), Some(offsets)) => (event, offsets),
+                _ => break,
+            };
 
-        if from_start {
-            for event in &self.log {
-                tx.send(event.clone()).unwrap();
+            self.log_bytes -= event.size();
+
+            if self.segment.as_ref().map_or(false, Segment::persistent) {
+                continue;
+            }
+
+            if let Err(error) = self.spill(&event, offsets) {
+                self.errors
+                    .report(error.context("failed to spill output event to disk"));
+
+                // The spilled events would be followed by a hole so they're discarded along with the event.
+                self.segment = None;
             }
         }
+    }
 
-        self.senders.push(tx);
-        rx
+    /// Move an evicted event to the segment on disk, creating the segment should it not exist yet.
+    /// The event is discarded instead if spilling is disabled.
+    fn spill(&mut self, event: &LogEvent, offsets: Offsets) -> Result<()> {
+        let directory = match &self.retention.spill_directory {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+
+        if self.segment.is_none() {
+            self.segment = Some(Segment::create(directory, self.errors.clone())?);
+        }
+
+        self.segment.as_mut().unwrap().append(event, offsets)?;
+        self.discard_beyond_limit()
+    }
+
+    /// Discard the oldest events on disk until the segment fits within its limit.
+    fn discard_beyond_limit(&mut self) -> Result<()> {
+        if let Some(segment) = &mut self.segment {
+            while segment.bytes() > self.retention.spill_limit {
+                if !segment.discard_oldest()? {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Register a new event listener that will receive all future events and past events according to where it starts.
+    /// Fails if it's to start from an event that hasn't been published yet. Past events are read as the listener
+    /// receives them, a queue of them at a time and without holding the lock on the output while they're read from
+    /// disk. The listener only starts receiving published events once it has caught up.
+    pub fn tail(output: &Arc<Mutex<Self>>, from: TailFrom) -> Result<Subscriber> {
+        let mut locked = output.lock().unwrap();
+        let (publisher, subscriber) = subscriber::subscribe(locked.backpressure);
+
+        let next = match from {
+            TailFrom::Now => {
+                locked.publishers.push(publisher);
+                return Ok(subscriber);
+            }
+            TailFrom::Start => 1,
+            TailFrom::Sequence(sequence) if sequence > locked.sequence + 1 => {
+                return Err(anyhow!(
+                    "sequence {} is past the latest event {}",
+                    sequence,
+                    locked.sequence
+                ))
+            }
+            TailFrom::Sequence(sequence) => sequence,
+        };
+
+        let backlog = Backlog {
+            output: Arc::clone(output),
+            next,
+            started: false,
+            limit: locked.backpressure.capacity.max(1),
+            rest: VecDeque::new(),
+            publisher: Some(publisher),
+        };
+
+        Ok(subscriber.with_backlog(backlog))
+    }
+
+    /// Creates a future that resolves once every listener that blocks publishing has room for more events.
+    /// The future doesn't borrow the output so it can be awaited without holding its lock.
+    pub fn ready(&self) -> Ready {
+        Ready::new(self.publishers.iter())
+    }
+
+    /// Collect every retained past event, preceded by a truncation event should any output have been discarded.
+    pub fn get_events(&self) -> Result<Vec<LogEvent>> {
+        self.events_from(1)
+    }
+
+    /// Collect the retained past events with the given sequence number and later. Only the frames on disk
+    /// from there on are read back. Should any of those events have been discarded, a truncation event
+    /// standing in for everything before the first retained event comes first.
+    fn events_from(&self, sequence: u64) -> Result<Vec<LogEvent>> {
+        let retained = self.retained();
+        let mut events = Vec::new();
+
+        if sequence < retained.start {
+            let at = if retained.is_empty() {
+                SystemTime::now()
+            } else {
+                self.retained_event(retained.start)?.at
+            };
+
+            events.push(LogEvent {
+                sequence: retained.start - 1,
+                at,
+                event: OutputEvent::Truncated {
+                    bytes: self.truncated(),
+                },
+            });
+        }
+
+        for sequence in sequence.max(retained.start)..retained.end {
+            events.push(self.retained_event(sequence)?);
+        }
+
+        Ok(events)
+    }
+
+    /// Take a snapshot of the retained events to be read without holding the lock on the output.
+    /// The events in memory are copied, while those on disk are only read back once the snapshot is.
+    pub fn snapshot(&self) -> LogSnapshot {
+        let segment = self.segment.as_ref();
+
+        // Every event of a job recorded in a store is in its log already.
+        let memory = match segment {
+            Some(segment) if segment.persistent() => Vec::new(),
+            _ => self
+                .log
+                .iter()
+                .cloned()
+                .zip(self.log_offsets.iter().copied())
+                .collect(),
+        };
+
+        LogSnapshot {
+            segment: segment.map(Segment::reader),
+            memory,
+            end: self.offsets,
+        }
+    }
+
+    /// The sequence numbers of the retained events, counting those on disk.
+    fn retained(&self) -> Range<u64> {
+        let on_disk = self
+            .segment
+            .as_ref()
+            .map(Segment::sequences)
+            .filter(|sequences| !sequences.is_empty())
+            .map(|sequences| sequences.start);
+
+        let in_memory = self.log.front().map(|first| first.sequence);
+        let first = match (on_disk, in_memory) {
+            (Some(on_disk), Some(in_memory)) => on_disk.min(in_memory),
+            (Some(first), None) | (None, Some(first)) => first,
+            (None, None) => self.sequence + 1,
+        };
+
+        first..self.sequence + 1
+    }
+
+    /// The number of output bytes from the start of the log that have been discarded,
+    /// which is everything published before the first retained event.
+    fn truncated(&self) -> u64 {
+        let on_disk = self.segment.as_ref().and_then(|segment| {
+            let offsets = segment.first_offsets()?;
+            Some((segment.sequences().start, offsets))
+        });
+
+        let in_memory = self
+            .log
+            .front()
+            .map(|first| (first.sequence, self.log_offsets[0]));
+
+        let offsets = on_disk
+            .into_iter()
+            .chain(in_memory)
+            .min_by_key(|(sequence, _)| *sequence)
+            .map_or(self.offsets, |(_, offsets)| offsets);
+
+        offsets.stdout + offsets.stderr
+    }
+
+    /// A retained event. Events on disk are read back from there.
+    fn retained_event(&self, sequence: u64) -> Result<LogEvent> {
+        match (&self.segment, self.log.front()) {
+            (_, Some(first)) if sequence >= first.sequence => {
+                Ok(self.log[(sequence - first.sequence) as usize].clone())
+            }
+            (Some(segment), _) => Ok(segment.event(sequence)?.0),
+            _ => Err(anyhow!("event {} is not retained", sequence)),
+        }
+    }
+
+    /// How and when the process terminated, should it have done so.
+    pub fn exit(&self) -> Option<(ExitStatus, SystemTime)> {
+        self.exit
     }
 
-    pub fn get_events(&mut self) -> Vec<OutputEvent> {
-        self.log.clone()
+    /// Returns true if the job has been stopped for running past its deadline.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// The resources the process has used as of the latest sample, should it have been sampled.
+    pub fn usage(&self) -> Option<ResourceUsage> {
+        self.usage
+    }
+
+    /// How much disk space the output takes up, whether spilled or recorded in a store.
+    pub fn storage(&self) -> LogStorage {
+        self.segment
+            .as_ref()
+            .map_or_else(LogStorage::default, Segment::storage)
+    }
+
+    /// Register a waiter that receives the exit status once the process has terminated.
+    /// The exit status is delivered right away if the process has already terminated,
+    /// or once the log is on disk should the job be recorded in a store.
+    pub fn wait(&mut self) -> oneshot::Receiver<ExitStatus> {
+        let (tx, rx) = oneshot::channel();
+
+        match (self.exit, &self.segment) {
+            (Some((status, _)), Some(segment)) if segment.persistent() => {
+                segment.then("failed to notify waiter", move || {
+                    let _ = tx.send(status);
+                    Ok(())
+                })
+            }
+            (Some((status, _)), _) => {
+                let _ = tx.send(status);
+            }
+            (None, _) => self.waiters.push(tx),
+        }
+
+        rx
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Output, OutputEvent};
+    use super::{
+        ExitStatus, LogRange, LogStreams, Output, OutputEvent, RetentionPolicy, TailFrom,
+        MAX_READ_BYTES, MAX_SCAN_BYTES,
+    };
+    use crate::search::LogSearch;
+    use crate::subscriber::{BackpressurePolicy, Disconnected, Overflow, Subscriber};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    /// Receive the next event without its sequence number and time.
+    async fn recv(rx: &mut Subscriber) -> Option<Result<OutputEvent, Disconnected>> {
+        rx.recv().await.map(|event| event.map(|event| event.event))
+    }
+
+    /// Share an output like that of a job, which listeners are registered on.
+    fn shared(output: Output) -> Arc<Mutex<Output>> {
+        Arc::new(Mutex::new(output))
+    }
+
+    fn events(output: &Output) -> Vec<OutputEvent> {
+        output
+            .get_events()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.event)
+            .collect()
+    }
 
     #[tokio::test]
     async fn publish_receive() {
-        let mut output = Output::new();
-        let mut rx = output.tail(false);
-        let event = OutputEvent::Exit(5);
-        output.publish(event.clone());
-        assert_eq!(rx.recv().await, Some(event));
+        let output = shared(Output::new(
+            RetentionPolicy::default(),
+            BackpressurePolicy::default(),
+        ));
+        let mut rx = Output::tail(&output, TailFrom::Now).unwrap();
+        let event = OutputEvent::Exit(ExitStatus::Code(5));
+        output.lock().unwrap().publish(event.clone());
+        assert_eq!(recv(&mut rx).await, Some(Ok(event)));
     }
 
     #[tokio::test]
     async fn publish_receive_past() {
-        let mut output = Output::new();
-        let event = OutputEvent::Exit(5);
+        let mut output = Output::new(RetentionPolicy::default(), BackpressurePolicy::default());
+        let event = OutputEvent::Exit(ExitStatus::Code(5));
         output.publish(event.clone());
-        let mut rx = output.tail(true);
-        assert_eq!(rx.recv().await, Some(event));
+        let mut rx = Output::tail(&shared(output), TailFrom::Start).unwrap();
+        assert_eq!(recv(&mut rx).await, Some(Ok(event)));
+    }
+
+    #[tokio::test]
+    async fn wait_before_and_after_exit() {
+        let mut output = Output::new(RetentionPolicy::default(), BackpressurePolicy::default());
+        let before = output.wait();
+        output.publish(OutputEvent::Exit(ExitStatus::Code(5)));
+        let after = output.wait();
+        assert_eq!(before.await, Ok(ExitStatus::Code(5)));
+        assert_eq!(after.await, Ok(ExitStatus::Code(5)));
+    }
+
+    fn retention(spill: bool) -> RetentionPolicy {
+        RetentionPolicy {
+            memory_limit: 4,
+            spill_directory: if spill {
+                Some(std::env::temp_dir())
+            } else {
+                None
+            },
+            spill_limit: 4,
+        }
+    }
+
+    #[tokio::test]
+    async fn evict_oldest_events() {
+        let mut output = Output::new(retention(false), BackpressurePolicy::default());
+
+        for chunk in &[b"ab", b"cd", b"ef"] {
+            output.publish(OutputEvent::Stdout(chunk.to_vec()));
+        }
+
+        assert_eq!(
+            events(&output),
+            vec![
+                OutputEvent::Truncated { bytes: 2 },
+                OutputEvent::Stdout(b"cd".to_vec()),
+                OutputEvent::Stdout(b"ef".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn spill_evicted_events() {
+        let mut output = Output::new(retention(true), BackpressurePolicy::default());
+
+        for chunk in &[b"ab", b"cd", b"ef", b"gh", b"ij"] {
+            output.publish(OutputEvent::Stdout(chunk.to_vec()));
+        }
+
+        output.publish(OutputEvent::Exit(ExitStatus::Code(0)));
+
+        // The first three chunks are spilled and the oldest of those is discarded once the segment is full.
+        let mut rx = Output::tail(&shared(output), TailFrom::Start).unwrap();
+        let expected = vec![
+            OutputEvent::Truncated { bytes: 2 },
+            OutputEvent::Stdout(b"cd".to_vec()),
+            OutputEvent::Stdout(b"ef".to_vec()),
+            OutputEvent::Stdout(b"gh".to_vec()),
+            OutputEvent::Stdout(b"ij".to_vec()),
+            OutputEvent::Exit(ExitStatus::Code(0)),
+        ];
+
+        for event in expected {
+            assert_eq!(recv(&mut rx).await, Some(Ok(event)));
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_from_sequence() {
+        let mut output = Output::new(retention(false), BackpressurePolicy::default());
+
+        for chunk in &[b"ab", b"cd", b"ef"] {
+            output.publish(OutputEvent::Stdout(chunk.to_vec()));
+        }
+
+        // Resuming right after the latest event only receives what's published from now on.
+        let output = shared(output);
+        let mut caught_up = Output::tail(&output, TailFrom::Sequence(4)).unwrap();
+        let mut behind = Output::tail(&output, TailFrom::Sequence(3)).unwrap();
+        let mut discarded = Output::tail(&output, TailFrom::Sequence(1)).unwrap();
+        output
+            .lock()
+            .unwrap()
+            .publish(OutputEvent::Exit(ExitStatus::Code(0)));
+
+        let exit = OutputEvent::Exit(ExitStatus::Code(0));
+        assert_eq!(recv(&mut caught_up).await, Some(Ok(exit.clone())));
+        assert_eq!(
+            recv(&mut behind).await,
+            Some(Ok(OutputEvent::Stdout(b"ef".to_vec())))
+        );
+        assert_eq!(recv(&mut behind).await, Some(Ok(exit)));
+        assert_eq!(
+            recv(&mut discarded).await,
+            Some(Ok(OutputEvent::Truncated { bytes: 2 }))
+        );
+        assert!(Output::tail(&output, TailFrom::Sequence(6)).is_err());
+    }
+
+    #[tokio::test]
+    async fn resume_into_spilled_events() {
+        let mut output = Output::new(retention(true), BackpressurePolicy::default());
+
+        for chunk in &[b"ab", b"cd", b"ef", b"gh", b"ij"] {
+            output.publish(stdout(*chunk));
+        }
+
+        // The first chunk has been discarded, the next two are spilled and the rest are in memory.
+        let first = |sequence| -> (u64, OutputEvent) {
+            let events = output.events_from(sequence).unwrap();
+            (events[0].sequence, events[0].event.clone())
+        };
+
+        assert_eq!(first(1), (1, OutputEvent::Truncated { bytes: 2 }));
+        assert_eq!(first(2), (2, stdout(b"cd")));
+        assert_eq!(first(3), (3, stdout(b"ef")));
+        assert_eq!(first(4), (4, stdout(b"gh")));
+        assert!(output.events_from(6).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_ranges_across_segment() {
+        let retention = RetentionPolicy {
+            spill_limit: 1024,
+            ..retention(true)
+        };
+
+        // Everything but the last event is spilled to disk.
+        let mut output = Output::new(retention, BackpressurePolicy::default());
+        output.publish(stdout(b"a\nb"));
+        output.publish(OutputEvent::Stderr(b"x\n".to_vec()));
+        output.publish(stdout(b"\nc\n"));
+        output.publish(stdout(b"d\n"));
+
+        let read = |streams, range| -> Vec<(u64, OutputEvent)> {
+            output
+                .snapshot()
+                .read(streams, range)
+                .unwrap()
+                .events
+                .into_iter()
+                .map(|event| (event.sequence, event.event))
+                .collect()
+        };
+
+        assert_eq!(
+            read(LogStreams::Stdout, LogRange::Head(2)),
+            vec![(1, stdout(b"a\nb")), (3, stdout(b"\n"))]
+        );
+        assert_eq!(
+            read(LogStreams::Stdout, LogRange::Tail(2)),
+            vec![(3, stdout(b"c\n")), (4, stdout(b"d\n"))]
+        );
+        assert_eq!(
+            read(
+                LogStreams::Stdout,
+                LogRange::Bytes {
+                    offset: 2,
+                    length: 3
+                }
+            ),
+            vec![(1, stdout(b"b")), (3, stdout(b"\nc"))]
+        );
+        assert_eq!(
+            read(LogStreams::Both, LogRange::Head(2)),
+            vec![
+                (1, stdout(b"a\nb")),
+                (2, OutputEvent::Stderr(b"x\n".to_vec()))
+            ]
+        );
+        assert_eq!(
+            read(LogStreams::Stderr, LogRange::Tail(5)),
+            vec![(2, OutputEvent::Stderr(b"x\n".to_vec()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn compress_spilled_frames() {
+        let retention = RetentionPolicy {
+            spill_limit: 1024 * 1024,
+            ..retention(true)
+        };
+
+        // Enough lines to fill a few frames, all but the last of which end up compressed on disk.
+        let mut output = Output::new(retention, BackpressurePolicy::default());
+        let lines: Vec<_> = (0..3000)
+            .map(|line| format!("compiling crate number {}\n", line).into_bytes())
+            .collect();
+
+        for line in &lines {
+            output.publish(OutputEvent::Stdout(line.clone()));
+        }
+
+        let storage = output.storage();
+        assert!(storage.compressed_bytes > 0);
+        assert!(storage.compressed_bytes * 4 < storage.raw_bytes);
+
+        let stdout: Vec<_> = events(&output)
+            .into_iter()
+            .map(|event| match event {
+                OutputEvent::Stdout(bytes) => bytes,
+                _ => Vec::new(),
+            })
+            .collect();
+
+        assert_eq!(stdout, lines);
+        assert_eq!(
+            output
+                .snapshot()
+                .read(LogStreams::Stdout, LogRange::Tail(1))
+                .unwrap()
+                .events
+                .into_iter()
+                .map(|event| (event.sequence, event.event))
+                .collect::<Vec<_>>(),
+            vec![(3000, OutputEvent::Stdout(lines[2999].clone()))]
+        );
+        assert_eq!(
+            output
+                .snapshot()
+                .read(LogStreams::Stdout, LogRange::Head(1000))
+                .unwrap()
+                .events[999]
+                .event,
+            OutputEvent::Stdout(lines[999].clone())
+        );
+
+        // Resuming in the middle of the frames only reads back the events from there on.
+        let mut rx = Output::tail(&shared(output), TailFrom::Sequence(2500)).unwrap();
+        let event = rx.recv().await.unwrap().unwrap();
+        assert_eq!(
+            (event.sequence, event.event),
+            (2500, OutputEvent::Stdout(lines[2499].clone()))
+        );
+    }
+
+    #[tokio::test]
+    async fn cap_reads() {
+        let mut output = Output::new(RetentionPolicy::default(), BackpressurePolicy::default());
+        for byte in b"abc" {
+            output.publish(stdout(&[*byte; 600 * 1024]));
+        }
+
+        let read = |range| -> Vec<Vec<u8>> {
+            output
+                .snapshot()
+                .read(LogStreams::Stdout, range)
+                .unwrap()
+                .events
+                .into_iter()
+                .map(|event| match event.event {
+                    OutputEvent::Stdout(bytes) => bytes,
+                    _ => Vec::new(),
+                })
+                .collect()
+        };
+
+        let head = read(LogRange::Head(10));
+        assert_eq!(head[0], vec![b'a'; 600 * 1024]);
+        assert_eq!(head[1], vec![b'b'; 424 * 1024]);
+
+        let tail = read(LogRange::Tail(10));
+        assert_eq!(tail[0], vec![b'b'; 424 * 1024]);
+        assert_eq!(tail[1], vec![b'c'; 600 * 1024]);
+
+        let bytes = read(LogRange::Bytes {
+            offset: 1024,
+            length: u64::MAX,
+        });
+        assert_eq!(bytes.concat().len(), MAX_READ_BYTES as usize);
+    }
+
+    #[tokio::test]
+    async fn bound_scans() {
+        let retention = RetentionPolicy {
+            memory_limit: 80 * 1024 * 1024,
+            ..RetentionPolicy::default()
+        };
+
+        // The only line of stderr is further from the start than a read looks.
+        let mut output = Output::new(retention, BackpressurePolicy::default());
+        for _ in 0..MAX_SCAN_BYTES / MAX_READ_BYTES {
+            output.publish(stdout(&[b'a'; MAX_READ_BYTES as usize]));
+        }
+
+        output.publish(OutputEvent::Stderr(b"error\n".to_vec()));
+
+        let snapshot = output.snapshot();
+        let read = |range| snapshot.read(LogStreams::Stderr, range).unwrap().events;
+
+        assert!(read(LogRange::Head(1)).is_empty());
+        assert_eq!(
+            read(LogRange::Tail(1))[0].event,
+            OutputEvent::Stderr(b"error\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn page_byte_reads() {
+        let retention = RetentionPolicy {
+            memory_limit: 2 * 1024 * 1024,
+            ..RetentionPolicy::default()
+        };
+
+        // The first megabyte is evicted and what's left is more than a single read returns.
+        let mut output = Output::new(retention, BackpressurePolicy::default());
+        let mut published = Vec::new();
+        for i in 0..48u8 {
+            let chunk = vec![b'a' + i % 26; 64 * 1024];
+            published.extend_from_slice(&chunk);
+            output.publish(stdout(&chunk));
+        }
+
+        let snapshot = output.snapshot();
+        let mut offset = 0;
+        let mut pages = Vec::new();
+        let mut bytes = Vec::new();
+        loop {
+            let read = snapshot
+                .read(
+                    LogStreams::Stdout,
+                    LogRange::Bytes {
+                        offset,
+                        length: u64::MAX,
+                    },
+                )
+                .unwrap();
+            if read.offsets.end == offset {
+                break;
+            }
+
+            pages.push(read.offsets.clone());
+            for event in read.events {
+                if let OutputEvent::Stdout(chunk) = event.event {
+                    bytes.extend(chunk);
+                }
+            }
+            offset = read.offsets.end;
+        }
+
+        let start = pages[0].start;
+        assert!(start > 0);
+        assert!(pages.len() > 1);
+        assert!(pages.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert_eq!(bytes, published[start as usize..]);
+    }
+
+    #[tokio::test]
+    async fn search_snapshot() {
+        let retention = RetentionPolicy {
+            memory_limit: 16,
+            spill_limit: 1024,
+            ..retention(true)
+        };
+
+        // The first line ends up on disk and the second one in memory.
+        let mut output = Output::new(retention, BackpressurePolicy::default());
+        output.publish(stdout(b"error: a\n"));
+        output.publish(stdout(b"ok\nerror: b\n"));
+
+        let snapshot = output.snapshot();
+        output.publish(stdout(b"error: c\n"));
+
+        let lines: Vec<_> = snapshot
+            .search(&LogSearch::substring("error"))
+            .unwrap()
+            .into_iter()
+            .map(|line| (line.sequence, line.event))
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![(1, stdout(b"error: a")), (2, stdout(b"error: b"))]
+        );
+    }
+
+    fn backpressure(overflow: Overflow) -> BackpressurePolicy {
+        BackpressurePolicy {
+            capacity: 2,
+            overflow,
+        }
+    }
+
+    fn stdout(bytes: &[u8]) -> OutputEvent {
+        OutputEvent::Stdout(bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_with_gap() {
+        let output = shared(Output::new(
+            RetentionPolicy::default(),
+            backpressure(Overflow::DropOldest),
+        ));
+        let mut rx = Output::tail(&output, TailFrom::Now).unwrap();
+
+        for chunk in &[b"a", b"b", b"c", b"d"] {
+            output.lock().unwrap().publish(stdout(*chunk));
+        }
+
+        assert_eq!(
+            recv(&mut rx).await,
+            Some(Ok(OutputEvent::Gap { events: 2 }))
+        );
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"c"))));
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"d"))));
+    }
+
+    #[tokio::test]
+    async fn number_events_in_order() {
+        let output = shared(Output::new(
+            retention(false),
+            backpressure(Overflow::DropOldest),
+        ));
+        let mut rx = Output::tail(&output, TailFrom::Now).unwrap();
+
+        for chunk in &[b"ab", b"cd", b"ef", b"gh"] {
+            output.lock().unwrap().publish(stdout(*chunk));
+        }
+
+        // Gap and truncation events carry the number of the last event they stand in for.
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let event = rx.recv().await.unwrap().unwrap();
+            received.push((event.sequence, event.event));
+        }
+
+        let past: Vec<_> = output
+            .lock()
+            .unwrap()
+            .get_events()
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.sequence, event.event))
+            .collect();
+
+        let expected = [(3, stdout(b"ef")), (4, stdout(b"gh"))];
+
+        assert_eq!(received[0], (2, OutputEvent::Gap { events: 2 }));
+        assert_eq!(received[1..], expected[..]);
+        assert_eq!(past[0], (2, OutputEvent::Truncated { bytes: 4 }));
+        assert_eq!(past[1..], expected[..]);
+    }
+
+    #[tokio::test]
+    async fn disconnect_slow_subscriber() {
+        let output = shared(Output::new(
+            RetentionPolicy::default(),
+            backpressure(Overflow::Disconnect),
+        ));
+        let mut slow = Output::tail(&output, TailFrom::Now).unwrap();
+        output.lock().unwrap().publish(stdout(b"a"));
+        output.lock().unwrap().publish(stdout(b"b"));
+
+        // Other subscribers are unaffected by the one that falls behind.
+        let mut fast = Output::tail(&output, TailFrom::Now).unwrap();
+        output.lock().unwrap().publish(stdout(b"c"));
+
+        assert_eq!(recv(&mut slow).await, Some(Err(Disconnected)));
+        assert_eq!(recv(&mut slow).await, None);
+        assert_eq!(recv(&mut fast).await, Some(Ok(stdout(b"c"))));
+    }
+
+    #[tokio::test]
+    async fn block_until_subscriber_catches_up() {
+        let output = shared(Output::new(
+            RetentionPolicy::default(),
+            backpressure(Overflow::Block),
+        ));
+        let mut rx = Output::tail(&output, TailFrom::Now).unwrap();
+        output.lock().unwrap().publish(stdout(b"a"));
+        assert_eq!(poll_once(output.lock().unwrap().ready()), Poll::Ready(()));
+        output.lock().unwrap().publish(stdout(b"b"));
+
+        let mut ready = output.lock().unwrap().ready();
+        assert_eq!(poll_once(&mut ready), Poll::Pending);
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"a"))));
+        assert_eq!(poll_once(&mut ready), Poll::Ready(()));
+
+        // Subscribers going away don't hold up publishing either.
+        output.lock().unwrap().publish(stdout(b"c"));
+        let mut ready = output.lock().unwrap().ready();
+        drop(rx);
+        assert_eq!(poll_once(&mut ready), Poll::Ready(()));
+    }
+
+    #[tokio::test]
+    async fn catch_up_a_queue_at_a_time() {
+        let output = shared(Output::new(
+            RetentionPolicy::default(),
+            backpressure(Overflow::Disconnect),
+        ));
+
+        for chunk in &[b"a", b"b", b"c", b"d", b"e"] {
+            output.lock().unwrap().publish(stdout(*chunk));
+        }
+
+        // Past events neither count as falling behind nor hold up publishing while they're read.
+        let mut rx = Output::tail(&output, TailFrom::Start).unwrap();
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"a"))));
+        output.lock().unwrap().publish(stdout(b"f"));
+        assert_eq!(poll_once(output.lock().unwrap().ready()), Poll::Ready(()));
+
+        for chunk in &[b"b", b"c", b"d", b"e", b"f"] {
+            assert_eq!(recv(&mut rx).await, Some(Ok(stdout(*chunk))));
+        }
+
+        // Once caught up, published events are queued as usual.
+        output.lock().unwrap().publish(stdout(b"g"));
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"g"))));
+    }
+
+    #[tokio::test]
+    async fn catch_up_past_discarded_events() {
+        let backpressure = BackpressurePolicy {
+            capacity: 1,
+            overflow: Overflow::DropOldest,
+        };
+
+        let output = shared(Output::new(retention(true), backpressure));
+        for chunk in &[b"ab", b"cd", b"ef"] {
+            output.lock().unwrap().publish(stdout(*chunk));
+        }
+
+        let mut rx = Output::tail(&output, TailFrom::Start).unwrap();
+        assert_eq!(recv(&mut rx).await, Some(Ok(stdout(b"ab"))));
+
+        // What's discarded before it has been read is stood in for by a gap.
+        for chunk in &[b"gh", b"ij", b"kl", b"mn"] {
+            output.lock().unwrap().publish(stdout(*chunk));
+        }
+
+        let expected = vec![
+            (3, OutputEvent::Gap { events: 2 }),
+            (4, stdout(b"gh")),
+            (5, stdout(b"ij")),
+            (6, stdout(b"kl")),
+            (7, stdout(b"mn")),
+        ];
+
+        for expected in expected {
+            let event = rx.recv().await.unwrap().unwrap();
+            assert_eq!((event.sequence, event.event), expected);
+        }
+    }
+
+    fn poll_once<F: Future + Unpin>(mut future: F) -> Poll<F::Output> {
+        let waker = futures::task::noop_waker();
+        Pin::new(&mut future).poll(&mut Context::from_waker(&waker))
     }
 }
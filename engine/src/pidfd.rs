@@ -0,0 +1,45 @@
+use anyhow::Result;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::io::unix::AsyncFd;
+
+/// A file descriptor referring to a process, which becomes readable once the process has exited.
+/// Unlike waiting on a pid, this works for processes that aren't our children and isn't fooled by pid reuse.
+#[derive(Debug)]
+pub struct PidFd(AsyncFd<Fd>);
+
+#[derive(Debug)]
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl PidFd {
+    pub fn open(pid: libc::pid_t) -> Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+
+        if fd == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self(AsyncFd::new(Fd(fd as RawFd))?))
+    }
+
+    /// Resolves once the process has exited.
+    pub async fn exited(&self) -> io::Result<()> {
+        // Readiness is kept when the guard is dropped, which is what we want since a process only exits once.
+        let _guard = self.0.readable().await?;
+        Ok(())
+    }
+}
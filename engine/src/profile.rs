@@ -0,0 +1,377 @@
+use crate::syscalls::{LEGACY_SYSCALLS, SYSCALLS};
+use anyhow::{anyhow, Error, Result};
+use std::io;
+use std::str::FromStr;
+
+/// The names of the capabilities the kernel knows about, indexed by their number.
+const CAPABILITIES: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+/// The highest capability number there's room for in the sets passed to `capget` and `capset`.
+const MAX_CAPABILITY: u32 = 63;
+
+/// The version of the `capget` and `capset` interface with 64 bit capability sets.
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Identifies the architecture a syscall was made for in the data seccomp filters inspect.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls of the x32 ABI have this bit set in their number and would otherwise slip past the filter.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+/// The offsets of the syscall number and architecture in `struct seccomp_data`.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+/// A capability, which can be parsed from its name with or without the `CAP_` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability(pub u32);
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_uppercase();
+        let name = name.trim_start_matches("CAP_");
+        CAPABILITIES
+            .iter()
+            .position(|capability| *capability == name)
+            .map(|number| Capability(number as u32))
+            .ok_or_else(|| anyhow!("unknown capability {}", s))
+    }
+}
+
+/// A syscall, which can be parsed from its name as found in `man 2 syscalls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syscall(pub libc::c_long);
+
+impl FromStr for Syscall {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim();
+        SYSCALLS
+            .iter()
+            .chain(LEGACY_SYSCALLS)
+            .find(|(syscall, _)| *syscall == name)
+            .map(|(_, number)| Syscall(*number))
+            .ok_or_else(|| anyhow!("unknown syscall {}", s))
+    }
+}
+
+/// What happens when a job makes a syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    Allow,
+
+    /// Fail the syscall with `EPERM`.
+    Errno,
+
+    /// Kill the job with `SIGSYS`.
+    Kill,
+}
+
+impl SeccompAction {
+    fn ret(self) -> u32 {
+        match self {
+            Self::Allow => SECCOMP_RET_ALLOW,
+            Self::Errno => SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            Self::Kill => SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// A seccomp filter that takes one action for the listed syscalls and another for all others,
+/// so that it serves both as a deny list and as an allow list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeccompFilter {
+    pub syscalls: Vec<Syscall>,
+
+    /// What happens when the job makes one of the listed syscalls.
+    pub action: SeccompAction,
+
+    /// What happens when the job makes any other syscall.
+    pub default_action: SeccompAction,
+}
+
+impl SeccompFilter {
+    /// Compile the filter to a BPF program for the architecture the engine is built for.
+    fn compile(&self) -> Result<Vec<SockFilter>> {
+        let arch = AUDIT_ARCH
+            .ok_or_else(|| anyhow!("seccomp filters aren't supported on this architecture"))?;
+
+        let mut program = vec![
+            // Syscall numbers differ between architectures so anything but the native one is killed.
+            SockFilter::statement(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+            SockFilter::jump(BPF_JMP_JEQ_K, arch, 1, 0),
+            SockFilter::statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+            SockFilter::statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+        ];
+
+        if let Some(x32_syscall_bit) = X32_SYSCALL_BIT {
+            program.push(SockFilter::jump(BPF_JMP_JGE_K, x32_syscall_bit, 0, 1));
+            program.push(SockFilter::statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS));
+        }
+
+        for syscall in &self.syscalls {
+            program.push(SockFilter::jump(BPF_JMP_JEQ_K, syscall.0 as u32, 0, 1));
+            program.push(SockFilter::statement(BPF_RET_K, self.action.ret()));
+        }
+
+        program.push(SockFilter::statement(BPF_RET_K, self.default_action.ret()));
+        Ok(program)
+    }
+}
+
+/// Restrictions the server defines under a name for jobs to be spawned with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecurityProfile {
+    /// The capabilities kept in the bounding set of the job, every other capability is dropped for good.
+    /// The bounding set is left alone if unset.
+    pub capabilities: Option<Vec<Capability>>,
+
+    /// The seccomp filter installed for the job.
+    pub seccomp: Option<SeccompFilter>,
+}
+
+/// `struct sock_filter`, a single BPF instruction.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+impl SockFilter {
+    fn statement(code: u16, k: u32) -> Self {
+        Self {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+/// `struct sock_fprog`, a BPF program.
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+/// `struct __user_cap_header_struct`.
+#[repr(C)]
+struct CapabilityHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// `struct __user_cap_data_struct`, of which there are two for the lower and upper 32 capabilities.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapabilityData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// A security profile prepared up front since allocating isn't permitted in the forked child.
+#[derive(Debug)]
+pub struct Restrictions {
+    /// The capabilities kept in the bounding set, as a bit mask.
+    capabilities: Option<u64>,
+
+    filter: Option<Vec<SockFilter>>,
+}
+
+impl Restrictions {
+    pub fn new(profile: &SecurityProfile) -> Result<Self> {
+        let capabilities = profile.capabilities.as_ref().map(|capabilities| {
+            capabilities
+                .iter()
+                .filter(|capability| capability.0 <= MAX_CAPABILITY)
+                .fold(0, |mask, capability| mask | 1 << capability.0)
+        });
+
+        let filter = match &profile.seccomp {
+            Some(seccomp) => Some(seccomp.compile()?),
+            None => None,
+        };
+
+        Ok(Self {
+            capabilities,
+            filter,
+        })
+    }
+
+    /// Drop every capability that isn't kept from the bounding set, as well as from the inheritable and
+    /// ambient sets since those would otherwise carry them past exec. This needs `CAP_SETPCAP` and
+    /// must therefore happen before the user is switched.
+    ///
+    /// This runs in the forked child before exec and must therefore be async-signal-safe.
+    pub fn drop_capabilities(&self) -> io::Result<()> {
+        let keep = match self.capabilities {
+            Some(keep) => keep,
+            None => return Ok(()),
+        };
+
+        unsafe {
+            for capability in 0..=MAX_CAPABILITY {
+                // Capabilities the kernel doesn't know about fail with EINVAL, which is fine.
+                if keep & 1 << capability == 0
+                    && libc::prctl(libc::PR_CAPBSET_DROP, capability as libc::c_ulong, 0, 0, 0)
+                        == -1
+                    && *libc::__errno_location() != libc::EINVAL
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            // Kernels without ambient capabilities don't have any to clear.
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+                0,
+                0,
+                0,
+            );
+
+            let mut header = CapabilityHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+
+            let mut data = [CapabilityData::default(); 2];
+            if libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            data[0].inheritable &= keep as u32;
+            data[1].inheritable &= (keep >> 32) as u32;
+            if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Install the seccomp filter. This forbids gaining privileges through exec, which is a requirement for
+    /// installing a filter without `CAP_SYS_ADMIN`, and should therefore be the last thing done before exec.
+    ///
+    /// This runs in the forked child before exec and must therefore be async-signal-safe.
+    pub fn install_filter(&self) -> io::Result<()> {
+        let filter = match &self.filter {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+
+        let program = SockFprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_ptr(),
+        };
+
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == -1
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                    &program as *const SockFprog,
+                ) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capability, Syscall};
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(
+            "CAP_SYS_ADMIN".parse::<Capability>().unwrap(),
+            Capability(21)
+        );
+        assert_eq!(
+            "net_bind_service".parse::<Capability>().unwrap(),
+            Capability(10)
+        );
+        assert!("CAP_NONSENSE".parse::<Capability>().is_err());
+
+        assert_eq!(
+            "uname".parse::<Syscall>().unwrap(),
+            Syscall(libc::SYS_uname)
+        );
+        assert!("nonsense".parse::<Syscall>().is_err());
+    }
+}
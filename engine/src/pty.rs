@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr;
+
+/// A pseudo-terminal. The job gets the slave side as its controlling terminal
+/// while we read its output from and write its input to the master side.
+#[derive(Debug)]
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let pty = unsafe {
+            Self {
+                master: File::from_raw_fd(master),
+                slave: File::from_raw_fd(slave),
+            }
+        };
+
+        // Neither side should leak into the job, the slave side is handed to it explicitly as stdio.
+        set_cloexec(&pty.master)?;
+        set_cloexec(&pty.slave)?;
+        Ok(pty)
+    }
+}
+
+/// Set the window size of a terminal, which also delivers SIGWINCH to its foreground process group.
+pub fn resize(terminal: &File, rows: u16, cols: u16) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    if unsafe { libc::ioctl(terminal.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
+/// Make the terminal on stdin the controlling terminal of the calling process.
+/// The process must be a session leader. This is async-signal-safe and meant to be called before exec.
+pub fn set_controlling_terminal() -> io::Result<()> {
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn set_cloexec(file: &File) -> Result<()> {
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pty;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn resize_reported_on_slave() {
+        let pty = Pty::open().unwrap();
+        super::resize(&pty.master, 30, 100).unwrap();
+
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        unsafe { libc::ioctl(pty.slave.as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+        assert_eq!((size.ws_row, size.ws_col), (30, 100));
+    }
+}
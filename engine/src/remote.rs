@@ -1,14 +1,35 @@
-use crate::output::{Output, OutputEvent};
+use crate::cgroup::{self, Cgroup};
+use crate::credentials::Credentials;
+use crate::follow::Follow;
+use crate::framing::{Framer, LineFraming};
+use crate::namespace::Namespaces;
+use crate::output::{ExitStatus, Output, OutputEvent};
+use crate::pidfd::PidFd;
+use crate::profile::{Restrictions, SecurityProfile};
+use crate::pty::{self, Pty};
+use crate::rlimit::{self, Rlimit};
+use crate::store::OutputFiles;
+use crate::tree;
+use crate::usage::{self, ResourceUsage};
+use crate::SpawnOptions;
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::{
-    io::AsyncReadExt,
-    process::{Child, Command},
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStdin, Command},
     select,
-    sync::oneshot,
+    sync::{self, oneshot},
     task,
+    time::{self, Instant},
 };
 
 /// The buffer size used for reading from stdout and stderr.
@@ -17,134 +38,751 @@ use tokio::{
 /// to stdout/stderr.
 const READ_BUFFER_SIZE: usize = 1024;
 
+/// The character a terminal reads as end-of-file by default, which is Ctrl-D.
+const EOF_CHARACTER: u8 = 0x04;
+
+/// A stream of output from a job, either a pipe or a terminal.
+type OutputReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// How long the remaining output of a job is waited for after it has exited.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a job is given to exit after the initial stop signal by default.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Describes how a job is stopped. The initial signal is delivered to the job's process group
+/// and if the job hasn't exited once the grace period is over, the escalation signal is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StopPolicy {
+    pub signal: i32,
+    pub grace_period: Duration,
+    pub escalation_signal: i32,
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        Self {
+            signal: libc::SIGTERM,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            escalation_signal: libc::SIGKILL,
+        }
+    }
+}
+
+impl StopPolicy {
+    /// Check that both signals are valid signal numbers.
+    pub fn validate(&self) -> Result<()> {
+        for &signal in &[self.signal, self.escalation_signal] {
+            if signal <= 0 || signal > libc::SIGRTMAX() {
+                return Err(anyhow!("invalid signal {}", signal));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The point in time a job is stopped at should it still be running, along with how it's stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deadline {
+    pub at: SystemTime,
+    pub policy: StopPolicy,
+}
+
+/// Where input written to a job ends up.
+#[derive(Debug)]
+enum Input {
+    Pipe(ChildStdin),
+    Terminal(fs::File),
+}
+
+/// A shared handle to the stdin of a job that any number of attached clients can write to.
+/// Closing it delivers end-of-file to the job.
+#[derive(Debug, Clone)]
+pub struct Stdin {
+    input: Arc<sync::Mutex<Option<Input>>>,
+
+    /// The master side of the terminal of the job, if it has one.
+    terminal: Option<Arc<File>>,
+}
+
+impl Stdin {
+    fn pipe(pipe: ChildStdin) -> Self {
+        Self {
+            input: Arc::new(sync::Mutex::new(Some(Input::Pipe(pipe)))),
+            terminal: None,
+        }
+    }
+
+    fn terminal(master: &File) -> Result<Self> {
+        let writer = fs::File::from_std(master.try_clone()?);
+
+        Ok(Self {
+            input: Arc::new(sync::Mutex::new(Some(Input::Terminal(writer)))),
+            terminal: Some(Arc::new(master.try_clone()?)),
+        })
+    }
+
+    /// Write all of the given bytes to the job. This waits for the job to read them
+    /// should the pipe be full.
+    pub async fn write(&self, bytes: &[u8]) -> Result<()> {
+        let mut input = self.input.lock().await;
+        let input = input
+            .as_mut()
+            .ok_or_else(|| anyhow!("stdin has already been closed"))?;
+
+        match input {
+            Input::Pipe(pipe) => {
+                pipe.write_all(bytes).await?;
+                pipe.flush().await?;
+            }
+            Input::Terminal(terminal) => {
+                terminal.write_all(bytes).await?;
+                terminal.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Close stdin, after which the job reads end-of-file.
+    ///
+    /// A terminal stays open for as long as the job runs so jobs with a terminal
+    /// are sent the end-of-file character instead, like when pressing Ctrl-D.
+    pub async fn close(&self) {
+        let mut input = self.input.lock().await;
+
+        if let Some(Input::Terminal(terminal)) = input.as_mut() {
+            let _ = terminal.write_all(&[EOF_CHARACTER]).await;
+            let _ = terminal.flush().await;
+        }
+
+        input.take();
+    }
+
+    /// Change the window size of the terminal of the job.
+    pub fn resize(&self, rows: u16, cols: u16) -> Result<()> {
+        let terminal = self
+            .terminal
+            .as_ref()
+            .ok_or_else(|| anyhow!("job does not have a terminal"))?;
+
+        pty::resize(terminal, rows, cols)
+    }
+}
+
+/// What a process is confined by before it executes.
+#[derive(Debug, Default)]
+pub struct Sandbox {
+    /// The cgroup the process is placed in, if it has resource limits.
+    pub cgroup: Option<Cgroup>,
+
+    /// The user the process runs as. It runs as the user of the engine if unset.
+    pub credentials: Option<Credentials>,
+
+    /// The capabilities and syscalls the process is restricted to, on top of what its user is allowed.
+    pub profile: Option<SecurityProfile>,
+
+    /// The per-process resource limits of the process.
+    pub rlimits: Vec<(Rlimit, u64)>,
+}
+
+/// The process a remote monitors.
+#[derive(Debug)]
+enum Process {
+    /// A process spawned by this remote, which is reaped once it exits.
+    Child(Child),
+
+    /// A process spawned before the engine was restarted. It's no longer our child
+    /// so all we get to know is when it has exited.
+    Orphan(PidFd),
+}
+
+/// Where the output of a process is read from.
+#[derive(Debug)]
+enum Source {
+    /// The stdout and stderr pipes of the child.
+    Pipes,
+
+    /// The master side of the terminal of the process, which merges stdout and stderr.
+    Terminal(File),
+
+    /// The files the process writes its stdout and stderr to, positioned where reading continues.
+    Files(File, File),
+}
+
 /// A remote is a sort of overwatch that monitors a process.
 /// It manages starting, stopping and streaming stdout/stderr + exit as events to an `Output`.
 #[derive(Debug)]
 pub struct Remote {
-    /// The RAII handle to the child process.
-    child: Option<Child>,
+    /// The process being monitored, taken once the events processor is spawned.
+    process: Option<Process>,
+
+    kill_switch: Option<oneshot::Sender<StopPolicy>>,
+    kill_switch_rx: Option<oneshot::Receiver<StopPolicy>>,
+
+    /// The stdin of the process, if it was spawned with stdin open or with a terminal.
+    stdin: Option<Stdin>,
+
+    /// Where the output of the process is read from, taken once the events processor is spawned.
+    source: Option<Source>,
+
+    /// The cgroup the process was placed in, if it has resource limits.
+    cgroup: Option<Cgroup>,
 
-    kill_switch: Option<oneshot::Sender<()>>,
-    kill_switch_rx: Option<oneshot::Receiver<()>>,
+    /// The id of the process group and session the process leads.
+    pgid: libc::pid_t,
+
+    /// Set once the process has exited and its process tree has been cleaned up.
+    exited: Arc<AtomicBool>,
+
+    /// How output is split into events, should it be published as whole lines.
+    framing: Option<LineFraming>,
 }
 
 impl Remote {
-    /// Creates a new remote with
+    /// Creates a new remote with a freshly spawned process. Its output is written to the given files
+    /// rather than to pipes should there be any, unless it's spawned with a terminal.
     pub fn new(
         program: &str,
         working_directory: &str,
         args: &[String],
         envs: &HashMap<String, String>,
+        sandbox: Sandbox,
+        options: &SpawnOptions,
+        output_files: Option<&OutputFiles>,
     ) -> Result<Self> {
+        let Sandbox {
+            cgroup,
+            credentials,
+            profile,
+            rlimits,
+        } = sandbox;
+
+        let restrictions = match &profile {
+            Some(profile) => Some(Arc::new(Restrictions::new(profile)?)),
+            None => None,
+        };
+
+        let namespaces = if options.isolation.is_empty() {
+            None
+        } else {
+            Some(Namespaces::new(&options.isolation, working_directory)?)
+        };
+
+        // The working directory of a job with a root filesystem of its own is only entered once it's in there.
         let mut command = Command::new(program);
-        command
-            .current_dir(working_directory)
-            .args(args)
-            .envs(envs)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let child = command.spawn()?;
+        command.args(args).envs(envs);
+        if !namespaces.as_ref().map_or(false, Namespaces::has_root) {
+            command.current_dir(working_directory);
+        }
+
+        // A job with a terminal has it as its stdin, stdout and stderr. Otherwise output is piped and
+        // jobs don't get to read from the stdin of the server unless it's been opened up for clients to write to.
+        let pty = if options.tty {
+            let pty = Pty::open()?;
+            command
+                .stdin(pty.slave.try_clone()?)
+                .stdout(pty.slave.try_clone()?)
+                .stderr(pty.slave.try_clone()?);
+
+            Some(pty)
+        } else {
+            let stdin = if options.open_stdin {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            };
+
+            command.stdin(stdin);
+
+            // Output written to files outlives the server, unlike output written to pipes.
+            match output_files {
+                Some(files) => {
+                    command
+                        .stdout(open_for_append(&files.stdout)?)
+                        .stderr(open_for_append(&files.stderr)?);
+                }
+                None => {
+                    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                }
+            }
+
+            None
+        };
+
+        // Put the child in a session and process group of its own so that stop signals reach
+        // everything it spawns and so that it's detached from the terminal of the server.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() != -1 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+
+        // The new session has no controlling terminal so give it the one we've allocated for it.
+        if pty.is_some() {
+            unsafe {
+                command.pre_exec(pty::set_controlling_terminal);
+            }
+        }
+
+        // Move the child into its cgroup before exec so that the limits apply from the very start.
+        if let Some(procs_fd) = cgroup.as_ref().map(Cgroup::procs_fd) {
+            unsafe {
+                command.pre_exec(move || cgroup::join(procs_fd));
+            }
+        }
+
+        // Namespaces are entered once in the cgroup so that everything forked off on the way is accounted for.
+        if let Some(namespaces) = namespaces {
+            unsafe {
+                command.pre_exec(move || namespaces.enter());
+            }
+        }
+
+        if !rlimits.is_empty() {
+            unsafe {
+                command.pre_exec(move || rlimit::apply(&rlimits));
+            }
+        }
+
+        // Dropping capabilities needs the privileges of the engine while the seccomp filter could
+        // forbid any of the syscalls made on the way to exec, so they go around switching the user.
+        if let Some(restrictions) = restrictions.clone() {
+            unsafe {
+                command.pre_exec(move || restrictions.drop_capabilities());
+            }
+        }
+
+        // Switch the user once everything that needs the privileges of the engine is done.
+        if let Some(credentials) = credentials {
+            unsafe {
+                command.pre_exec(move || credentials.apply());
+            }
+        }
+
+        if let Some(restrictions) = restrictions {
+            unsafe {
+                command.pre_exec(move || restrictions.install_filter());
+            }
+        }
+
+        let mut child = command.spawn()?;
+
+        // Only the master side of the terminal is kept around, the child has its own copies of the slave side
+        // and it's important that those are the only ones so that reading from the master ends once the job is gone.
+        let (stdin, source) = match (pty, output_files) {
+            (Some(pty), _) => (
+                Some(Stdin::terminal(&pty.master)?),
+                Source::Terminal(pty.master),
+            ),
+            (None, Some(files)) => (
+                child.stdin.take().map(Stdin::pipe),
+                Source::Files(File::open(&files.stdout)?, File::open(&files.stderr)?),
+            ),
+            (None, None) => (child.stdin.take().map(Stdin::pipe), Source::Pipes),
+        };
+
         let (kill_switch, kill_switch_rx) = oneshot::channel();
 
+        // The child leads its own process group so the group id is the same as its pid.
+        let pgid = child
+            .id()
+            .ok_or_else(|| anyhow!("process has already been reaped"))?
+            as libc::pid_t;
+
         Ok(Self {
-            child: Some(child),
+            process: Some(Process::Child(child)),
             kill_switch: Some(kill_switch),
             kill_switch_rx: Some(kill_switch_rx),
+            stdin,
+            source: Some(source),
+            cgroup,
+            pgid,
+            exited: Arc::new(AtomicBool::new(false)),
+            framing: options.line_framing,
         })
     }
 
-    /// Sends SIGINT to a process should it still be running. This allows it to perform a graceful exit.
-    pub fn stop(&mut self) -> Result<()> {
+    /// Creates a remote monitoring a process that was spawned before the engine was restarted, given its pid
+    /// and start time. Its output is read from the files it writes to, continuing at the given offsets.
+    pub fn reattach(
+        pid: libc::pid_t,
+        start_time: u64,
+        output_files: &OutputFiles,
+        offsets: (u64, u64),
+        cgroup: Option<Cgroup>,
+        framing: Option<LineFraming>,
+    ) -> Result<Self> {
+        // Check the start time only once the pidfd is open so that the pidfd is known to refer to the
+        // process that was spawned rather than to one that got its pid after it exited.
+        let pidfd = PidFd::open(pid)?;
+
+        if tree::start_time(pid) != Some(start_time) {
+            return Err(anyhow!("process has already exited"));
+        }
+
+        let mut stdout = File::open(&output_files.stdout)?;
+        let mut stderr = File::open(&output_files.stderr)?;
+        stdout.seek(SeekFrom::Start(offsets.0))?;
+        stderr.seek(SeekFrom::Start(offsets.1))?;
+
+        let (kill_switch, kill_switch_rx) = oneshot::channel();
+
+        Ok(Self {
+            process: Some(Process::Orphan(pidfd)),
+            kill_switch: Some(kill_switch),
+            kill_switch_rx: Some(kill_switch_rx),
+            stdin: None,
+            source: Some(Source::Files(stdout, stderr)),
+            cgroup,
+            pgid: pid,
+            exited: Arc::new(AtomicBool::new(false)),
+            framing,
+        })
+    }
+
+    /// The pid of the process, which is also the id of the process group it leads.
+    pub fn pid(&self) -> libc::pid_t {
+        self.pgid
+    }
+
+    /// Stops the process group should it still be running according to the given stop policy.
+    /// This allows it to perform a graceful exit before being forcibly terminated.
+    pub fn stop(&mut self, policy: StopPolicy) -> Result<()> {
+        policy.validate()?;
+
         let kill_switch = self
             .kill_switch
             .take()
             .ok_or_else(|| anyhow!("already sent stop signal"))?;
-        let _ = kill_switch.send(());
+        let _ = kill_switch.send(policy);
         Ok(())
     }
 
+    /// Hand out a handle to the stdin of the process, if it was spawned with stdin open.
+    pub fn stdin(&self) -> Option<Stdin> {
+        self.stdin.clone()
+    }
+
     /// Spawn event processors that monitor the process for things like output and termination
-    /// and publishes events based on that.
-    pub fn spawn_events_processor(&mut self, output: Arc<Mutex<Output>>) -> Result<()> {
+    /// and publishes events based on that. The process is stopped should it still be running at the deadline.
+    pub fn spawn_events_processor(
+        &mut self,
+        output: Arc<Mutex<Output>>,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
         let output_stream = Arc::clone(&output);
+        let errors = output.lock().unwrap().errors();
 
         let mut kill_switch = self
             .kill_switch_rx
             .take()
             .ok_or_else(|| anyhow!("could not grab process kill switch"))?;
 
-        // Nab the child RAII handle from the remote. If it's taken, this method has already called.
-        let mut child = self
-            .child
+        // Nab the process from the remote. If it's taken, this method has already called.
+        let mut process = self
+            .process
             .take()
             .ok_or_else(|| anyhow!("events processor already spawned"))?;
 
-        // Nab the RAII stdout handle from the remote. If it's taken, this method has already called.
-        let mut stdout = child
-            .stdout
+        let source = self
+            .source
             .take()
-            .ok_or_else(|| anyhow!("could not attach stdout"))?;
-
-        // Nab the RAII stderr handle from the remote. If it's taken, this method has already called.
-        let mut stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| anyhow!("could not attach stderr"))?;
+            .ok_or_else(|| anyhow!("events processor already spawned"))?;
 
-        task::spawn(async move {
-            'outer: loop {
-                select! {
-                    exit_status = child.wait() => {
-                        let code = exit_status.map(|s| s.code()).ok().flatten().unwrap_or(1);
-                        let event = OutputEvent::Exit(code);
-                        let mut output_guard = output.lock().unwrap();
-                        output_guard.publish(event);
-                        break 'outer;
-                    }
+        let exited = Arc::clone(&self.exited);
 
-                    _ = &mut kill_switch => {
-                        let _ = child.kill();
-                    }
-                }
+        // A terminal merges stdout and stderr into a single stream which is published as stdout.
+        // Output files are followed until the process has exited and everything it wrote has been read.
+        let (mut stdout, mut stderr): (OutputReader, OutputReader) = match (source, &mut process) {
+            (Source::Terminal(terminal), _) => (
+                Box::new(fs::File::from_std(terminal)),
+                Box::new(tokio::io::empty()),
+            ),
+            (Source::Files(stdout, stderr), _) => (
+                Box::new(Follow::new(fs::File::from_std(stdout), Arc::clone(&exited))),
+                Box::new(Follow::new(fs::File::from_std(stderr), Arc::clone(&exited))),
+            ),
+            (Source::Pipes, Process::Child(child)) => (
+                Box::new(
+                    child
+                        .stdout
+                        .take()
+                        .ok_or_else(|| anyhow!("could not attach stdout"))?,
+                ),
+                Box::new(
+                    child
+                        .stderr
+                        .take()
+                        .ok_or_else(|| anyhow!("could not attach stderr"))?,
+                ),
+            ),
+            (Source::Pipes, Process::Orphan(_)) => {
+                return Err(anyhow!(
+                    "could not attach to the pipes of an orphaned process"
+                ))
             }
-        });
+        };
 
-        task::spawn(async move {
+        let cgroup = self.cgroup.take();
+        let pgid = self.pgid;
+        let framing = self.framing;
+
+        let mut reader = task::spawn(async move {
             let mut stdout_buffer = [0; READ_BUFFER_SIZE];
             let mut stderr_buffer = [0; READ_BUFFER_SIZE];
             let mut stdout_enabled = true;
             let mut stderr_enabled = true;
+            let mut stdout_framer = Framer::new(framing);
+            let mut stderr_framer = Framer::new(framing);
 
             while stdout_enabled || stderr_enabled {
+                // Hold off on reading more output until every subscriber that blocks publishing has caught up.
+                // A job writing to pipes or a terminal then blocks once they fill up, whereas the output files of a
+                // reattached job keep growing and are only read from once the subscribers have caught up.
+                let ready = output_stream.lock().unwrap().ready();
+                ready.await;
+
+                // Partial lines are published once they have been held back for long enough.
+                let flush_at = match (stdout_framer.flush_at(), stderr_framer.flush_at()) {
+                    (Some(stdout), Some(stderr)) => Some(stdout.min(stderr)),
+                    (stdout, stderr) => stdout.or(stderr),
+                };
+
                 select! {
                     maybe_read = stdout.read(&mut stdout_buffer), if stdout_enabled => {
                         match maybe_read {
                             Ok(read) if read != 0 => {
-                                let bytes = Vec::from(&stdout_buffer[..read]);
-                                let event = OutputEvent::Stdout(bytes);
-                                let mut output_guard = output_stream.lock().unwrap();
-                                output_guard.publish(event);
+                                let chunks = stdout_framer.push(&stdout_buffer[..read]);
+                                publish(&output_stream, chunks, OutputEvent::Stdout);
+                            }
+                            _ => {
+                                stdout_enabled = false;
+                                publish(&output_stream, stdout_framer.flush(), OutputEvent::Stdout);
                             }
-                            _ => stdout_enabled = false,
                         }
                     }
 
                     maybe_read = stderr.read(&mut stderr_buffer), if stderr_enabled => {
                         match maybe_read {
                             Ok(read) if read != 0 => {
-                                let bytes = Vec::from(&stderr_buffer[..read]);
-                                let event = OutputEvent::Stderr(bytes);
-                                let mut output_guard = output_stream.lock().unwrap();
-                                output_guard.publish(event);
+                                let chunks = stderr_framer.push(&stderr_buffer[..read]);
+                                publish(&output_stream, chunks, OutputEvent::Stderr);
+                            }
+                            _ => {
+                                stderr_enabled = false;
+                                publish(&output_stream, stderr_framer.flush(), OutputEvent::Stderr);
                             }
-                            _ => stderr_enabled = false,
+                        }
+                    }
+
+                    _ = time::sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => {
+                        let now = Instant::now();
+
+                        if stdout_framer.flush_at().map_or(false, |at| at <= now) {
+                            publish(&output_stream, stdout_framer.flush(), OutputEvent::Stdout);
+                        }
+
+                        if stderr_framer.flush_at().map_or(false, |at| at <= now) {
+                            publish(&output_stream, stderr_framer.flush(), OutputEvent::Stderr);
                         }
                     }
                 }
             }
         });
 
+        task::spawn(async move {
+            // We can't know how the process terminated if waiting on it failed
+            // so report it as a generic failure. Orphans can't be waited on at all
+            // so all that's known about them is that they exited.
+            let exit_status = async move {
+                match process {
+                    Process::Child(mut child) => {
+                        // The resource usage of a child can only be read once it has exited
+                        // and before it's reaped, which is why we wait for it to exit first.
+                        // That's also when its process group is taken down since the group id
+                        // can't have been reused for as long as the leader hasn't been reaped.
+                        let usage = match PidFd::open(pgid) {
+                            Ok(pidfd) => {
+                                let _ = pidfd.exited().await;
+                                usage::of_exited_child(pgid)
+                            }
+
+                            // Without a pidfd, waiting for the child to exit without reaping it blocks a thread.
+                            Err(_) => task::spawn_blocking(move || usage::of_exited_child(pgid))
+                                .await
+                                .unwrap_or(None),
+                        };
+                        kill_group(pgid);
+
+                        let status = child
+                            .wait()
+                            .await
+                            .map_or(ExitStatus::Code(1), ExitStatus::from);
+
+                        (status, usage)
+                    }
+                    Process::Orphan(pidfd) => {
+                        // Whoever reaps an orphan doesn't wait for us so this is as early as its group can be taken down.
+                        let _ = pidfd.exited().await;
+                        kill_group(pgid);
+                        (ExitStatus::Unknown, None)
+                    }
+                }
+            };
+            tokio::pin!(exit_status);
+
+            // Armed with the escalation signal once a stop has been requested.
+            let escalation = time::sleep(Duration::from_secs(0));
+            tokio::pin!(escalation);
+            let mut escalation_signal = None;
+            let mut stopping = false;
+            let mut stop_requested = false;
+
+            // A deadline that has already passed, like one that passed while the engine was away, fires right away.
+            let timeout = time::sleep_until(deadline.map_or_else(Instant::now, |deadline| {
+                let remaining = deadline.at.duration_since(SystemTime::now());
+                Instant::now() + remaining.unwrap_or_default()
+            }));
+            tokio::pin!(timeout);
+
+            let mut sampler = time::interval(usage::SAMPLE_INTERVAL);
+
+            'outer: loop {
+                select! {
+                    (status, usage) = &mut exit_status => {
+                        // Whatever is left in the cgroup has run as part of the job too.
+                        let usage: Option<ResourceUsage> = match (usage, cgroup.as_ref().and_then(Cgroup::usage)) {
+                            (Some(usage), Some(cgroup)) => Some(usage.max(cgroup)),
+                            (usage, cgroup) => usage.or(cgroup),
+                        };
+
+                        exited.store(true, Ordering::SeqCst);
+
+                        // Publish whatever output is left before the exit so that the exit is the last event.
+                        // Processes that escaped the process group may hold on to the pipes so don't wait forever.
+                        // The reader is stopped before the exit is published so that nothing it still reads comes after it.
+                        if time::timeout(OUTPUT_DRAIN_TIMEOUT, &mut reader).await.is_err() {
+                            reader.abort();
+                            let _ = (&mut reader).await;
+                        }
+
+                        // The final usage is in place by the time anyone learns that the job has exited.
+                        let event = OutputEvent::Exit(status);
+                        let mut output_guard = output.lock().unwrap();
+                        if let Some(usage) = usage {
+                            output_guard.record_usage(usage);
+                        }
+
+                        output_guard.publish(event);
+                        break 'outer;
+                    }
+
+                    _ = sampler.tick() => {
+                        if let Some(usage) = usage::sample(pgid, cgroup.as_ref()) {
+                            output.lock().unwrap().record_usage(usage);
+                        }
+                    }
+
+                    policy = &mut kill_switch, if !stop_requested => {
+                        stop_requested = true;
+
+                        // The remote has been dropped if there is no policy, in which case
+                        // the process tree has already been killed.
+                        let policy = match policy {
+                            Ok(policy) => policy,
+                            Err(_) => continue,
+                        };
+
+                        // A job that is already being stopped for running past its deadline has been signalled,
+                        // but it's escalated on the explicit schedule should that come sooner.
+                        let escalate_at = Instant::now() + policy.grace_period;
+                        if stopping && escalation_signal.is_some() && escalation.deadline() <= escalate_at {
+                            continue;
+                        }
+
+                        if !stopping {
+                            signal_tree(pgid, policy.signal, false, &output);
+                        }
+
+                        stopping = true;
+                        escalation.as_mut().reset(escalate_at);
+                        escalation_signal = Some(policy.escalation_signal);
+                    }
+
+                    _ = &mut timeout, if deadline.is_some() && !stopping => {
+                        stopping = true;
+
+                        let policy = deadline.unwrap().policy;
+                        output.lock().unwrap().publish(OutputEvent::TimedOut);
+                        signal_tree(pgid, policy.signal, false, &output);
+                        escalation.as_mut().reset(Instant::now() + policy.grace_period);
+                        escalation_signal = Some(policy.escalation_signal);
+                    }
+
+                    _ = &mut escalation, if escalation_signal.is_some() => {
+                        let signal = escalation_signal.take().unwrap();
+                        signal_tree(pgid, signal, true, &output);
+                    }
+                }
+            }
+
+            // Tear down the cgroup now that the job has exited, taking any stragglers with it.
+            if let Some(cgroup) = cgroup {
+                if let Err(error) = cgroup.remove().await {
+                    errors.report(error.context("failed to remove job cgroup"));
+                }
+            }
+        });
+
         Ok(())
     }
 }
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        // Don't leave anything running behind when the engine goes away, like when the server shuts down.
+        if !self.exited.load(Ordering::SeqCst) {
+            tree::signal(self.pgid, libc::SIGKILL);
+        }
+    }
+}
+
+/// Open an output file for the process to write to, keeping whatever has been written to it already.
+fn open_for_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+/// The job is over once its leader has exited so take down anything it left behind in its process group.
+fn kill_group(pgid: libc::pid_t) {
+    // This only fails if the group is already gone, which is fine.
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+}
+
+/// Publish chunks of output as events of the stream they were read from.
+fn publish(
+    output: &Mutex<Output>,
+    chunks: impl IntoIterator<Item = Vec<u8>>,
+    event: fn(Vec<u8>) -> OutputEvent,
+) {
+    let mut output_guard = output.lock().unwrap();
+    for chunk in chunks {
+        output_guard.publish(event(chunk));
+    }
+}
+
+/// Deliver a signal to the process tree of a job and publish an event about it.
+fn signal_tree(pgid: libc::pid_t, signal: i32, escalated: bool, output: &Mutex<Output>) {
+    tree::signal(pgid, signal);
+
+    let event = OutputEvent::Stop { signal, escalated };
+    let mut output_guard = output.lock().unwrap();
+    output_guard.publish(event);
+}
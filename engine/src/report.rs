@@ -0,0 +1,30 @@
+use anyhow::Error;
+use tokio::sync::mpsc;
+
+/// Where errors that happen in the background are reported, like failing to write output to disk or to tear down
+/// the cgroup of a job that has exited, since there's no caller to return them to. Errors are dropped should nobody
+/// be listening, which is the default.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorReporter {
+    sender: Option<mpsc::UnboundedSender<Error>>,
+}
+
+impl ErrorReporter {
+    /// Create a reporter along with the receiving end its errors are delivered to.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Error>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                sender: Some(sender),
+            },
+            receiver,
+        )
+    }
+
+    /// Report an error, which should carry the context of what failed.
+    pub fn report(&self, error: Error) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(error);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// A per-process resource limit, named after its `RLIMIT_*` constant without the prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rlimit {
+    /// The maximum size of core dumps in bytes.
+    Core,
+
+    /// The maximum amount of CPU time in seconds.
+    Cpu,
+
+    /// The maximum size of files the process creates in bytes.
+    Fsize,
+
+    /// The maximum number of open file descriptors.
+    Nofile,
+
+    /// The maximum size of the stack in bytes.
+    Stack,
+}
+
+impl Rlimit {
+    fn resource(self) -> libc::__rlimit_resource_t {
+        match self {
+            Self::Core => libc::RLIMIT_CORE,
+            Self::Cpu => libc::RLIMIT_CPU,
+            Self::Fsize => libc::RLIMIT_FSIZE,
+            Self::Nofile => libc::RLIMIT_NOFILE,
+            Self::Stack => libc::RLIMIT_STACK,
+        }
+    }
+}
+
+impl FromStr for Rlimit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "core" => Ok(Self::Core),
+            "cpu" => Ok(Self::Cpu),
+            "fsize" => Ok(Self::Fsize),
+            "nofile" => Ok(Self::Nofile),
+            "stack" => Ok(Self::Stack),
+            _ => Err(anyhow!("unknown rlimit {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Rlimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Core => "core",
+            Self::Cpu => "cpu",
+            Self::Fsize => "fsize",
+            Self::Nofile => "nofile",
+            Self::Stack => "stack",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Work out the limits a job runs with, which are only the requested ones. Those may not exceed their ceiling,
+/// while resources that weren't requested keep the limits the engine runs with.
+pub fn resolve(
+    requested: &HashMap<Rlimit, u64>,
+    ceilings: &HashMap<Rlimit, u64>,
+) -> Result<Vec<(Rlimit, u64)>> {
+    for (rlimit, value) in requested {
+        if let Some(ceiling) = ceilings.get(rlimit) {
+            if value > ceiling {
+                return Err(anyhow!(
+                    "rlimit {} of {} exceeds the ceiling of {}",
+                    rlimit,
+                    value,
+                    ceiling
+                ));
+            }
+        }
+    }
+
+    Ok(requested
+        .iter()
+        .map(|(rlimit, value)| (*rlimit, *value))
+        .collect())
+}
+
+/// Set both the soft and the hard limit of each resource, so that the job can't raise them again.
+/// Raising a hard limit needs the privileges of the engine so this must happen before the user is switched.
+///
+/// This runs in the forked child before exec and must therefore be async-signal-safe.
+pub fn apply(limits: &[(Rlimit, u64)]) -> io::Result<()> {
+    for (rlimit, value) in limits {
+        let limit = libc::rlimit {
+            rlim_cur: *value,
+            rlim_max: *value,
+        };
+
+        if unsafe { libc::setrlimit(rlimit.resource(), &limit) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
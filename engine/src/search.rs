@@ -0,0 +1,223 @@
+use crate::output::{LogEvent, LogStreams, OutputEvent};
+use anyhow::Result;
+use regex::bytes::Regex;
+use std::time::SystemTime;
+
+/// How many matching lines a search stops after by default.
+const DEFAULT_MAX_MATCHES: usize = 1000;
+
+/// The longest line matched by default before the rest of it is left out.
+const DEFAULT_MAX_LINE: usize = 64 * 1024;
+
+/// A search through the output of a job, line by line. Lines are assembled from however the output was
+/// split into events, separately for stdout and stderr, and are matched without their newline.
+#[derive(Debug, Clone)]
+pub struct LogSearch {
+    pattern: Regex,
+
+    pub streams: LogStreams,
+
+    /// Only lines that started being published within this time range match, should it be bounded.
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+
+    /// Stop searching after this many matching lines.
+    pub max_matches: Option<usize>,
+
+    /// Lines longer than this are cut to it, so only the start of such a line is matched and returned.
+    /// This bounds how much of a line is assembled in memory.
+    pub max_line: usize,
+}
+
+impl LogSearch {
+    /// Search for lines matching a regular expression.
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Ok(Self::new(Regex::new(pattern)?))
+    }
+
+    /// Search for lines containing a substring.
+    pub fn substring(substring: &str) -> Self {
+        Self::new(Regex::new(&regex::escape(substring)).unwrap())
+    }
+
+    fn new(pattern: Regex) -> Self {
+        Self {
+            pattern,
+            streams: LogStreams::Both,
+            since: None,
+            until: None,
+            max_matches: Some(DEFAULT_MAX_MATCHES),
+            max_line: DEFAULT_MAX_LINE,
+        }
+    }
+
+    /// Run the search over events in the order they were published. Every matching line is returned as an output
+    /// event of its stream, stamped with the sequence number and time of the event the line starts in.
+    pub(crate) fn run(
+        &self,
+        events: impl Iterator<Item = Result<LogEvent>>,
+    ) -> Result<Vec<LogEvent>> {
+        // The line being assembled for stdout and stderr respectively.
+        let mut lines: [Option<LogEvent>; 2] = [None, None];
+        let mut matches = Vec::new();
+
+        for event in events {
+            let event = event?;
+            let bytes = match self.streams.bytes(&event.event) {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+
+            let slot = match event.event {
+                OutputEvent::Stdout(_) => 0,
+                _ => 1,
+            };
+
+            let mut rest = bytes;
+            while !rest.is_empty() {
+                let newline = rest.iter().position(|byte| *byte == b'\n');
+                let (piece, remaining) = match newline {
+                    Some(position) => (&rest[..position], &rest[position + 1..]),
+                    None => (rest, &rest[rest.len()..]),
+                };
+
+                let line = lines[slot].get_or_insert_with(|| empty_line(&event));
+                append(line, piece, self.max_line);
+                rest = remaining;
+
+                if newline.is_some() {
+                    let line = lines[slot].take().unwrap();
+                    if self.check(line, &mut matches) {
+                        return Ok(sorted(matches));
+                    }
+                }
+            }
+        }
+
+        // The output may not end with a newline, in which case its last line is still a line.
+        for line in lines.iter_mut() {
+            if let Some(line) = line.take() {
+                if self.check(line, &mut matches) {
+                    break;
+                }
+            }
+        }
+
+        Ok(sorted(matches))
+    }
+
+    /// Keep the line should it match. Returns true once enough lines have matched.
+    fn check(&self, line: LogEvent, matches: &mut Vec<LogEvent>) -> bool {
+        let within = self.since.map_or(true, |since| line.at >= since)
+            && self.until.map_or(true, |until| line.at <= until);
+
+        let bytes = match &line.event {
+            OutputEvent::Stdout(bytes) | OutputEvent::Stderr(bytes) => bytes,
+            _ => return false,
+        };
+
+        if within && self.pattern.is_match(bytes) {
+            matches.push(line);
+        }
+
+        self.max_matches.map_or(false, |max| matches.len() >= max)
+    }
+}
+
+/// An empty line of the same stream as the event, stamped with it.
+fn empty_line(event: &LogEvent) -> LogEvent {
+    let output = match event.event {
+        OutputEvent::Stdout(_) => OutputEvent::Stdout(Vec::new()),
+        _ => OutputEvent::Stderr(Vec::new()),
+    };
+
+    LogEvent {
+        sequence: event.sequence,
+        at: event.at,
+        event: output,
+    }
+}
+
+/// Append a piece to a line, leaving out whatever doesn't fit within the maximum line length.
+fn append(line: &mut LogEvent, piece: &[u8], max_line: usize) {
+    if let OutputEvent::Stdout(bytes) | OutputEvent::Stderr(bytes) = &mut line.event {
+        let room = max_line.saturating_sub(bytes.len());
+        bytes.extend_from_slice(&piece[..piece.len().min(room)]);
+    }
+}
+
+/// Lines of stdout and stderr complete independently, so put them back in the order they were published.
+fn sorted(mut matches: Vec<LogEvent>) -> Vec<LogEvent> {
+    matches.sort_by_key(|line| line.sequence);
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogSearch;
+    use crate::output::{LogEvent, LogStreams, OutputEvent};
+    use std::time::{Duration, SystemTime};
+
+    fn event(sequence: u64, at: SystemTime, event: OutputEvent) -> LogEvent {
+        LogEvent {
+            sequence,
+            at,
+            event,
+        }
+    }
+
+    #[test]
+    fn match_lines_split_across_events() {
+        let start = SystemTime::now();
+        let later = start + Duration::from_secs(10);
+        let events = [
+            event(1, start, OutputEvent::Stdout(b"warn: disk ".to_vec())),
+            event(2, start, OutputEvent::Stderr(b"warn: stderr\n".to_vec())),
+            event(3, start, OutputEvent::Stdout(b"full\nok\n".to_vec())),
+            event(4, later, OutputEvent::Stdout(b"warn: late".to_vec())),
+        ];
+
+        let lines = |search: LogSearch| -> Vec<(u64, OutputEvent)> {
+            search
+                .run(events.iter().cloned().map(Ok))
+                .unwrap()
+                .into_iter()
+                .map(|line| (line.sequence, line.event))
+                .collect()
+        };
+
+        assert_eq!(
+            lines(LogSearch::regex("^warn").unwrap()),
+            vec![
+                (1, OutputEvent::Stdout(b"warn: disk full".to_vec())),
+                (2, OutputEvent::Stderr(b"warn: stderr".to_vec())),
+                (4, OutputEvent::Stdout(b"warn: late".to_vec())),
+            ]
+        );
+
+        let mut search = LogSearch::substring("warn");
+        search.streams = LogStreams::Stdout;
+        search.until = Some(start);
+        assert_eq!(
+            lines(search),
+            vec![(1, OutputEvent::Stdout(b"warn: disk full".to_vec()))]
+        );
+
+        let mut search = LogSearch::substring("warn");
+        search.max_matches = Some(1);
+        assert_eq!(lines(search).len(), 1);
+        assert!(LogSearch::regex("(").is_err());
+
+        // Only the start of a line that's too long is matched.
+        let mut search = LogSearch::regex("^warn: d").unwrap();
+        search.max_line = 7;
+        assert_eq!(
+            lines(search),
+            vec![(1, OutputEvent::Stdout(b"warn: d".to_vec()))]
+        );
+
+        let mut search = LogSearch::substring("full");
+        search.max_line = 7;
+        assert!(lines(search).is_empty());
+    }
+}
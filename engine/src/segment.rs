@@ -0,0 +1,662 @@
+use crate::output::{LogEvent, LogStorage, Offsets, OutputEvent};
+use crate::report::ErrorReporter;
+use crate::store::LogFiles;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+/// How many bytes of serialized events are gathered before they're compressed into a frame of their own.
+/// Larger frames compress better but reading a single event means decompressing the whole frame it's in.
+const FRAME_SIZE: usize = 64 * 1024;
+
+/// The zstd compression level frames are compressed at.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// An event read back from a segment along with its offsets.
+type SpilledEvent = (LogEvent, Offsets);
+
+/// Events compressed in frames appended to a file on disk. The events gathered for the next frame are held
+/// in memory until there are enough of them. Once the segment is full the oldest events are discarded,
+/// so it always holds consecutive events and the frames are indexed by the sequence number of their first event.
+///
+/// A segment is either created to spill events evicted from memory to an unlinked file, or opened on the log of
+/// a job in a store, in which case its index is recorded in a file of its own so that it can be opened again.
+/// Frames are written by a writer so that appending never waits for the disk.
+#[derive(Debug)]
+pub struct Segment {
+    writer: Writer,
+
+    /// Where the next frame goes in the file.
+    end: u64,
+
+    /// The number of events in the segment and the number of output bytes they hold.
+    events: u64,
+    bytes: u64,
+
+    /// Every frame that's still in the file in the order they were written.
+    index: VecDeque<Frame>,
+
+    /// The events gathered for the next frame and the size they take up once serialized.
+    pending: VecDeque<SpilledEvent>,
+    pending_size: usize,
+
+    /// Set once a timed out event has been appended, even if it has been discarded since.
+    timed_out: bool,
+
+    /// The offsets of whatever comes after the frames written since they were last taken.
+    flushed: Option<Offsets>,
+
+    /// The events of the most recently decompressed frame along with the sequence number it starts at,
+    /// since events tend to be read in order.
+    cache: RefCell<Option<(u64, Vec<SpilledEvent>)>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Frame {
+    /// The sequence number of the first event in the frame and the number of events in it.
+    first_sequence: u64,
+    events: u64,
+
+    /// The offsets of the first event in the frame, from which the offsets of the others follow.
+    offsets: Offsets,
+
+    /// The number of output bytes held by the events in the frame.
+    bytes: u64,
+
+    /// Where the frame is in the file and its size before it was compressed.
+    position: u64,
+    length: u64,
+    raw_length: u64,
+
+    /// Whether a timed out event had been appended by the end of the frame.
+    timed_out: bool,
+}
+
+/// The files of a segment.
+#[derive(Debug)]
+struct Files {
+    frames: File,
+
+    /// The file every change to the index is appended to, should the segment be the log of a job.
+    index: Option<File>,
+}
+
+/// Work done on the files of a segment or on behalf of it, like giving back what it no longer needs,
+/// along with what failed should it fail.
+type Work = (&'static str, Box<dyn FnOnce(&Files) -> Result<()> + Send>);
+
+/// Does the work queued for a segment in the order it was queued. Should there be a runtime it's done on a blocking
+/// thread, so that whoever appends to the segment with the lock on the output held doesn't wait for the disk.
+#[derive(Debug, Clone)]
+struct Writer {
+    files: Arc<Files>,
+    queue: Arc<Mutex<Queue>>,
+
+    /// Where work that fails is reported, since nobody waits for it.
+    errors: ErrorReporter,
+}
+
+#[derive(Default)]
+struct Queue {
+    work: VecDeque<Work>,
+
+    /// Set while the work is being done.
+    working: bool,
+
+    /// The frames that are queued to be written by their position, which are read back from here until they are.
+    unwritten: HashMap<u64, Arc<Vec<u8>>>,
+
+    /// Set once any of the work has failed, after which the segment can't be relied on.
+    failed: bool,
+}
+
+impl Debug for Queue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("work", &self.work.len())
+            .field("working", &self.working)
+            .field("unwritten", &self.unwritten.len())
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl Writer {
+    fn new(files: Files, errors: ErrorReporter) -> Self {
+        Self {
+            files: Arc::new(files),
+            queue: Arc::new(Mutex::new(Queue::default())),
+            errors,
+        }
+    }
+
+    fn queue(
+        &self,
+        failure: &'static str,
+        work: impl FnOnce(&Files) -> Result<()> + Send + 'static,
+    ) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.work.push_back((failure, Box::new(work)));
+
+        if queue.working {
+            return;
+        }
+
+        queue.working = true;
+        drop(queue);
+
+        match Handle::try_current() {
+            Ok(runtime) => {
+                let writer = self.clone();
+                runtime.spawn_blocking(move || writer.work());
+            }
+            Err(_) => self.work(),
+        }
+    }
+
+    /// Do the queued work until there's none left.
+    fn work(&self) {
+        loop {
+            let (failure, work) = {
+                let mut queue = self.queue.lock().unwrap();
+                match queue.work.pop_front() {
+                    Some(work) => work,
+                    None => {
+                        queue.working = false;
+                        return;
+                    }
+                }
+            };
+
+            if let Err(error) = work(&self.files) {
+                self.errors.report(error.context(failure));
+                self.queue.lock().unwrap().failed = true;
+            }
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.queue.lock().unwrap().failed
+    }
+
+    /// Read back every event in a frame along with their offsets.
+    fn decompress(&self, frame: Frame) -> Result<Vec<SpilledEvent>> {
+        let unwritten = self
+            .queue
+            .lock()
+            .unwrap()
+            .unwritten
+            .get(&frame.position)
+            .cloned();
+
+        let serialized = match unwritten {
+            Some(compressed) => zstd::decode_all(&compressed[..])?,
+            None => {
+                let mut compressed = vec![0; frame.length as usize];
+                self.files
+                    .frames
+                    .read_exact_at(&mut compressed, frame.position)?;
+                zstd::decode_all(&compressed[..])?
+            }
+        };
+
+        let mut remaining = &serialized[..];
+        let mut offsets = frame.offsets;
+        let mut events = Vec::with_capacity(frame.events as usize);
+
+        for _ in 0..frame.events {
+            let event: LogEvent = bincode::deserialize_from(&mut remaining)?;
+            let next = offsets.after(&event.event);
+            events.push((event, offsets));
+            offsets = next;
+        }
+
+        Ok(events)
+    }
+}
+
+/// A change to the index of a segment as recorded in its index file.
+#[derive(Debug, Serialize, Deserialize)]
+enum IndexEntry {
+    /// A frame was appended to the file.
+    Frame(Frame),
+
+    /// The oldest frame was discarded.
+    Discard,
+}
+
+impl Segment {
+    /// Create a segment in an unlinked file in the given directory, which is gone along with the segment.
+    /// Writing to it happens in the background, so failing to do so is reported rather than returned.
+    pub fn create(directory: &Path, errors: ErrorReporter) -> Result<Self> {
+        let path = directory.join(format!("{}.segment", Uuid::new_v4()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        fs::remove_file(&path)?;
+        Ok(Self::new(
+            Files {
+                frames: file,
+                index: None,
+            },
+            errors,
+        ))
+    }
+
+    /// Open the log of a job, which picks up where it was left off. A frame or index entry cut short by the
+    /// server going down is cut off along with anything after it, so that what's appended next can be read back.
+    pub fn open(log: LogFiles, errors: ErrorReporter) -> Result<Self> {
+        let mut bytes = Vec::new();
+        (&log.index).read_to_end(&mut bytes)?;
+
+        let mut segment = Self::new(
+            Files {
+                frames: log.frames,
+                index: Some(log.index),
+            },
+            errors,
+        );
+        let mut remaining = &bytes[..];
+        let mut valid = 0;
+
+        while let Ok(entry) = bincode::deserialize_from(&mut remaining) {
+            match entry {
+                IndexEntry::Frame(frame) => {
+                    segment.events += frame.events;
+                    segment.bytes += frame.bytes;
+                    segment.timed_out = frame.timed_out;
+                    segment.index.push_back(frame);
+                    segment.end = frame.position + frame.length;
+                }
+                IndexEntry::Discard => {
+                    if let Some(frame) = segment.index.pop_front() {
+                        segment.events -= frame.events;
+                        segment.bytes -= frame.bytes;
+                    }
+                }
+            }
+
+            valid = bytes.len() - remaining.len();
+        }
+
+        let files = &segment.writer.files;
+        files.frames.set_len(segment.end)?;
+        if let Some(index) = &files.index {
+            index.set_len(valid as u64)?;
+        }
+
+        Ok(segment)
+    }
+
+    fn new(files: Files, errors: ErrorReporter) -> Self {
+        Self {
+            writer: Writer::new(files, errors),
+            end: 0,
+            events: 0,
+            bytes: 0,
+            index: VecDeque::new(),
+            pending: VecDeque::new(),
+            pending_size: 0,
+            timed_out: false,
+            flushed: None,
+            cache: RefCell::new(None),
+        }
+    }
+
+    /// Returns true if the segment is the log of a job rather than spilled events.
+    pub fn persistent(&self) -> bool {
+        self.writer.files.index.is_some()
+    }
+
+    /// The number of output bytes held by the events in the segment.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns true if a timed out event has been appended to the segment.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Append an event. Fails should writing any of the earlier events have failed,
+    /// since the segment would be missing those.
+    pub fn append(&mut self, event: &LogEvent, offsets: Offsets) -> Result<()> {
+        if self.writer.failed() {
+            return Err(anyhow!("failed to write earlier events to disk"));
+        }
+
+        // The pending frame is only written out once it's known there is more to come,
+        // so that the event isn't part of the segment should that fail.
+        if self.pending_size >= FRAME_SIZE {
+            self.compress_pending()?;
+        }
+
+        if let OutputEvent::TimedOut = event.event {
+            self.timed_out = true;
+        }
+
+        self.events += 1;
+        self.bytes += event.size() as u64;
+        self.pending_size += bincode::serialized_size(event)? as usize;
+        self.pending.push_back((event.clone(), offsets));
+        Ok(())
+    }
+
+    /// Write out the pending events and make sure everything written so far ends up on disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.compress_pending()?;
+        self.writer
+            .queue("failed to sync output events to disk", |files| {
+                files.frames.sync_data()?;
+
+                if let Some(index) = &files.index {
+                    index.sync_data()?;
+                }
+
+                Ok(())
+            });
+
+        Ok(())
+    }
+
+    /// Queue work to be done once everything written so far has been, off the runtime like the writing.
+    /// Should it fail, the error is reported as the given failure, which doesn't affect the segment.
+    pub fn then(&self, failure: &'static str, work: impl FnOnce() -> Result<()> + Send + 'static) {
+        let errors = self.writer.errors.clone();
+        self.writer.queue(failure, move |_| {
+            if let Err(error) = work() {
+                errors.report(error.context(failure));
+            }
+
+            Ok(())
+        });
+    }
+
+    /// The offsets of whatever comes after the frames written since this was last called, should any have been.
+    pub fn take_flushed(&mut self) -> Option<Offsets> {
+        self.flushed.take()
+    }
+
+    /// Compress the pending events into a frame and queue it to be appended to the file.
+    fn compress_pending(&mut self) -> Result<()> {
+        let (first_sequence, offsets) = match self.pending.front() {
+            Some((first, offsets)) => (first.sequence, *offsets),
+            None => return Ok(()),
+        };
+
+        let mut serialized = Vec::with_capacity(self.pending_size);
+        for (event, _) in &self.pending {
+            bincode::serialize_into(&mut serialized, event)?;
+        }
+
+        let compressed = Arc::new(zstd::encode_all(&serialized[..], COMPRESSION_LEVEL)?);
+        let position = self.end;
+
+        let frame = Frame {
+            first_sequence,
+            events: self.pending.len() as u64,
+            offsets,
+            bytes: self
+                .pending
+                .iter()
+                .map(|(event, _)| event.size() as u64)
+                .sum(),
+            position,
+            length: compressed.len() as u64,
+            raw_length: serialized.len() as u64,
+            timed_out: self.timed_out,
+        };
+
+        // The frame only becomes part of the log once it's in the index.
+        let entry = bincode::serialize(&IndexEntry::Frame(frame))?;
+        let queue = Arc::clone(&self.writer.queue);
+        queue
+            .lock()
+            .unwrap()
+            .unwritten
+            .insert(position, Arc::clone(&compressed));
+
+        self.writer
+            .queue("failed to write output events to disk", move |files| {
+                files.frames.write_all_at(&compressed, position)?;
+
+                if let Some(mut index) = files.index.as_ref() {
+                    index.write_all(&entry)?;
+                }
+
+                queue.lock().unwrap().unwritten.remove(&position);
+                Ok(())
+            });
+
+        let (last, last_offsets) = self.pending.back().unwrap();
+        self.flushed = Some(last_offsets.after(&last.event));
+        self.end += frame.length;
+        self.index.push_back(frame);
+        self.pending.clear();
+        self.pending_size = 0;
+        Ok(())
+    }
+
+    /// Discard the oldest frame, or the oldest pending event should all frames be gone. Returns false should
+    /// there be nothing left to discard. The disk space of the frame is given back.
+    pub fn discard_oldest(&mut self) -> Result<bool> {
+        let (events, bytes) = match self.index.front().copied() {
+            Some(frame) => {
+                let entry = bincode::serialize(&IndexEntry::Discard)?;
+                self.writer
+                    .queue("failed to discard output events on disk", move |files| {
+                        if let Some(mut index) = files.index.as_ref() {
+                            index.write_all(&entry)?;
+                        }
+
+                        punch_hole(&files.frames, frame.position, frame.length);
+                        Ok(())
+                    });
+
+                self.index.pop_front();
+                (frame.events, frame.bytes)
+            }
+            None => match self.pending.pop_front() {
+                Some((event, _)) => {
+                    self.pending_size -= bincode::serialized_size(&event)? as usize;
+                    (1, event.size() as u64)
+                }
+                None => return Ok(false),
+            },
+        };
+
+        self.events -= events;
+        self.bytes -= bytes;
+        Ok(true)
+    }
+
+    /// The offsets of the first event in the segment, should there be any.
+    pub fn first_offsets(&self) -> Option<Offsets> {
+        self.index
+            .front()
+            .map(|frame| frame.offsets)
+            .or_else(|| self.pending.front().map(|(_, offsets)| *offsets))
+    }
+
+    /// The sequence numbers of the events in the segment.
+    pub fn sequences(&self) -> Range<u64> {
+        let first = self
+            .index
+            .front()
+            .map(|frame| frame.first_sequence)
+            .or_else(|| self.pending.front().map(|(event, _)| event.sequence))
+            .unwrap_or(0);
+
+        first..first + self.events
+    }
+
+    /// Read back a single event along with its offsets, decompressing only the frame it's in.
+    pub fn event(&self, sequence: u64) -> Result<SpilledEvent> {
+        if let Some((first, _)) = self.pending.front() {
+            if sequence >= first.sequence {
+                return self
+                    .pending
+                    .get((sequence - first.sequence) as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("event {} is not in the segment", sequence));
+            }
+        }
+
+        let frame = self
+            .frame(sequence)
+            .ok_or_else(|| anyhow!("event {} is not in the segment", sequence))?;
+
+        let mut cache = self.cache.borrow_mut();
+        if cache.as_ref().map(|(first, _)| *first) != Some(frame.first_sequence) {
+            *cache = Some((frame.first_sequence, self.writer.decompress(frame)?));
+        }
+
+        let (_, events) = cache.as_ref().unwrap();
+        events
+            .get((sequence - frame.first_sequence) as usize)
+            .cloned()
+            .ok_or_else(|| anyhow!("event {} is not in the segment", sequence))
+    }
+
+    /// The frame an event is in, which is the last frame starting at or before it.
+    fn frame(&self, sequence: u64) -> Option<Frame> {
+        let (mut low, mut high) = (0, self.index.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.index[middle].first_sequence <= sequence {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        low.checked_sub(1).map(|number| self.index[number])
+    }
+
+    /// Take a view of the events in the segment as they are now, which can be read from another thread.
+    pub fn reader(&self) -> SegmentReader {
+        SegmentReader {
+            writer: self.writer.clone(),
+            index: self.index.iter().copied().collect(),
+            pending: self.pending.iter().cloned().collect(),
+        }
+    }
+
+    /// Take a view of the events from the given one up to the end of the frame it's in, or of the pending events
+    /// from it on should it be one of those, which can be read from another thread.
+    pub fn reader_from(&self, sequence: u64) -> SegmentReader {
+        let (index, pending) = match self.pending.front() {
+            Some((first, _)) if sequence >= first.sequence => (
+                Vec::new(),
+                self.pending
+                    .iter()
+                    .skip((sequence - first.sequence) as usize)
+                    .cloned()
+                    .collect(),
+            ),
+            _ => (self.frame(sequence).into_iter().collect(), Vec::new()),
+        };
+
+        SegmentReader {
+            writer: self.writer.clone(),
+            index,
+            pending,
+        }
+    }
+
+    /// How large the events in the frames on disk are before and after compression.
+    pub fn storage(&self) -> LogStorage {
+        LogStorage {
+            raw_bytes: self.index.iter().map(|frame| frame.raw_length).sum(),
+            compressed_bytes: self.index.iter().map(|frame| frame.length).sum(),
+        }
+    }
+}
+
+/// The events in a segment as of when the reader was taken. Should the oldest of them be discarded from the segment
+/// in the meantime, reading them fails.
+#[derive(Debug)]
+pub struct SegmentReader {
+    writer: Writer,
+    index: Vec<Frame>,
+    pending: Vec<SpilledEvent>,
+}
+
+impl SegmentReader {
+    /// Read back every event in order, decompressing one frame at a time.
+    pub fn events(&self) -> impl Iterator<Item = Result<LogEvent>> + '_ {
+        (0..self.blocks()).flat_map(move |block| {
+            let events: Vec<Result<LogEvent>> = match self.block(block) {
+                Ok(events) => events.into_iter().map(|(event, _)| Ok(event)).collect(),
+                Err(error) => vec![Err(error)],
+            };
+
+            events
+        })
+    }
+
+    /// The number of blocks the events are read back in, which are the frames followed by the pending events.
+    pub fn blocks(&self) -> usize {
+        self.index.len() + usize::from(!self.pending.is_empty())
+    }
+
+    /// The offsets of the first event in a block, which doesn't read anything back.
+    pub fn block_offsets(&self, block: usize) -> Offsets {
+        match self.index.get(block) {
+            Some(frame) => frame.offsets,
+            None => self.pending[0].1,
+        }
+    }
+
+    /// Read back the events in a block along with their offsets, decompressing the frame should it be one.
+    pub fn block(&self, block: usize) -> Result<Vec<SpilledEvent>> {
+        match self.index.get(block) {
+            Some(frame) => self.writer.decompress(*frame),
+            None => Ok(self.pending.clone()),
+        }
+    }
+
+    /// The sequence number of whatever comes after the events in view.
+    pub fn end(&self) -> u64 {
+        match (self.pending.last(), self.index.last()) {
+            (Some((last, _)), _) => last.sequence + 1,
+            (None, Some(frame)) => frame.first_sequence + frame.events,
+            (None, None) => 0,
+        }
+    }
+}
+
+impl Drop for Segment {
+    /// The events gathered for the next frame of a log are written out rather than lost along with the segment.
+    fn drop(&mut self) {
+        if self.persistent() {
+            let _ = self.compress_pending();
+        }
+    }
+}
+
+/// Give back the disk space of part of a file. Filesystems that can't do so only give it back once the file is gone.
+pub fn punch_hole(file: &File, position: u64, length: u64) {
+    unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            position as libc::off_t,
+            length as libc::off_t,
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use crate::framing::LineFraming;
+use crate::remote::Deadline;
+use crate::segment;
+use crate::usage::ResourceUsage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// The name of the file holding the job record in the directory of a job.
+const RECORD_FILE: &str = "job";
+
+/// The name of the file holding the compressed frames of events in the directory of a job.
+const EVENTS_FILE: &str = "events";
+
+/// The name of the file holding the index of the frames of events in the directory of a job.
+const INDEX_FILE: &str = "index";
+
+/// The name of the file holding the latest resource usage in the directory of a job.
+const USAGE_FILE: &str = "usage";
+
+/// The name of the file the stdout of a job is written to in the directory of a job.
+const STDOUT_FILE: &str = "stdout";
+
+/// The name of the file the stderr of a job is written to in the directory of a job.
+const STDERR_FILE: &str = "stderr";
+
+/// The metadata of a job as recorded when it's spawned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub user: String,
+    pub uuid: Uuid,
+    pub index: u64,
+    pub program: String,
+    pub args: Vec<String>,
+    pub started_at: SystemTime,
+
+    /// The pid of the process along with its start time in clock ticks since boot,
+    /// which tells it apart from a later process that happens to get the same pid.
+    pub pid: i32,
+    pub start_time: u64,
+
+    /// The files the process writes its output to, if it doesn't write to pipes or a terminal.
+    /// Only jobs with output files can be reattached to once the engine that spawned them is gone.
+    pub output_files: Option<OutputFiles>,
+
+    /// When the job is stopped should it still be running, if it has a timeout or a deadline.
+    pub deadline: Option<Deadline>,
+
+    /// How output is split into events, should it be published as whole lines.
+    pub line_framing: Option<LineFraming>,
+}
+
+/// The files a job writes its stdout and stderr to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputFiles {
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+}
+
+/// The files the log of a job is kept in: its events compressed in frames and the index of those frames.
+/// Both are opened for reading and writing, the index for appending.
+#[derive(Debug)]
+pub struct LogFiles {
+    pub frames: File,
+    pub index: File,
+}
+
+/// A job as loaded back from a store.
+#[derive(Debug)]
+pub struct StoredJob {
+    pub record: JobRecord,
+    pub log: LogFiles,
+
+    /// The latest resource usage recorded for the job, if any.
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Somewhere to record jobs and everything they publish so that they outlive the engine.
+pub trait Store: Debug + Send + Sync {
+    /// Hand out the files a job about to be spawned should write its output to so that the output can
+    /// still be read after the engine has gone away. Jobs write their output to pipes should there be none.
+    fn output_files(&self, _job: Uuid) -> Result<Option<OutputFiles>> {
+        Ok(None)
+    }
+
+    /// Record a newly spawned job and hand out the files to keep its log in.
+    fn insert(&self, record: &JobRecord) -> Result<LogFiles>;
+
+    /// Let go of the output a job has written to its output files up to the given stdout and stderr offsets,
+    /// since it has been written to the log of the job, or of the output files altogether once the job has exited.
+    fn release_output(&self, _job: Uuid, _upto: Option<(u64, u64)>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Record the latest resource usage of a job, replacing what was recorded before.
+    fn record_usage(&self, job: Uuid, usage: &ResourceUsage) -> Result<()>;
+
+    /// Load every recorded job along with its log. Jobs that fail to load are errors in their place,
+    /// so that one job that can't be loaded doesn't hold back the others.
+    fn load(&self) -> Result<Vec<Result<StoredJob>>>;
+
+    /// Remove a job along with everything recorded for it.
+    fn remove(&self, job: Uuid) -> Result<()>;
+}
+
+/// A store keeping a directory for each job with its record, its log and the output files it writes to.
+#[derive(Debug)]
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn open(directory: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn load_job(&self, directory: PathBuf) -> Result<StoredJob> {
+        let record = bincode::deserialize(&fs::read(directory.join(RECORD_FILE))?)?;
+        let usage = match fs::read(directory.join(USAGE_FILE)) {
+            Ok(bytes) => bincode::deserialize(&bytes).ok(),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(StoredJob {
+            record,
+            log: open_log(&directory)?,
+            usage,
+        })
+    }
+}
+
+/// Open the log files in the directory of a job, creating them should they not exist yet.
+fn open_log(directory: &Path) -> Result<LogFiles> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true);
+
+    Ok(LogFiles {
+        frames: options.open(directory.join(EVENTS_FILE))?,
+        index: options.append(true).open(directory.join(INDEX_FILE))?,
+    })
+}
+
+impl Store for FileStore {
+    fn output_files(&self, job: Uuid) -> Result<Option<OutputFiles>> {
+        let directory = self.directory.join(job.to_string());
+        fs::create_dir_all(&directory)?;
+
+        Ok(Some(OutputFiles {
+            stdout: directory.join(STDOUT_FILE),
+            stderr: directory.join(STDERR_FILE),
+        }))
+    }
+
+    fn insert(&self, record: &JobRecord) -> Result<LogFiles> {
+        let directory = self.directory.join(record.uuid.to_string());
+        fs::create_dir_all(&directory)?;
+
+        // Write the record under a temporary name first so that a record is never seen half written.
+        let temporary = directory.join(format!("{}.tmp", RECORD_FILE));
+        fs::write(&temporary, bincode::serialize(record)?)?;
+        fs::rename(&temporary, directory.join(RECORD_FILE))?;
+
+        open_log(&directory)
+    }
+
+    fn release_output(&self, job: Uuid, upto: Option<(u64, u64)>) -> Result<()> {
+        let directory = self.directory.join(job.to_string());
+        let files = [
+            (directory.join(STDOUT_FILE), upto.map(|(stdout, _)| stdout)),
+            (directory.join(STDERR_FILE), upto.map(|(_, stderr)| stderr)),
+        ];
+
+        // Jobs with a terminal don't have output files.
+        for (path, upto) in &files {
+            let released = match upto {
+                Some(upto) => OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .map(|file| segment::punch_hole(&file, 0, *upto)),
+                None => fs::remove_file(path),
+            };
+
+            match released {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_usage(&self, job: Uuid, usage: &ResourceUsage) -> Result<()> {
+        let directory = self.directory.join(job.to_string());
+        let temporary = directory.join(format!("{}.tmp", USAGE_FILE));
+        fs::write(&temporary, bincode::serialize(usage)?)?;
+        fs::rename(&temporary, directory.join(USAGE_FILE))?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<Result<StoredJob>>> {
+        let mut jobs = Vec::new();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let job = self.load_job(path.clone());
+            jobs.push(job.with_context(|| format!("failed to load job from {}", path.display())));
+        }
+
+        Ok(jobs)
+    }
+
+    fn remove(&self, job: Uuid) -> Result<()> {
+        match fs::remove_dir_all(self.directory.join(job.to_string())) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileStore, JobRecord, Store, INDEX_FILE};
+    use crate::output::{ExitStatus, Output, OutputEvent, RetentionPolicy};
+    use crate::subscriber::BackpressurePolicy;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[test]
+    fn record_and_load() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = Arc::new(FileStore::open(directory.clone()).unwrap());
+
+        let uuid = Uuid::new_v4();
+        let record = JobRecord {
+            user: "user".into(),
+            uuid,
+            index: 3,
+            program: "/bin/echo".into(),
+            args: vec!["hi".into()],
+            started_at: SystemTime::now(),
+            pid: 1234,
+            start_time: 5678,
+            output_files: store.output_files(uuid).unwrap(),
+            deadline: None,
+            line_framing: None,
+        };
+
+        let output = || Output::new(RetentionPolicy::default(), BackpressurePolicy::default());
+        let mut first = output();
+        first
+            .record_to(store.clone(), uuid, store.insert(&record).unwrap())
+            .unwrap();
+        first.publish(OutputEvent::Stdout(b"hi\n".to_vec()));
+        drop(first);
+
+        // An index entry cut short by the server going down is ignored.
+        OpenOptions::new()
+            .append(true)
+            .open(directory.join(uuid.to_string()).join(INDEX_FILE))
+            .unwrap()
+            .write_all(&[1])
+            .unwrap();
+
+        // The rest is appended through another store as if the first one went away with the server.
+        let store = Arc::new(FileStore::open(directory.clone()).unwrap());
+        let job = store.load().unwrap().pop().unwrap().unwrap();
+        let mut second = output();
+        second.record_to(store.clone(), uuid, job.log).unwrap();
+        second.publish(OutputEvent::Exit(ExitStatus::Code(0)));
+        drop(second);
+
+        let mut jobs = FileStore::open(directory.clone()).unwrap().load().unwrap();
+        let job = jobs.pop().unwrap().unwrap();
+        let mut restored = output();
+        restored.record_to(store, uuid, job.log).unwrap();
+        let events = restored.get_events().unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(jobs.is_empty());
+        assert_eq!(job.record, record);
+        assert_eq!(
+            events
+                .into_iter()
+                .map(|event| (event.sequence, event.event))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, OutputEvent::Stdout(b"hi\n".to_vec())),
+                (2, OutputEvent::Exit(ExitStatus::Code(0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn discard_oldest_recorded_output() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = Arc::new(FileStore::open(directory.clone()).unwrap());
+        let uuid = Uuid::new_v4();
+
+        let record = JobRecord {
+            user: "user".into(),
+            uuid,
+            index: 0,
+            program: "/bin/cat".into(),
+            args: Vec::new(),
+            started_at: SystemTime::now(),
+            pid: 1234,
+            start_time: 5678,
+            output_files: store.output_files(uuid).unwrap(),
+            deadline: None,
+            line_framing: None,
+        };
+
+        // Enough lines to fill a few frames on disk, of which only the last ones fit within the limit.
+        let output = || {
+            let retention = RetentionPolicy {
+                memory_limit: 4,
+                spill_directory: None,
+                spill_limit: 64 * 1024,
+            };
+
+            Output::new(retention, BackpressurePolicy::default())
+        };
+
+        let mut first = output();
+        first
+            .record_to(store.clone(), uuid, store.insert(&record).unwrap())
+            .unwrap();
+
+        for line in 0..10000 {
+            first.publish(OutputEvent::Stdout(
+                format!("compiling crate number {}\n", line).into_bytes(),
+            ));
+        }
+
+        first.publish(OutputEvent::Exit(ExitStatus::Code(0)));
+        let storage = first.storage();
+        drop(first);
+
+        let job = store.load().unwrap().pop().unwrap().unwrap();
+        let mut restored = output();
+        restored.record_to(store, uuid, job.log).unwrap();
+        let events = restored.get_events().unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(restored.storage(), storage);
+        assert!(storage.raw_bytes < 4 * 64 * 1024);
+        assert!(matches!(events[0].event, OutputEvent::Truncated { bytes } if bytes > 0));
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[1].sequence == pair[0].sequence + 1));
+        assert_eq!(
+            events.last().unwrap().event,
+            OutputEvent::Exit(ExitStatus::Code(0))
+        );
+    }
+
+    #[test]
+    fn remove_job() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = FileStore::open(directory.clone()).unwrap();
+        let uuid = Uuid::new_v4();
+
+        store
+            .insert(&JobRecord {
+                user: "user".into(),
+                uuid,
+                index: 0,
+                program: "/bin/true".into(),
+                args: Vec::new(),
+                started_at: SystemTime::now(),
+                pid: 1234,
+                start_time: 5678,
+                output_files: store.output_files(uuid).unwrap(),
+                deadline: None,
+                line_framing: None,
+            })
+            .unwrap();
+
+        store.remove(uuid).unwrap();
+        let jobs = store.load().unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(jobs.is_empty());
+    }
+}
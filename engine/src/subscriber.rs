@@ -0,0 +1,345 @@
+use crate::output::{Backlog, LogEvent, OutputEvent};
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::SystemTime;
+use tokio::task::{self, JoinHandle};
+
+/// How many events are queued for each subscriber by default.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// What happens when a subscriber falls so far behind that its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop reading output from the job until the subscriber catches up.
+    /// This slows down the job and every other subscriber along with it. Jobs read under this policy write their output
+    /// to pipes rather than to output files in the store, so they can't be reattached to.
+    Block,
+
+    /// Drop the oldest queued events and let the subscriber know with a gap event.
+    DropOldest,
+
+    /// Disconnect the subscriber.
+    Disconnect,
+}
+
+/// Bounds the queue of events each subscriber has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackpressurePolicy {
+    /// The maximum number of events queued for a subscriber. Past events are read a queue at a time.
+    pub capacity: usize,
+
+    pub overflow: Overflow,
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            overflow: Overflow::DropOldest,
+        }
+    }
+}
+
+/// The error a subscriber receives once it has been disconnected for falling behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "subscriber fell too far behind and was disconnected")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+/// The queue shared between the publishing and the subscribing side.
+#[derive(Debug, Default)]
+struct Queue {
+    events: VecDeque<LogEvent>,
+
+    /// The number of events dropped since the subscriber last received one.
+    dropped: u64,
+
+    /// The sequence number and time of the most recently dropped event, which the gap event is stamped with.
+    last_dropped: Option<(u64, SystemTime)>,
+
+    disconnected: bool,
+    publisher_gone: bool,
+    subscriber_gone: bool,
+
+    /// Woken once there is something for the subscriber to receive.
+    subscriber: Option<Waker>,
+
+    /// Woken once the subscriber has made room in the queue.
+    publishers: Vec<Waker>,
+}
+
+/// The publishing side of a subscription.
+#[derive(Debug)]
+pub struct Publisher {
+    queue: Arc<Mutex<Queue>>,
+    policy: BackpressurePolicy,
+}
+
+/// The receiving side of a subscription. Past events requested when subscribing are received first.
+#[derive(Debug)]
+pub struct Subscriber {
+    /// Past events that have been read but not received yet, which are never more than a queue of them.
+    backlog: VecDeque<LogEvent>,
+
+    /// How far reading the past events has come.
+    catch_up: CatchUp,
+
+    queue: Arc<Mutex<Queue>>,
+
+    /// Set once the subscriber has been told that it was disconnected, after which nothing more is received.
+    done: bool,
+}
+
+/// Where a subscriber is with reading past events. They're only read once the ones read before have been received.
+#[derive(Debug)]
+enum CatchUp {
+    /// The next past events are yet to be read.
+    Idle(Backlog),
+
+    /// The next past events are being read on a blocking thread.
+    Reading(JoinHandle<(Backlog, Vec<LogEvent>)>),
+
+    /// Every past event has been read, so published events are received from the queue.
+    Done,
+}
+
+/// Create a subscription that receives every event published from now on.
+pub fn subscribe(policy: BackpressurePolicy) -> (Publisher, Subscriber) {
+    let queue = Arc::new(Mutex::new(Queue::default()));
+
+    let publisher = Publisher {
+        queue: Arc::clone(&queue),
+        policy,
+    };
+
+    let subscriber = Subscriber {
+        backlog: VecDeque::new(),
+        catch_up: CatchUp::Done,
+        queue,
+        done: false,
+    };
+
+    (publisher, subscriber)
+}
+
+impl Publisher {
+    /// Queue an event for the subscriber, applying the overflow policy should the queue be full.
+    /// Returns false once the subscriber is gone or has been disconnected.
+    pub fn send(&self, event: LogEvent) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+
+        if queue.subscriber_gone || queue.disconnected {
+            return false;
+        }
+
+        // Blocking publishers wait for room before publishing so the queue only ever grows past
+        // its capacity by the events published in the meantime.
+        if queue.events.len() >= self.policy.capacity {
+            match self.policy.overflow {
+                Overflow::Block => (),
+                Overflow::DropOldest => {
+                    if let Some(dropped) = queue.events.pop_front() {
+                        queue.last_dropped = Some((dropped.sequence, dropped.at));
+                        queue.dropped += 1;
+                    }
+                }
+                Overflow::Disconnect => {
+                    queue.disconnected = true;
+                    queue.events.clear();
+                    wake(&mut queue.subscriber);
+                    return false;
+                }
+            }
+        }
+
+        queue.events.push_back(event);
+        wake(&mut queue.subscriber);
+        true
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.publisher_gone = true;
+        wake(&mut queue.subscriber);
+    }
+}
+
+impl Subscriber {
+    /// Receive the past events read by the backlog before any published ones. The publisher of the subscription
+    /// is expected to be with the backlog, which publishes to it once every past event has been read.
+    pub(crate) fn with_backlog(mut self, backlog: Backlog) -> Self {
+        self.catch_up = CatchUp::Idle(backlog);
+        self
+    }
+
+    /// Poll for the next event. A gap event is received in place of any events that were dropped.
+    /// Resolves to `None` once everything has been received and nothing more will be published
+    /// or once the subscriber has received the error telling it that it has been disconnected.
+    pub fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<LogEvent, Disconnected>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            match std::mem::replace(&mut self.catch_up, CatchUp::Done) {
+                CatchUp::Idle(mut backlog) => {
+                    self.catch_up = CatchUp::Reading(task::spawn_blocking(move || {
+                        let events = backlog.read();
+                        (backlog, events)
+                    }));
+                }
+
+                CatchUp::Reading(mut reading) => match Pin::new(&mut reading).poll(cx) {
+                    Poll::Ready(Ok((backlog, events))) => {
+                        // Nothing left to read means the backlog has caught up and is done with.
+                        if !events.is_empty() {
+                            self.catch_up = CatchUp::Idle(backlog);
+                        }
+
+                        self.backlog = events.into();
+                    }
+
+                    // Reading panicked so there's no telling what's been missed.
+                    Poll::Ready(Err(_)) => {
+                        self.done = true;
+                        return Poll::Ready(None);
+                    }
+
+                    Poll::Pending => {
+                        self.catch_up = CatchUp::Reading(reading);
+                        return Poll::Pending;
+                    }
+                },
+
+                CatchUp::Done => break,
+            }
+        }
+
+        let mut queue = self.queue.lock().unwrap();
+
+        if queue.disconnected {
+            self.done = true;
+            return Poll::Ready(Some(Err(Disconnected)));
+        }
+
+        if let Some((sequence, at)) = queue.last_dropped.take() {
+            let events = queue.dropped;
+            queue.dropped = 0;
+            return Poll::Ready(Some(Ok(LogEvent {
+                sequence,
+                at,
+                event: OutputEvent::Gap { events },
+            })));
+        }
+
+        match queue.events.pop_front() {
+            Some(event) => {
+                for publisher in queue.publishers.drain(..) {
+                    publisher.wake();
+                }
+
+                Poll::Ready(Some(Ok(event)))
+            }
+
+            None if queue.publisher_gone => Poll::Ready(None),
+
+            None => {
+                queue.subscriber = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Receive the next event. See `poll_recv`.
+    pub async fn recv(&mut self) -> Option<Result<LogEvent, Disconnected>> {
+        Recv(self).await
+    }
+}
+
+struct Recv<'a>(&'a mut Subscriber);
+
+impl Future for Recv<'_> {
+    type Output = Option<Result<LogEvent, Disconnected>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.subscriber_gone = true;
+
+        for publisher in queue.publishers.drain(..) {
+            publisher.wake();
+        }
+    }
+}
+
+/// Resolves once every subscriber that blocks publishing when it falls behind has room for more events.
+#[derive(Debug)]
+pub struct Ready {
+    /// The queues still being waited on along with their capacities.
+    queues: Vec<(Arc<Mutex<Queue>>, usize)>,
+}
+
+impl Ready {
+    pub fn new<'a>(publishers: impl Iterator<Item = &'a Publisher>) -> Self {
+        let queues = publishers
+            .filter(|publisher| publisher.policy.overflow == Overflow::Block)
+            .map(|publisher| (Arc::clone(&publisher.queue), publisher.policy.capacity))
+            .collect();
+
+        Self { queues }
+    }
+}
+
+impl Future for Ready {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Forget about the queues that have room so that they're not waited on again.
+        self.queues.retain(|(queue, capacity)| {
+            let mut queue = queue.lock().unwrap();
+            let full = queue.events.len() >= *capacity && !queue.subscriber_gone;
+
+            if full {
+                queue.publishers.push(cx.waker().clone());
+            }
+
+            full
+        });
+
+        if self.queues.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+
+/// Find every descendant of a process by walking the parent links in `/proc`.
+///
+/// This only finds processes that are still attached to the tree. Anything that
+/// has been orphaned and reparented to init is out of reach.
+pub fn descendants(pid: libc::pid_t) -> Vec<libc::pid_t> {
+    let mut children: HashMap<libc::pid_t, Vec<libc::pid_t>> = HashMap::new();
+
+    for (pid, ppid) in processes() {
+        children.entry(ppid).or_default().push(pid);
+    }
+
+    let mut descendants = Vec::new();
+    let mut pending = vec![pid];
+
+    while let Some(pid) = pending.pop() {
+        if let Some(children) = children.get(&pid) {
+            descendants.extend_from_slice(children);
+            pending.extend_from_slice(children);
+        }
+    }
+
+    descendants
+}
+
+/// Deliver a signal to every process in a job's process group as well as every descendant
+/// of the job leader, including those that have moved to a process group or session of their own.
+pub fn signal(pgid: libc::pid_t, signal: i32) {
+    // Collect the descendants before signalling the group since processes that die
+    // will have their children reparented out of the tree.
+    let descendants = descendants(pgid);
+
+    // These only fail if the processes have already exited, which is fine.
+    unsafe {
+        libc::killpg(pgid, signal);
+
+        for pid in descendants {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// List the pid and parent pid of every process on the system.
+fn processes() -> Vec<(libc::pid_t, libc::pid_t)> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(|pid| Some((pid, parent(pid)?)))
+        .collect()
+}
+
+/// Read the parent pid of a process from `/proc/<pid>/stat`.
+fn parent(pid: libc::pid_t) -> Option<libc::pid_t> {
+    stat_field(pid, 1)?.parse().ok()
+}
+
+/// Read the time a process started at in clock ticks since boot from `/proc/<pid>/stat`.
+/// Together with the pid this identifies a process even if its pid is reused.
+pub fn start_time(pid: libc::pid_t) -> Option<u64> {
+    stat_field(pid, 19)?.parse().ok()
+}
+
+/// Read a field from `/proc/<pid>/stat`, counting from the field after the command name.
+pub fn stat_field(pid: libc::pid_t, index: usize) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The command name is wrapped in parentheses and may contain anything, including spaces
+    // and parentheses, so skip past the last closing parenthesis before splitting.
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(index).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn finds_grandchildren() {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg("sleep 5 & sleep 5 & echo started; wait")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // The shell has forked both of its children once it says so.
+        let mut started = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut started)
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        let descendants = super::descendants(pid);
+        super::signal(pid, libc::SIGKILL);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(descendants.len(), 2);
+    }
+
+    #[test]
+    fn start_time_identifies_process() {
+        let pid = std::process::id() as libc::pid_t;
+        assert!(super::start_time(pid).is_some());
+        assert_eq!(super::start_time(pid), super::start_time(pid));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cgroup::Cgroup;
+use crate::tree;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::mem;
+use std::time::Duration;
+
+/// How often the resource usage of a running job is sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The size of the blocks the block IO counters of `getrusage` count in.
+const BLOCK_SIZE: u64 = 512;
+
+/// How much of the system a job has used so far, or altogether once it has terminated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+
+    /// The peak resident set size in bytes, of the job as a whole should it have a cgroup
+    /// and otherwise of its largest process.
+    pub max_rss_bytes: u64,
+
+    /// The number of bytes read from and written to storage.
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+}
+
+impl ResourceUsage {
+    /// Combine two measurements of the same job. Every counter only ever grows
+    /// so whichever measurement has seen more is the more accurate one.
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            user_time: self.user_time.max(other.user_time),
+            system_time: self.system_time.max(other.system_time),
+            max_rss_bytes: self.max_rss_bytes.max(other.max_rss_bytes),
+            read_bytes: self.read_bytes.max(other.read_bytes),
+            write_bytes: self.write_bytes.max(other.write_bytes),
+            voluntary_context_switches: self
+                .voluntary_context_switches
+                .max(other.voluntary_context_switches),
+            involuntary_context_switches: self
+                .involuntary_context_switches
+                .max(other.involuntary_context_switches),
+        }
+    }
+}
+
+/// Sample the resource usage of a running job from `/proc` for its leader and the descendants it has
+/// reaped, and from its cgroup should it have one, which covers every process that was ever in it.
+pub fn sample(pid: libc::pid_t, cgroup: Option<&Cgroup>) -> Option<ResourceUsage> {
+    let process = sample_process(pid);
+    let cgroup = cgroup.and_then(Cgroup::usage);
+
+    match (process, cgroup) {
+        (Some(process), Some(cgroup)) => Some(process.max(cgroup)),
+        (process, cgroup) => process.or(cgroup),
+    }
+}
+
+/// Read the resource usage of a child that has exited but hasn't been reaped yet, which includes
+/// every descendant it has reaped. The child is left for whoever reaps it.
+pub fn of_exited_child(pid: libc::pid_t) -> Option<ResourceUsage> {
+    unsafe {
+        let mut info: libc::siginfo_t = mem::zeroed();
+        let mut usage: libc::rusage = mem::zeroed();
+
+        // Only the raw syscall hands out the resource usage of the child.
+        let result = libc::syscall(
+            libc::SYS_waitid,
+            libc::P_PID,
+            pid,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+            &mut usage,
+        );
+
+        if result == -1 {
+            return None;
+        }
+
+        Some(ResourceUsage {
+            user_time: duration(usage.ru_utime),
+            system_time: duration(usage.ru_stime),
+            max_rss_bytes: usage.ru_maxrss as u64 * 1024,
+            read_bytes: usage.ru_inblock as u64 * BLOCK_SIZE,
+            write_bytes: usage.ru_oublock as u64 * BLOCK_SIZE,
+            voluntary_context_switches: usage.ru_nvcsw as u64,
+            involuntary_context_switches: usage.ru_nivcsw as u64,
+        })
+    }
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
+
+/// Read the resource usage of a process and the children it has reaped from `/proc/<pid>`.
+fn sample_process(pid: libc::pid_t) -> Option<ResourceUsage> {
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    let ticks = |index| -> Option<u64> { tree::stat_field(pid, index)?.parse().ok() };
+    let time = |own, children| -> Option<Duration> {
+        let ticks = ticks(own)? + ticks(children)?;
+        Some(Duration::from_millis(ticks * 1000 / ticks_per_second))
+    };
+
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let io = fs::read_to_string(format!("/proc/{}/io", pid)).unwrap_or_default();
+
+    Some(ResourceUsage {
+        user_time: time(11, 13)?,
+        system_time: time(12, 14)?,
+        max_rss_bytes: field(&status, "VmHWM:").unwrap_or(0) * 1024,
+        read_bytes: field(&io, "read_bytes:").unwrap_or(0),
+        write_bytes: field(&io, "write_bytes:").unwrap_or(0),
+        voluntary_context_switches: field(&status, "voluntary_ctxt_switches:").unwrap_or(0),
+        involuntary_context_switches: field(&status, "nonvoluntary_ctxt_switches:").unwrap_or(0),
+    })
+}
+
+/// Find the number following a key in a file of `key value` lines.
+pub fn field(contents: &str, key: &str) -> Option<u64> {
+    contents
+        .lines()
+        .find(|line| line.starts_with(key))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn sample_own_usage() {
+        let usage = super::sample(std::process::id() as libc::pid_t, None).unwrap();
+        assert!(usage.max_rss_bytes > 0);
+        assert!(usage.voluntary_context_switches + usage.involuntary_context_switches > 0);
+    }
+}
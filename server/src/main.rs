@@ -1,8 +1,14 @@
 mod server;
 
 use anyhow::Result;
+use server::Config;
+use std::path::Path;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    server::serve().await
+    // The server optionally takes the path to a configuration file as its only argument.
+    match std::env::args().nth(1) {
+        Some(path) => server::serve_with_config(Config::load(Path::new(&path))?).await,
+        None => server::serve().await,
+    }
 }
@@ -1,27 +1,72 @@
 mod routes;
+mod transform;
 
 use crate::server::auth;
-use engine::Engine;
+use anyhow::Result;
+use engine::{Engine, EngineConfig, ErrorReporter};
 use protocol::{
-    api_server::Api, IssueJwtRequest, IssueJwtResponse, SpawnRequest, SpawnResponse, StatusRequest,
-    StatusResponse, StopRequest, StopResponse, StreamLogRequest,
+    api_server::Api, AttachRequest, GetJobStatsRequest, GetJobStatsResponse, GetLogsRequest,
+    GetLogsResponse, IssueJwtRequest, IssueJwtResponse, ListJobsRequest, ListJobsResponse,
+    RemoveJobRequest, RemoveJobResponse, SearchLogsRequest, SearchLogsResponse, SpawnRequest,
+    SpawnResponse, StatusRequest, StatusResponse, StopRequest, StopResponse, StreamLogRequest,
+    WaitRequest, WaitResponse,
 };
-use tokio::sync::Mutex;
-use tonic::{Request, Response, Status};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::{task, time};
+use tonic::{Request, Response, Status, Streaming};
 
 /// Our service handler.
 pub struct ApiCore {
-    engine: Mutex<Engine>,
+    engine: Arc<Mutex<Engine>>,
 }
 
 impl ApiCore {
-    pub fn new() -> Self {
-        Self {
-            engine: Mutex::new(Engine::new()),
+    /// Create the service handler, collecting garbage every `gc_interval` should the engine be configured to.
+    pub fn new(mut config: EngineConfig, gc_interval: Duration) -> Result<Self> {
+        let (errors, receiver) = ErrorReporter::new();
+        config.errors = errors;
+        task::spawn(log_errors(receiver));
+
+        let gc_enabled = !config.gc.is_disabled();
+        let engine = Arc::new(Mutex::new(Engine::with_config(config)?));
+
+        if gc_enabled {
+            task::spawn(collect_garbage(Arc::downgrade(&engine), gc_interval));
+        }
+
+        Ok(Self { engine })
+    }
+}
+
+/// Periodically remove terminated jobs from the engine for as long as it's around.
+/// The engine is only held on to weakly so that it still goes away along with the service.
+async fn collect_garbage(engine: Weak<Mutex<Engine>>, interval: Duration) {
+    let mut interval = time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let engine = match engine.upgrade() {
+            Some(engine) => engine,
+            None => break,
+        };
+
+        let removed = engine.lock().await.collect_garbage();
+        if removed != 0 {
+            println!("garbage collected {} terminated jobs", removed);
         }
     }
 }
 
+/// Log the errors the engine runs into in the background until it goes away.
+async fn log_errors(mut errors: mpsc::UnboundedReceiver<anyhow::Error>) {
+    while let Some(error) = errors.recv().await {
+        eprintln!("{:#}", error);
+    }
+}
+
 /// For authorization we'd ideally use some sort of proper middleware setup here.
 /// The only good way to integrate that into the Rust stack would be with a Tower service
 /// but that takes significant amounts of boilerplate code. Therefore I'll be doing in the
@@ -29,6 +74,7 @@ impl ApiCore {
 #[tonic::async_trait]
 impl Api for ApiCore {
     type StreamLogStream = routes::stream_log::EventStream;
+    type AttachStream = routes::stream_log::EventStream;
 
     async fn spawn(
         &self,
@@ -75,6 +121,25 @@ impl Api for ApiCore {
             .map(Response::new)
     }
 
+    async fn attach(
+        &self,
+        request: Request<Streaming<AttachRequest>>,
+    ) -> Result<Response<Self::AttachStream>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Attaching streams the output of a job like streaming its log does
+        // while also giving control over its input, which we treat like spawning it.
+        if !claims.stream_log || !claims.spawn {
+            return Err(Status::permission_denied(
+                "claims.stream_log and claims.spawn not true",
+            ));
+        }
+
+        routes::attach::attach(&self.engine, request.into_inner(), &claims.username)
+            .await
+            .map(Response::new)
+    }
+
     async fn status(
         &self,
         request: Request<StatusRequest>,
@@ -91,6 +156,105 @@ impl Api for ApiCore {
             .map(Response::new)
     }
 
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>,
+    ) -> Result<Response<ListJobsResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Listing jobs only reveals the same information as querying their status.
+        if !claims.status {
+            return Err(Status::permission_denied("claims.status not true"));
+        }
+
+        let request = request.get_ref();
+        routes::list_jobs::list_jobs(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
+    async fn wait(&self, request: Request<WaitRequest>) -> Result<Response<WaitResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Waiting for a job only reveals the same information as querying its status.
+        if !claims.status {
+            return Err(Status::permission_denied("claims.status not true"));
+        }
+
+        let request = request.get_ref();
+        routes::wait::wait(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
+    async fn remove_job(
+        &self,
+        request: Request<RemoveJobRequest>,
+    ) -> Result<Response<RemoveJobResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Removing a job may kill it if it's still running, which we treat like stopping it.
+        if !claims.stop {
+            return Err(Status::permission_denied("claims.stop not true"));
+        }
+
+        let request = request.get_ref();
+        routes::remove_job::remove_job(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
+    async fn get_job_stats(
+        &self,
+        request: Request<GetJobStatsRequest>,
+    ) -> Result<Response<GetJobStatsResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Resource usage is part of the status of a job.
+        if !claims.status {
+            return Err(Status::permission_denied("claims.status not true"));
+        }
+
+        let request = request.get_ref();
+        routes::get_job_stats::get_job_stats(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
+    async fn get_logs(
+        &self,
+        request: Request<GetLogsRequest>,
+    ) -> Result<Response<GetLogsResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Reading part of the log reveals nothing more than streaming it.
+        if !claims.stream_log {
+            return Err(Status::permission_denied("claims.stream_log not true"));
+        }
+
+        let request = request.get_ref();
+        routes::get_logs::get_logs(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
+    async fn search_logs(
+        &self,
+        request: Request<SearchLogsRequest>,
+    ) -> Result<Response<SearchLogsResponse>, Status> {
+        let claims = auth::validate_claims(&request)?;
+
+        // Searching the log only reveals output that could be read by streaming it.
+        if !claims.stream_log {
+            return Err(Status::permission_denied("claims.stream_log not true"));
+        }
+
+        let request = request.get_ref();
+        routes::search_logs::search_logs(&self.engine, request, &claims.username)
+            .await
+            .map(Response::new)
+    }
+
     async fn issue_jwt(
         &self,
         request: Request<IssueJwtRequest>,
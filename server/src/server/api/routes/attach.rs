@@ -0,0 +1,74 @@
+use crate::server::api::routes::stream_log::{subscriber_to_stream, tail_from, EventStream};
+use anyhow::Result;
+use engine::{Engine, Stdin, UniqueJobId};
+use protocol::{attach_request, AttachRequest};
+use tokio::{sync::Mutex, task};
+use tonic::{Status, Streaming};
+use uuid::Uuid;
+
+pub async fn attach(
+    engine: &Mutex<Engine>,
+    mut requests: Streaming<AttachRequest>,
+    username: &str,
+) -> Result<EventStream, Status> {
+    let start = match requests
+        .message()
+        .await?
+        .and_then(|request| request.request)
+    {
+        Some(attach_request::Request::Start(start)) => start,
+        _ => {
+            return Err(Status::invalid_argument(
+                "attach stream must begin with a start message",
+            ))
+        }
+    };
+
+    let uuid =
+        Uuid::from_slice(&start.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let id = UniqueJobId::new(username.into(), uuid);
+    let engine = engine.lock().await;
+    let stdin = engine
+        .stdin(&id)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    let subscriber = engine
+        .tail_log(&id, tail_from(start.from_beginning, 0))
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    task::spawn(forward_input(requests, stdin));
+    Ok(subscriber_to_stream(subscriber))
+}
+
+/// Feed input from the client to the job until the client closes stdin or goes away.
+/// Stdin is left open for later attaches if the client goes away without closing it.
+async fn forward_input(mut requests: Streaming<AttachRequest>, stdin: Stdin) {
+    while let Ok(Some(request)) = requests.message().await {
+        match request.request {
+            Some(attach_request::Request::Stdin(bytes)) => {
+                // The job has closed its end of stdin or exited so there is nowhere to forward input to.
+                if stdin.write(&bytes).await.is_err() {
+                    break;
+                }
+            }
+
+            Some(attach_request::Request::Eof(_)) => {
+                stdin.close().await;
+                break;
+            }
+
+            Some(attach_request::Request::Resize(size)) => {
+                // Resizing is best effort since it has no meaning for jobs without a terminal.
+                let _ = stdin.resize(clamp(size.rows), clamp(size.cols));
+            }
+
+            Some(attach_request::Request::Start(_)) | None => break,
+        }
+    }
+}
+
+/// Fit a terminal dimension into the range a terminal supports.
+fn clamp(dimension: u32) -> u16 {
+    dimension.min(u16::MAX as u32) as u16
+}
@@ -0,0 +1,28 @@
+use crate::server::api::transform;
+use anyhow::Result;
+use engine::{Engine, UniqueJobId};
+use protocol::{GetJobStatsRequest, GetJobStatsResponse};
+use tokio::sync::Mutex;
+use tonic::Status;
+use uuid::Uuid;
+
+pub async fn get_job_stats(
+    engine: &Mutex<Engine>,
+    request: &GetJobStatsRequest,
+    username: &str,
+) -> Result<GetJobStatsResponse, Status> {
+    let uuid =
+        Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let id = UniqueJobId::new(username.into(), uuid);
+    let engine = engine.lock().await;
+    let summary = engine
+        .summary(&id)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(GetJobStatsResponse {
+        usage: summary.usage.map(transform::resource_usage),
+        terminated: summary.exit.is_some(),
+        log_storage: Some(transform::log_storage(summary.storage)),
+    })
+}
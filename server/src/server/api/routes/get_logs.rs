@@ -0,0 +1,61 @@
+use crate::server::api::routes::stream_log;
+use anyhow::Result;
+use engine::{Engine, LogRange, LogStreams, UniqueJobId};
+use protocol::{get_logs_request, GetLogsRequest, GetLogsResponse};
+use tokio::sync::Mutex;
+use tonic::Status;
+use uuid::Uuid;
+
+pub async fn get_logs(
+    engine: &Mutex<Engine>,
+    request: &GetLogsRequest,
+    username: &str,
+) -> Result<GetLogsResponse, Status> {
+    let uuid =
+        Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let streams = log_streams(request.streams)?;
+
+    let range = match &request.range {
+        Some(get_logs_request::Range::HeadLines(lines)) => LogRange::Head(*lines as usize),
+        Some(get_logs_request::Range::TailLines(lines)) => LogRange::Tail(*lines as usize),
+        Some(get_logs_request::Range::Bytes(bytes)) => LogRange::Bytes {
+            offset: bytes.offset,
+            length: bytes.length,
+        },
+        None => return Err(Status::invalid_argument("no range given")),
+    };
+
+    let id = UniqueJobId::new(username.into(), uuid);
+
+    // Only hold the engine lock while snapshotting the output and not while reading it.
+    let read = engine
+        .lock()
+        .await
+        .read_log(&id, streams, range)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    let read = read
+        .await
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(GetLogsResponse {
+        events: read
+            .events
+            .into_iter()
+            .map(stream_log::transform)
+            .collect::<Result<_, _>>()?,
+        start_offset: read.offsets.start,
+        end_offset: read.offsets.end,
+    })
+}
+
+/// Transform the output streams to read from our gRPC protocol format.
+pub fn log_streams(streams: i32) -> Result<LogStreams, Status> {
+    match protocol::LogStreams::from_i32(streams) {
+        Some(protocol::LogStreams::Both) => Ok(LogStreams::Both),
+        Some(protocol::LogStreams::Stdout) => Ok(LogStreams::Stdout),
+        Some(protocol::LogStreams::Stderr) => Ok(LogStreams::Stderr),
+        None => Err(Status::invalid_argument("unknown log streams")),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::server::api::transform;
+use anyhow::Result;
+use engine::{Engine, JobState, JobSummary};
+use protocol::{list_jobs_response, ListJobsRequest, ListJobsResponse};
+use tokio::sync::Mutex;
+use tonic::Status;
+
+/// The number of jobs returned per page if the client doesn't ask for a specific amount.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// The maximum number of jobs returned per page.
+const MAX_PAGE_SIZE: usize = 1000;
+
+pub async fn list_jobs(
+    engine: &Mutex<Engine>,
+    request: &ListJobsRequest,
+    username: &str,
+) -> Result<ListJobsResponse, Status> {
+    let state = match protocol::JobState::from_i32(request.state) {
+        Some(protocol::JobState::Unspecified) => None,
+        Some(protocol::JobState::Running) => Some(JobState::Running),
+        Some(protocol::JobState::Terminated) => Some(JobState::Terminated),
+        None => return Err(Status::invalid_argument("unknown job state")),
+    };
+
+    let page_size = match request.page_size as usize {
+        0 => DEFAULT_PAGE_SIZE,
+        page_size => page_size.min(MAX_PAGE_SIZE),
+    };
+
+    // Page tokens are simply the cursor of the last job listed but clients should treat them as opaque.
+    let cursor = if request.page_token.is_empty() {
+        None
+    } else {
+        let cursor = request
+            .page_token
+            .parse()
+            .map_err(|_| Status::invalid_argument("malformed page token"))?;
+
+        Some(cursor)
+    };
+
+    let engine = engine.lock().await;
+    let (jobs, next_cursor) = engine.list(username, state, cursor, page_size);
+
+    Ok(ListJobsResponse {
+        jobs: jobs.into_iter().map(transform).collect(),
+        next_page_token: next_cursor.map_or_else(String::new, |cursor| cursor.to_string()),
+    })
+}
+
+/// Transform an internal job summary to our gRPC protocol format.
+fn transform(job: JobSummary) -> list_jobs_response::Job {
+    let state = match job.state() {
+        JobState::Running => protocol::JobState::Running,
+        JobState::Terminated => protocol::JobState::Terminated,
+    };
+
+    list_jobs_response::Job {
+        uuid: job.uuid.as_bytes()[..].into(),
+        state: state as i32,
+        started_at_unix_ms: transform::unix_ms(job.started_at),
+        finished_at_unix_ms: job
+            .exit
+            .map_or(0, |(_, finished_at)| transform::unix_ms(finished_at)),
+        exit_status: job.exit.map(|(status, _)| transform::exit_status(status)),
+        timed_out: job.timed_out,
+        program: job.program,
+        args: job.args,
+    }
+}
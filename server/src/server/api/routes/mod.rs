@@ -1,5 +1,12 @@
+pub mod attach;
+pub mod get_job_stats;
+pub mod get_logs;
 pub mod issue_jwt;
+pub mod list_jobs;
+pub mod remove_job;
+pub mod search_logs;
 pub mod spawn;
 pub mod status;
 pub mod stop;
 pub mod stream_log;
+pub mod wait;
@@ -0,0 +1,23 @@
+use anyhow::Result;
+use engine::{Engine, UniqueJobId};
+use protocol::{RemoveJobRequest, RemoveJobResponse};
+use tokio::sync::Mutex;
+use tonic::Status;
+use uuid::Uuid;
+
+pub async fn remove_job(
+    engine: &Mutex<Engine>,
+    request: &RemoveJobRequest,
+    username: &str,
+) -> Result<RemoveJobResponse, Status> {
+    let uuid =
+        Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let id = UniqueJobId::new(username.into(), uuid);
+    let mut engine = engine.lock().await;
+    engine
+        .remove(&id, request.force)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(RemoveJobResponse {})
+}
@@ -0,0 +1,60 @@
+use crate::server::api::routes::{get_logs, stream_log};
+use anyhow::Result;
+use engine::{Engine, LogSearch, UniqueJobId};
+use protocol::{search_logs_request, SearchLogsRequest, SearchLogsResponse};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tonic::Status;
+use uuid::Uuid;
+
+pub async fn search_logs(
+    engine: &Mutex<Engine>,
+    request: &SearchLogsRequest,
+    username: &str,
+) -> Result<SearchLogsResponse, Status> {
+    let uuid =
+        Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let mut search = match &request.pattern {
+        Some(search_logs_request::Pattern::Regex(pattern)) => LogSearch::regex(pattern)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?,
+        Some(search_logs_request::Pattern::Substring(substring)) => LogSearch::substring(substring),
+        None => return Err(Status::invalid_argument("no pattern given")),
+    };
+
+    search.streams = get_logs::log_streams(request.streams)?;
+    search.since = time(request.since_unix_ms);
+    search.until = time(request.until_unix_ms);
+
+    if request.max_matches != 0 {
+        search.max_matches = Some(request.max_matches as usize);
+    }
+
+    let id = UniqueJobId::new(username.into(), uuid);
+
+    // Only hold the engine lock while snapshotting the output and not while searching it.
+    let search = engine
+        .lock()
+        .await
+        .search_log(&id, search)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    let matches = search
+        .await
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(SearchLogsResponse {
+        matches: matches
+            .into_iter()
+            .map(stream_log::transform)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// A point in time given in milliseconds since the unix epoch, with zero meaning none.
+fn time(unix_ms: u64) -> Option<SystemTime> {
+    match unix_ms {
+        0 => None,
+        unix_ms => Some(UNIX_EPOCH + Duration::from_millis(unix_ms)),
+    }
+}
@@ -1,6 +1,8 @@
+use crate::server::api::transform;
 use anyhow::Result;
-use engine::Engine;
+use engine::{CpuMax, Engine, IoMax, Isolation, ResourceLimits, Rlimit, SpawnOptions};
 use protocol::{SpawnRequest, SpawnResponse};
+use std::time::{Duration, UNIX_EPOCH};
 use tokio::sync::Mutex;
 use tonic::Status;
 
@@ -9,6 +11,51 @@ pub async fn spawn(
     request: &SpawnRequest,
     username: &str,
 ) -> Result<SpawnResponse, Status> {
+    let limits = request
+        .limits
+        .as_ref()
+        .map(transform_limits)
+        .transpose()?
+        .unwrap_or_default();
+
+    let rlimits = request
+        .rlimits
+        .iter()
+        .map(|(name, value)| {
+            let rlimit = name
+                .parse::<Rlimit>()
+                .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+            Ok((rlimit, *value))
+        })
+        .collect::<Result<_, Status>>()?;
+
+    let options = SpawnOptions {
+        limits,
+        open_stdin: request.open_stdin,
+        tty: request.tty,
+        timeout: nonzero(request.timeout_ms).map(Duration::from_millis),
+        deadline: nonzero(request.deadline_unix_ms)
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+        timeout_policy: request
+            .timeout_policy
+            .as_ref()
+            .map(transform::stop_policy)
+            .unwrap_or_default(),
+        isolation: request
+            .isolation
+            .as_ref()
+            .map(transform_isolation)
+            .unwrap_or_default(),
+        profile: if request.profile.is_empty() {
+            None
+        } else {
+            Some(request.profile.clone())
+        },
+        rlimits,
+        line_framing: request.line_framing.as_ref().map(transform::line_framing),
+    };
+
     let mut engine = engine.lock().await;
     let uuid = engine
         .spawn(
@@ -17,6 +64,7 @@ pub async fn spawn(
             &request.working_directory,
             &request.args,
             &request.envs,
+            &options,
         )
         .map_err(|error| Status::internal(error.to_string()))?;
 
@@ -24,3 +72,70 @@ pub async fn spawn(
         uuid: uuid.as_bytes()[..].into(),
     })
 }
+
+/// Zero values in our gRPC protocol format are treated as unset.
+fn nonzero(value: u64) -> Option<u64> {
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Transform resource limits in our gRPC protocol format to the internal representation.
+/// Zero values are treated as unset.
+fn transform_limits(limits: &protocol::ResourceLimits) -> Result<ResourceLimits, Status> {
+    let cpu_max = limits
+        .cpu_max
+        .as_ref()
+        .map(|cpu_max| {
+            if cpu_max.quota_us == 0 || cpu_max.period_us == 0 {
+                Err(Status::invalid_argument(
+                    "cpu_max quota and period must be non-zero",
+                ))
+            } else {
+                Ok(CpuMax {
+                    quota: cpu_max.quota_us,
+                    period: cpu_max.period_us,
+                })
+            }
+        })
+        .transpose()?;
+
+    let io_max = limits
+        .io_max
+        .iter()
+        .map(|io_max| IoMax {
+            major: io_max.major,
+            minor: io_max.minor,
+            rbps: nonzero(io_max.rbps),
+            wbps: nonzero(io_max.wbps),
+            riops: nonzero(io_max.riops),
+            wiops: nonzero(io_max.wiops),
+        })
+        .collect();
+
+    Ok(ResourceLimits {
+        cpu_max,
+        memory_max: nonzero(limits.memory_max),
+        io_max,
+        pids_max: nonzero(limits.pids_max),
+    })
+}
+
+/// Transform an isolation profile in our gRPC protocol format to the internal representation.
+/// An empty root is treated as unset.
+fn transform_isolation(isolation: &protocol::Isolation) -> Isolation {
+    Isolation {
+        pid: isolation.pid,
+        mount: isolation.mount,
+        uts: isolation.uts,
+        ipc: isolation.ipc,
+        network: isolation.network,
+        root: if isolation.root.is_empty() {
+            None
+        } else {
+            Some(isolation.root.clone().into())
+        },
+    }
+}
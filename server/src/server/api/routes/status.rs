@@ -1,5 +1,6 @@
+use crate::server::api::transform;
 use anyhow::Result;
-use engine::{Engine, OutputEvent, UniqueJobId};
+use engine::{Engine, UniqueJobId};
 use protocol::{status_response, StatusRequest, StatusResponse};
 use tokio::sync::Mutex;
 use tonic::Status;
@@ -15,23 +16,21 @@ pub async fn status(
 
     let id = UniqueJobId::new(username.into(), uuid);
     let engine = engine.lock().await;
-    let events = engine
-        .get_past_events(&id)
+    let summary = engine
+        .summary(&id)
         .map_err(|error| Status::internal(error.to_string()))?;
 
-    let response = if let Some(code) = events.iter().find_map(|event| {
-        if let OutputEvent::Exit(code) = event {
-            Some(*code)
-        } else {
-            None
-        }
-    }) {
-        status_response::Response::Terminated(status_response::StatusResponseTerminated { code })
+    let response = if let Some((status, _)) = summary.exit {
+        status_response::Response::Terminated(status_response::StatusResponseTerminated {
+            status: Some(transform::exit_status(status)),
+            timed_out: summary.timed_out,
+        })
     } else {
         status_response::Response::Running(status_response::StatusResponseRunning {})
     };
 
     Ok(StatusResponse {
         response: Some(response),
+        usage: summary.usage.map(transform::resource_usage),
     })
 }
@@ -1,3 +1,4 @@
+use crate::server::api::transform;
 use anyhow::Result;
 use engine::{Engine, UniqueJobId};
 use protocol::{StopRequest, StopResponse};
@@ -13,10 +14,16 @@ pub async fn stop(
     let uuid =
         Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
 
+    let policy = request
+        .policy
+        .as_ref()
+        .map(transform::stop_policy)
+        .unwrap_or_default();
+
     let id = UniqueJobId::new(username.into(), uuid);
     let engine = engine.lock().await;
     engine
-        .stop(&id)
+        .stop(&id, policy)
         .map_err(|error| Status::internal(error.to_string()))?;
 
     Ok(StopResponse {})
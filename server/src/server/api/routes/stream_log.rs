@@ -1,9 +1,9 @@
+use crate::server::api::transform;
 use anyhow::Result;
-use engine::{Engine, OutputEvent, UniqueJobId};
+use engine::{Engine, LogEvent, OutputEvent, Subscriber, TailFrom, UniqueJobId};
 use futures::{stream, Stream, StreamExt};
 use protocol::{stream_log_response, StreamLogRequest, StreamLogResponse};
 use std::pin::Pin;
-use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::Mutex;
 use tonic::Status;
 use uuid::Uuid;
@@ -11,10 +11,15 @@ use uuid::Uuid;
 /// The internal type of event stream we are handing over to tonic.
 pub type EventStream = Pin<Box<dyn Stream<Item = Result<StreamLogResponse, Status>> + Send + Sync>>;
 
-fn channel_to_stream<T: Send + Sync + 'static>(
-    mut channel: UnboundedReceiver<T>,
-) -> Pin<Box<dyn Stream<Item = T> + Send + Sync>> {
-    Box::pin(stream::poll_fn(move |cx| channel.poll_recv(cx)))
+/// Turn a subscriber into a stream of events in our gRPC protocol format.
+/// A subscriber that has been disconnected for falling behind ends the stream with an error.
+pub fn subscriber_to_stream(mut subscriber: Subscriber) -> EventStream {
+    let stream = stream::poll_fn(move |cx| subscriber.poll_recv(cx));
+
+    Box::pin(stream.map(|event| match event {
+        Ok(event) => transform(event),
+        Err(error) => Err(Status::resource_exhausted(error.to_string())),
+    }))
 }
 
 pub async fn stream_log(
@@ -27,19 +32,31 @@ pub async fn stream_log(
 
     let id = UniqueJobId::new(username.into(), uuid);
     let engine = engine.lock().await;
-    let stream = channel_to_stream(
-        engine
-            .tail_log(&id, request.from_beginning)
-            .map_err(|error| Status::internal(error.to_string()))?,
-    );
+    let subscriber = engine
+        .tail_log(
+            &id,
+            tail_from(request.from_beginning, request.from_sequence),
+        )
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(subscriber_to_stream(subscriber))
+}
 
-    Ok(Box::pin(stream.map(transform)))
+/// Where a stream starts given the flag for past events and an optional sequence number to resume from.
+pub fn tail_from(from_beginning: bool, from_sequence: u64) -> TailFrom {
+    match (from_sequence, from_beginning) {
+        (0, true) => TailFrom::Start,
+        (0, false) => TailFrom::Now,
+        (sequence, _) => TailFrom::Sequence(sequence),
+    }
 }
 
 /// Transform internal output events to our gRPC protocol format.
-fn transform(event: OutputEvent) -> Result<StreamLogResponse, Status> {
+pub fn transform(event: LogEvent) -> Result<StreamLogResponse, Status> {
     Ok(StreamLogResponse {
-        response: Some(match event {
+        sequence: event.sequence,
+        at_unix_ms: transform::unix_ms(event.at),
+        response: Some(match event.event {
             OutputEvent::Stdout(output) => {
                 stream_log_response::Response::Stdout(stream_log_response::StreamLogStdoutEvent {
                     output,
@@ -52,11 +69,32 @@ fn transform(event: OutputEvent) -> Result<StreamLogResponse, Status> {
                 })
             }
 
-            OutputEvent::Exit(code) => {
+            OutputEvent::Stop { signal, escalated } => {
+                stream_log_response::Response::Stop(stream_log_response::StreamLogStopEvent {
+                    signal,
+                    escalated,
+                })
+            }
+
+            OutputEvent::Exit(status) => {
                 stream_log_response::Response::Exit(stream_log_response::StreamLogExitEvent {
-                    code,
+                    status: Some(transform::exit_status(status)),
                 })
             }
+
+            OutputEvent::TimedOut => stream_log_response::Response::TimedOut(
+                stream_log_response::StreamLogTimedOutEvent {},
+            ),
+
+            OutputEvent::Gap { events } => {
+                stream_log_response::Response::Gap(stream_log_response::StreamLogGapEvent {
+                    events,
+                })
+            }
+
+            OutputEvent::Truncated { bytes } => stream_log_response::Response::Truncated(
+                stream_log_response::StreamLogTruncatedEvent { bytes },
+            ),
         }),
     })
 }
@@ -0,0 +1,39 @@
+use crate::server::api::transform;
+use anyhow::Result;
+use engine::{Engine, UniqueJobId};
+use protocol::{WaitRequest, WaitResponse};
+use std::time::Duration;
+use tokio::{sync::Mutex, time};
+use tonic::Status;
+use uuid::Uuid;
+
+pub async fn wait(
+    engine: &Mutex<Engine>,
+    request: &WaitRequest,
+    username: &str,
+) -> Result<WaitResponse, Status> {
+    let uuid =
+        Uuid::from_slice(&request.uuid).map_err(|_| Status::invalid_argument("malformed uuid"))?;
+
+    let id = UniqueJobId::new(username.into(), uuid);
+
+    // Only hold the engine lock while registering the waiter and not while actually waiting.
+    let exit = engine
+        .lock()
+        .await
+        .wait(&id)
+        .map_err(|error| Status::internal(error.to_string()))?;
+
+    let status = if request.timeout_ms == 0 {
+        exit.await
+    } else {
+        time::timeout(Duration::from_millis(request.timeout_ms), exit)
+            .await
+            .map_err(|_| Status::deadline_exceeded("job did not terminate within the timeout"))?
+    }
+    .map_err(|error| Status::internal(error.to_string()))?;
+
+    Ok(WaitResponse {
+        status: Some(transform::exit_status(status)),
+    })
+}
@@ -0,0 +1,101 @@
+use engine::{ExitStatus, LineFraming, LogStorage, ResourceUsage, StopPolicy};
+use protocol::exit_status;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Transform an internal exit status to our gRPC protocol format.
+pub fn exit_status(status: ExitStatus) -> protocol::ExitStatus {
+    match status {
+        ExitStatus::Code(code) => protocol::ExitStatus {
+            status: Some(exit_status::Status::Code(code)),
+            core_dumped: false,
+        },
+
+        ExitStatus::Signal {
+            signal,
+            core_dumped,
+        } => protocol::ExitStatus {
+            status: Some(exit_status::Status::Signal(signal)),
+            core_dumped,
+        },
+
+        ExitStatus::Lost => protocol::ExitStatus {
+            status: Some(exit_status::Status::Lost(true)),
+            core_dumped: false,
+        },
+
+        ExitStatus::Unknown => protocol::ExitStatus {
+            status: Some(exit_status::Status::Unknown(true)),
+            core_dumped: false,
+        },
+    }
+}
+
+/// Transform internal resource usage to our gRPC protocol format.
+pub fn resource_usage(usage: ResourceUsage) -> protocol::ResourceUsage {
+    protocol::ResourceUsage {
+        user_time_us: usage.user_time.as_micros() as u64,
+        system_time_us: usage.system_time.as_micros() as u64,
+        max_rss_bytes: usage.max_rss_bytes,
+        read_bytes: usage.read_bytes,
+        write_bytes: usage.write_bytes,
+        voluntary_context_switches: usage.voluntary_context_switches,
+        involuntary_context_switches: usage.involuntary_context_switches,
+    }
+}
+
+/// Transform how much disk space output takes up to our gRPC protocol format.
+pub fn log_storage(storage: LogStorage) -> protocol::LogStorage {
+    protocol::LogStorage {
+        raw_bytes: storage.raw_bytes,
+        compressed_bytes: storage.compressed_bytes,
+    }
+}
+
+/// Transform a stop policy in our gRPC protocol format to the internal representation.
+/// Zero values are replaced with the defaults.
+pub fn stop_policy(policy: &protocol::StopPolicy) -> StopPolicy {
+    let defaults = StopPolicy::default();
+
+    StopPolicy {
+        signal: if policy.signal == 0 {
+            defaults.signal
+        } else {
+            policy.signal
+        },
+        grace_period: if policy.grace_period_ms == 0 {
+            defaults.grace_period
+        } else {
+            Duration::from_millis(policy.grace_period_ms)
+        },
+        escalation_signal: if policy.escalation_signal == 0 {
+            defaults.escalation_signal
+        } else {
+            policy.escalation_signal
+        },
+    }
+}
+
+/// Transform line framing in our gRPC protocol format to the internal representation.
+/// Zero values are replaced with the defaults.
+pub fn line_framing(framing: &protocol::LineFraming) -> LineFraming {
+    let defaults = LineFraming::default();
+
+    LineFraming {
+        max_line: if framing.max_line_bytes == 0 {
+            defaults.max_line
+        } else {
+            framing.max_line_bytes as usize
+        },
+        flush_timeout: if framing.flush_timeout_ms == 0 {
+            defaults.flush_timeout
+        } else {
+            Duration::from_millis(framing.flush_timeout_ms)
+        },
+    }
+}
+
+/// Milliseconds since the unix epoch.
+pub fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
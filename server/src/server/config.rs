@@ -0,0 +1,327 @@
+use anyhow::{Context, Result};
+use engine::{
+    BackpressurePolicy, Capability, Credentials, EngineConfig, ErrorReporter, FileStore, GcPolicy,
+    OrphanPolicy, Overflow, RetentionPolicy, Rlimit, SeccompAction, SeccompFilter, SecurityProfile,
+    Syscall,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often terminated jobs are garbage collected by default.
+const DEFAULT_GC_INTERVAL_SECS: u64 = 60;
+
+/// The server configuration, read from a TOML file. Anything left out falls back to the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub retention: RetentionConfig,
+    pub backpressure: BackpressureConfig,
+    pub store: StoreConfig,
+    pub gc: GcConfig,
+
+    /// The unix user the jobs of each authenticated user run as. Once set, users that aren't listed can't spawn jobs.
+    /// Jobs run as the user of the server if unset.
+    pub users: Option<HashMap<String, UserConfig>>,
+
+    /// Security profiles that jobs can be spawned with by name.
+    pub profiles: HashMap<String, ProfileConfig>,
+
+    /// The highest per-process resource limit jobs may request for each resource, by the name of the resource.
+    pub rlimit_ceilings: HashMap<String, u64>,
+}
+
+/// The unix user and groups the jobs of a user run as.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Supplementary groups. Jobs have none other than their primary group if left out.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+}
+
+/// Restrictions jobs can be spawned with on top of those of the user they run as.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// The capabilities kept in the bounding set of jobs, all others are dropped. The bounding set is left alone
+    /// if left out.
+    pub capabilities: Option<Vec<String>>,
+
+    pub seccomp: Option<SeccompConfig>,
+}
+
+/// A seccomp filter that takes one action for the listed syscalls and another for all others.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeccompConfig {
+    pub syscalls: Vec<String>,
+
+    /// What happens when a listed syscall is made. Syscalls fail with `EPERM` if left out.
+    #[serde(default = "SeccompActionConfig::errno")]
+    pub action: SeccompActionConfig,
+
+    /// What happens when any other syscall is made. Syscalls are allowed if left out.
+    #[serde(default = "SeccompActionConfig::allow")]
+    pub default_action: SeccompActionConfig,
+}
+
+/// What happens when a job makes a syscall.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeccompActionConfig {
+    Allow,
+    Errno,
+    Kill,
+}
+
+impl SeccompActionConfig {
+    fn allow() -> Self {
+        Self::Allow
+    }
+
+    fn errno() -> Self {
+        Self::Errno
+    }
+
+    fn action(self) -> SeccompAction {
+        match self {
+            Self::Allow => SeccompAction::Allow,
+            Self::Errno => SeccompAction::Errno,
+            Self::Kill => SeccompAction::Kill,
+        }
+    }
+}
+
+impl ProfileConfig {
+    /// Resolve the names of the capabilities and syscalls of the profile.
+    fn profile(&self) -> Result<SecurityProfile> {
+        let capabilities = match &self.capabilities {
+            Some(capabilities) => Some(
+                capabilities
+                    .iter()
+                    .map(|capability| capability.parse())
+                    .collect::<Result<Vec<Capability>>>()?,
+            ),
+            None => None,
+        };
+
+        let seccomp = match &self.seccomp {
+            Some(seccomp) => Some(SeccompFilter {
+                syscalls: seccomp
+                    .syscalls
+                    .iter()
+                    .map(|syscall| syscall.parse())
+                    .collect::<Result<Vec<Syscall>>>()?,
+                action: seccomp.action.action(),
+                default_action: seccomp.default_action.action(),
+            }),
+            None => None,
+        };
+
+        Ok(SecurityProfile {
+            capabilities,
+            seccomp,
+        })
+    }
+}
+
+/// When terminated jobs are removed automatically. Jobs are kept until they're removed by their owner if neither
+/// a time to live nor a limit is set.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcConfig {
+    /// Remove jobs once they have been terminated for this many seconds.
+    pub ttl_secs: Option<u64>,
+
+    /// Only keep this many of the most recently terminated jobs of each user.
+    pub max_finished_jobs_per_user: Option<usize>,
+
+    /// How often to look for jobs to remove.
+    pub interval_secs: u64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: None,
+            max_finished_jobs_per_user: None,
+            interval_secs: DEFAULT_GC_INTERVAL_SECS,
+        }
+    }
+}
+
+impl GcConfig {
+    /// How often to look for jobs to remove. The interval is at least a second.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+}
+
+/// Where jobs are recorded so that they survive restarts of the server.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    /// The directory of the file-backed store. Jobs are only kept in memory if unset.
+    pub directory: Option<PathBuf>,
+
+    /// What happens to recorded jobs that are still running when the server starts, like after a crash.
+    pub orphans: OrphanConfig,
+}
+
+/// What happens to jobs that outlived a previous instance of the server.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrphanConfig {
+    Reattach,
+    Kill,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            orphans: match OrphanPolicy::default() {
+                OrphanPolicy::Reattach => OrphanConfig::Reattach,
+                OrphanPolicy::Kill => OrphanConfig::Kill,
+            },
+        }
+    }
+}
+
+/// How much past output is retained for each job.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// The maximum number of output bytes kept in memory for each job.
+    pub memory_limit_bytes: usize,
+
+    /// Spill output evicted from memory to segment files in this directory instead of discarding it.
+    pub spill_directory: Option<PathBuf>,
+
+    /// The maximum number of output bytes kept on disk for each job, whether spilled or recorded in the store.
+    pub spill_limit_bytes: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        let retention = RetentionPolicy::default();
+
+        Self {
+            memory_limit_bytes: retention.memory_limit,
+            spill_directory: retention.spill_directory,
+            spill_limit_bytes: retention.spill_limit,
+        }
+    }
+}
+
+/// How far behind a log subscriber may fall and what happens once it does.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackpressureConfig {
+    /// The maximum number of events queued for each subscriber, past events included.
+    pub capacity: usize,
+
+    pub overflow: OverflowConfig,
+}
+
+/// What happens when the queue of a subscriber is full.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowConfig {
+    Block,
+    DropOldest,
+    Disconnect,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        let backpressure = BackpressurePolicy::default();
+
+        Self {
+            capacity: backpressure.capacity,
+            overflow: match backpressure.overflow {
+                Overflow::Block => OverflowConfig::Block,
+                Overflow::DropOldest => OverflowConfig::DropOldest,
+                Overflow::Disconnect => OverflowConfig::Disconnect,
+            },
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let config = toml::from_str(&fs::read_to_string(path)?)?;
+        Ok(config)
+    }
+
+    /// The part of the configuration that concerns the engine. This opens the store should there be one.
+    pub fn engine(&self) -> Result<EngineConfig> {
+        let store = match &self.store.directory {
+            Some(directory) => Some(Arc::new(FileStore::open(directory.clone())?) as _),
+            None => None,
+        };
+
+        Ok(EngineConfig {
+            retention: RetentionPolicy {
+                memory_limit: self.retention.memory_limit_bytes,
+                spill_directory: self.retention.spill_directory.clone(),
+                spill_limit: self.retention.spill_limit_bytes,
+            },
+            backpressure: BackpressurePolicy {
+                capacity: self.backpressure.capacity,
+                overflow: match self.backpressure.overflow {
+                    OverflowConfig::Block => Overflow::Block,
+                    OverflowConfig::DropOldest => Overflow::DropOldest,
+                    OverflowConfig::Disconnect => Overflow::Disconnect,
+                },
+            },
+            store,
+            orphans: match self.store.orphans {
+                OrphanConfig::Reattach => OrphanPolicy::Reattach,
+                OrphanConfig::Kill => OrphanPolicy::Kill,
+            },
+            gc: GcPolicy {
+                ttl: self.gc.ttl_secs.map(Duration::from_secs),
+                max_finished_per_user: self.gc.max_finished_jobs_per_user,
+            },
+            users: self.users.as_ref().map(|users| {
+                users
+                    .iter()
+                    .map(|(username, user)| {
+                        let credentials = Credentials {
+                            uid: user.uid,
+                            gid: user.gid,
+                            groups: user.groups.clone(),
+                        };
+
+                        (username.clone(), credentials)
+                    })
+                    .collect()
+            }),
+            profiles: self
+                .profiles
+                .iter()
+                .map(|(name, profile)| {
+                    let profile = profile
+                        .profile()
+                        .with_context(|| format!("invalid security profile {}", name))?;
+
+                    Ok((name.clone(), profile))
+                })
+                .collect::<Result<_>>()?,
+            rlimit_ceilings: self
+                .rlimit_ceilings
+                .iter()
+                .map(|(name, ceiling)| Ok((name.parse::<Rlimit>()?, *ceiling)))
+                .collect::<Result<_>>()?,
+            errors: ErrorReporter::default(),
+        })
+    }
+}
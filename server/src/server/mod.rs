@@ -1,10 +1,18 @@
 mod api;
 mod auth;
+mod config;
 mod tls;
 
+pub use config::Config;
+
 use anyhow::Result;
 use api::ApiCore;
 use protocol::api_server::ApiServer;
+use tokio::select;
+use tokio::signal::{
+    ctrl_c,
+    unix::{signal, SignalKind},
+};
 use tonic::transport::{Server, ServerTlsConfig};
 
 const ADDR: &str = "127.0.0.1:7005";
@@ -15,10 +23,14 @@ const KEY: &[u8] = include_bytes!("../../../data/server.key");
 const CLIENT_CA_CERT: &[u8] = include_bytes!("../../../data/client_ca.pem");
 
 pub async fn serve() -> Result<()> {
+    serve_with_config(Config::default()).await
+}
+
+pub async fn serve_with_config(config: Config) -> Result<()> {
     let server_cert = tls::load_pem_cert(CERT)?;
     let server_key = tls::load_private_key(KEY)?;
     let addr = ADDR.parse().unwrap();
-    let service = ApiServer::new(ApiCore::new());
+    let service = ApiServer::new(ApiCore::new(config.engine()?, config.gc.interval())?);
     let base_tls_config = tls::tls_server_config(server_cert, server_key, CLIENT_CA_CERT)?;
     let mut tls = ServerTlsConfig::new();
     tls.rustls_server_config(base_tls_config);
@@ -27,8 +39,26 @@ pub async fn serve() -> Result<()> {
     Server::builder()
         .tls_config(tls)?
         .add_service(service)
-        .serve(addr)
+        .serve_with_shutdown(addr, shutdown_signal())
         .await?;
 
+    // The engine is dropped along with the service which takes down every job that is still running.
+    println!("shut down gRPC endpoint");
     Ok(())
 }
+
+/// Resolves once the process is asked to shut down through either SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            select! {
+                _ = ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+        }
+
+        Err(_) => {
+            let _ = ctrl_c().await;
+        }
+    }
+}